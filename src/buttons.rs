@@ -1,8 +1,10 @@
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum GbKeyEvent {
     Button(Button),
     Dpad(DpadDirection),
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Button {
     A,
     B,
@@ -10,6 +12,7 @@ pub enum Button {
     Select,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum DpadDirection {
     Right,
     Left,
@@ -17,10 +20,22 @@ pub enum DpadDirection {
     Down,
 }
 
+/// Frames between turbo presses and releases, unless the config says otherwise
+pub const TURBO_RATE: u32 = 2;
+
+#[derive(Clone)]
 pub struct Btns {
     row: u8,
     btn_nib: u8,
     dpad_nib: u8,
+    btn_held: u8,             // buttons held down normally, 1 = held
+    dpad_held: u8,            // directions held down, even when blocked by the opposite
+    turbo: u8,                // buttons held down with turbo, 1 = held
+    turbo_down: bool,         // turbo buttons are pressed in this phase
+    turbo_frames: u32,        // frames since the last turbo toggle
+    pub turbo_rate: u32,      // frames between turbo presses and releases
+    lines: u8,                // P10-P13 at the last interrupt check
+    pub block_opposite: bool, // pressing a direction releases the opposite one
 }
 
 impl Btns {
@@ -29,27 +44,119 @@ impl Btns {
             row: 0xF0,
             btn_nib: 0x0F,
             dpad_nib: 0x0F,
+            btn_held: 0,
+            dpad_held: 0,
+            turbo: 0,
+            turbo_down: false,
+            turbo_frames: 0,
+            turbo_rate: TURBO_RATE,
+            lines: 0x0F,
+            block_opposite: false,
         }
     }
 
     pub fn press(&mut self, key: GbKeyEvent) {
         match key {
-            GbKeyEvent::Button(btn) => self.btn_nib &= !(1 << btn as u8),
-            GbKeyEvent::Dpad(direction) => self.dpad_nib &= !(1 << direction as u8),
+            GbKeyEvent::Button(btn) => {
+                self.btn_held |= 1 << btn as u8;
+                self.update_btn_nib();
+            }
+            GbKeyEvent::Dpad(direction) => {
+                let bit = direction as u8;
+                self.dpad_held |= 1 << bit;
+                self.dpad_nib &= !(1 << bit);
+                // Left + Right and Up + Down can't happen on hardware, some games break
+                if self.block_opposite {
+                    self.dpad_nib |= 1 << (bit ^ 1);
+                }
+            }
         };
     }
 
     pub fn release(&mut self, key: GbKeyEvent) {
         match key {
-            GbKeyEvent::Button(btn) => self.btn_nib |= 1 << btn as u8,
-            GbKeyEvent::Dpad(direction) => self.dpad_nib |= 1 << direction as u8,
+            GbKeyEvent::Button(btn) => {
+                self.btn_held &= !(1 << btn as u8);
+                self.update_btn_nib();
+            }
+            GbKeyEvent::Dpad(direction) => {
+                let bit = direction as u8;
+                self.dpad_held &= !(1 << bit);
+                self.dpad_nib |= 1 << bit;
+                // The opposite direction is still held, it's back to being pressed
+                if self.block_opposite && self.dpad_held & (1 << (bit ^ 1)) != 0 {
+                    self.dpad_nib &= !(1 << (bit ^ 1));
+                }
+            }
         };
     }
 
+    /// Turbo buttons are pressed and released every turbo_rate frames while held
+    pub fn press_turbo(&mut self, btn: Button) {
+        if self.turbo == 0 {
+            self.turbo_down = true;
+            self.turbo_frames = 0;
+        }
+        self.turbo |= 1 << btn as u8;
+        self.update_btn_nib();
+    }
+
+    pub fn release_turbo(&mut self, btn: Button) {
+        self.turbo &= !(1 << btn as u8);
+        self.update_btn_nib();
+    }
+
+    /// Called once per frame to toggle the turbo buttons
+    pub fn turbo_frame(&mut self) {
+        if self.turbo == 0 {
+            return;
+        }
+        self.turbo_frames += 1;
+        if self.turbo_frames >= self.turbo_rate {
+            self.turbo_frames = 0;
+            self.turbo_down = !self.turbo_down;
+            self.update_btn_nib();
+        }
+    }
+
+    fn update_btn_nib(&mut self) {
+        let turbo = if self.turbo_down { self.turbo } else { 0 };
+        self.btn_nib = !(self.btn_held | turbo) & 0x0F;
+    }
+
+    /// Both nibbles as the game sees them, dpad in the upper one, 0 = pressed
+    pub fn state(&self) -> u8 {
+        (self.dpad_nib << 4) | self.btn_nib
+    }
+
+    /// Replace the state of every button, used by input movie playback
+    pub fn set_state(&mut self, state: u8) {
+        self.dpad_nib = state >> 4;
+        self.btn_nib = state & 0x0F;
+    }
+
+    /// Let go of every button, when a movie's last frame shouldn't stay held
+    pub fn release_all(&mut self) {
+        self.btn_held = 0;
+        self.dpad_held = 0;
+        self.turbo = 0;
+        self.dpad_nib = 0x0F;
+        self.update_btn_nib();
+    }
+
     pub fn pick_row(&mut self, val: u8) {
         self.row = (self.row & 0xCF) | (val & 0x30);
     }
 
+    /// The joypad interrupt fires when any of P10-P13 goes from high to low,
+    /// by a press in the selected row or by selecting a row with a button held
+    pub fn should_interrupt(&mut self) -> bool {
+        let lines = self.data() & 0x0F;
+        let falling = self.lines & !lines != 0;
+        self.lines = lines;
+        falling
+    }
+
     pub fn data(&self) -> u8 {
         let mut data = self.row;
         if self.row & 0x10 == 0 {
@@ -87,5 +194,83 @@ mod tests {
         btns.pick_row(!0x10);
         btns.press(GbKeyEvent::Dpad(Up));
         assert_eq!(btns.data(), 0b11101011);
+
+        // A movie frame holding Down + Start, then the movie ends
+        btns.set_state(0x77);
+        btns.release_all();
+        assert_eq!(btns.state(), 0xFF);
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut btns = Btns::new();
+        btns.pick_row(!0x20);
+        assert!(!btns.should_interrupt());
+        btns.press(GbKeyEvent::Button(Button::A));
+        assert!(btns.should_interrupt());
+        // Held buttons don't fire again
+        assert!(!btns.should_interrupt());
+
+        // Other row
+        btns.pick_row(!0x10);
+        assert!(!btns.should_interrupt());
+        btns.press(GbKeyEvent::Button(Button::B));
+        assert!(!btns.should_interrupt());
+        // Selecting a row with buttons held is a falling edge too
+        btns.pick_row(!0x20);
+        assert!(btns.should_interrupt());
+    }
+
+    #[test]
+    fn turbo() {
+        use Button::*;
+
+        let mut btns = Btns::new();
+        btns.turbo_rate = 2;
+        btns.press_turbo(A);
+        let states: Vec<u8> = (0..6)
+            .map(|_| {
+                let state = btns.state() & 0xF;
+                btns.turbo_frame();
+                state
+            })
+            .collect();
+        assert_eq!(states, [0xE, 0xE, 0xF, 0xF, 0xE, 0xE]);
+
+        // Holding B normally isn't affected, releasing turbo A leaves it held
+        btns.press(GbKeyEvent::Button(B));
+        assert_eq!(btns.state() & 0xF, 0xD);
+        btns.turbo_frame();
+        btns.turbo_frame();
+        assert_eq!(btns.state() & 0xF, 0xC);
+        btns.release_turbo(A);
+        btns.turbo_frame();
+        btns.turbo_frame();
+        assert_eq!(btns.state() & 0xF, 0xD);
+    }
+
+    #[test]
+    fn opposite_directions() {
+        let mut btns = Btns::new();
+        btns.pick_row(!0x10);
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Left));
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Right));
+        assert_eq!(btns.data() & 0xF, 0b1100);
+
+        btns.block_opposite = true;
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Up));
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Down));
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Left));
+        assert_eq!(btns.data() & 0xF, 0b0101);
+
+        // Hold Left, tap Right, Left comes back
+        btns.release_all();
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Left));
+        btns.press(GbKeyEvent::Dpad(DpadDirection::Right));
+        assert_eq!(btns.data() & 0xF, 0b1110);
+        btns.release(GbKeyEvent::Dpad(DpadDirection::Right));
+        assert_eq!(btns.data() & 0xF, 0b1101);
+        btns.release(GbKeyEvent::Dpad(DpadDirection::Left));
+        assert_eq!(btns.data() & 0xF, 0b1111);
     }
 }
@@ -0,0 +1,320 @@
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mbc {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+#[derive(Clone)]
+pub struct Cartridge {
+    rom: Rc<[u8]>, // shared between save states
+    ram: Vec<u8>,
+    mbc: Mbc,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,   // also the upper ROM bank bits on MBC1
+    mode: bool,     // MBC1 banking mode
+    rtc: [u8; 5],   // MBC3 clock registers, the clock doesn't tick
+    rtc_select: u8, // MBC3 0x08-0x0C maps a clock register to 0xA000
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Self {
+        let header = |addr: usize| rom.get(addr).copied().unwrap_or(0);
+        let mbc = match header(0x147) {
+            0x00 | 0x08 | 0x09 => Mbc::None,
+            0x01..=0x03 => Mbc::Mbc1,
+            0x05 | 0x06 => Mbc::Mbc2,
+            0x0F..=0x13 => Mbc::Mbc3,
+            0x19..=0x1E => Mbc::Mbc5,
+            // Unsupported types and homebrew with junk in the header, bigger ROMs are
+            // guessed to switch banks like MBC1
+            t => {
+                let mbc = if rom.len() > 0x8000 {
+                    Mbc::Mbc1
+                } else {
+                    Mbc::None
+                };
+                eprintln!(
+                    "Unsupported cartridge type 0x{:02X}, running it as {:?}",
+                    t, mbc
+                );
+                mbc
+            }
+        };
+        let ram_size = match (mbc, header(0x149)) {
+            (Mbc::Mbc2, _) => 512,
+            (_, 0x02) => 0x2000,
+            (_, 0x03) => 0x8000,
+            (_, 0x04) => 0x20000,
+            (_, 0x05) => 0x10000,
+            _ => 0,
+        };
+
+        Cartridge {
+            rom: rom.into(),
+            ram: vec![0; ram_size],
+            mbc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            mode: false,
+            rtc: [0; 5],
+            rtc_select: 0,
+        }
+    }
+
+    pub fn header(&self, addr: usize) -> u8 {
+        self.rom.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Header flags the game as CGB enhanced or CGB only
+    pub fn cgb(&self) -> bool {
+        self.header(0x143) & 0x80 > 0
+    }
+
+    /// Sum of the title bytes, the CGB boot ROM colors DMG games by it
+    pub fn title_checksum(&self) -> u8 {
+        (0x134..=0x143).fold(0u8, |sum, addr| sum.wrapping_add(self.header(addr)))
+    }
+
+    /// Header title, without the padding
+    pub fn title(&self) -> String {
+        (0x134..=0x143)
+            .map(|addr| self.header(addr))
+            .take_while(|&c| c == b' ' || c.is_ascii_graphic())
+            .map(char::from)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    /// Checksum of the whole ROM from the header, tells apart versions with the same title
+    pub fn global_checksum(&self) -> u16 {
+        u16::from_be_bytes([self.header(0x14E), self.header(0x14F)])
+    }
+
+    /// Cartridge types with a battery keeping the RAM
+    pub fn battery(&self) -> bool {
+        matches!(
+            self.header(0x147),
+            0x03 | 0x06 | 0x09 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+        )
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Restore RAM from a save file, ignored if the size doesn't match
+    pub fn load_ram(&mut self, data: &[u8]) -> bool {
+        if data.len() != self.ram.len() {
+            return false;
+        }
+        self.ram.copy_from_slice(data);
+        true
+    }
+
+    /// Old licensee code 0x01, or 0x33 followed by new licensee code "01"
+    pub fn nintendo(&self) -> bool {
+        match self.header(0x14B) {
+            0x01 => true,
+            0x33 => self.header(0x144) == b'0' && self.header(0x145) == b'1',
+            _ => false,
+        }
+    }
+
+    fn rom_banks(&self) -> usize {
+        (self.rom.len() / 0x4000).max(1)
+    }
+
+    fn rom_at(&self, bank: usize, addr: u16) -> u8 {
+        let bank = bank % self.rom_banks();
+        let i = bank * 0x4000 + (addr as usize & 0x3FFF);
+        self.rom.get(i).copied().unwrap_or(0xFF)
+    }
+
+    /// ROM bank mapped at `addr`
+    pub fn rom_bank_at(&self, addr: u16) -> usize {
+        let bank = match (addr, self.mbc) {
+            (0x0000..=0x3FFF, Mbc::Mbc1) if self.mode => (self.ram_bank as usize) << 5,
+            (0x0000..=0x3FFF, _) => 0,
+            (_, Mbc::None) => 1,
+            (_, Mbc::Mbc1) => (self.ram_bank as usize) << 5 | self.rom_bank as usize,
+            _ => self.rom_bank as usize,
+        };
+        bank % self.rom_banks()
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.rom_at(self.rom_bank_at(addr), addr)
+    }
+
+    /// Writes to ROM control the MBC
+    pub fn write_rom(&mut self, addr: u16, val: u8) {
+        match (self.mbc, addr) {
+            (Mbc::None, _) => {}
+            (Mbc::Mbc2, 0x0000..=0x3FFF) => {
+                // Address bit 8 picks the register
+                if addr & 0x100 == 0 {
+                    self.ram_enabled = val & 0xF == 0xA;
+                } else {
+                    self.rom_bank = (val as u16 & 0xF).max(1);
+                }
+            }
+            (Mbc::Mbc2, _) => {}
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = val & 0xF == 0xA,
+            (Mbc::Mbc1, 0x2000..=0x3FFF) => self.rom_bank = (val as u16 & 0x1F).max(1),
+            (Mbc::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (val as u16 & 0x7F).max(1),
+            (Mbc::Mbc5, 0x2000..=0x2FFF) => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            (Mbc::Mbc5, 0x3000..=0x3FFF) => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((val as u16 & 1) << 8)
+            }
+            (Mbc::Mbc1, 0x4000..=0x5FFF) => self.ram_bank = val & 0b11,
+            (Mbc::Mbc3, 0x4000..=0x5FFF) => {
+                if val <= 0x03 {
+                    self.ram_bank = val;
+                    self.rtc_select = 0;
+                } else if (0x08..=0x0C).contains(&val) {
+                    self.rtc_select = val;
+                }
+            }
+            (Mbc::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = val & 0xF,
+            (Mbc::Mbc1, 0x6000..=0x7FFF) => self.mode = val & 1 > 0,
+            _ => {}
+        }
+    }
+
+    fn ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        self.mapped_ram_index(addr)
+    }
+
+    /// Index of the RAM at `addr` in the bank that's mapped, enabled or not
+    fn mapped_ram_index(&self, addr: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let offset = addr as usize & 0x1FFF;
+        let i = match self.mbc {
+            Mbc::Mbc2 => offset & 0x1FF,
+            Mbc::Mbc1 if !self.mode => offset,
+            _ => self.ram_bank as usize * 0x2000 + offset,
+        };
+        Some(i % self.ram.len())
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if self.mbc == Mbc::Mbc3 && self.rtc_select > 0 && self.ram_enabled {
+            return self.rtc[(self.rtc_select - 0x08) as usize];
+        }
+        match self.ram_index(addr) {
+            // MBC2 RAM is only 4 bits wide
+            Some(i) if self.mbc == Mbc::Mbc2 => self.ram[i] | 0xF0,
+            Some(i) => self.ram[i],
+            None => 0xFF,
+        }
+    }
+
+    /// RAM at `addr` for debugging, readable while it's disabled
+    pub fn peek_ram(&self, addr: u16) -> Option<u8> {
+        self.mapped_ram_index(addr).map(|i| self.ram[i])
+    }
+
+    pub fn poke_ram(&mut self, addr: u16, val: u8) {
+        if let Some(i) = self.mapped_ram_index(addr) {
+            self.ram[i] = val;
+        }
+    }
+
+    pub fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.mbc == Mbc::Mbc3 && self.rtc_select > 0 && self.ram_enabled {
+            self.rtc[(self.rtc_select - 0x08) as usize] = val;
+            return;
+        }
+        if let Some(i) = self.ram_index(addr) {
+            self.ram[i] = val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM where every bank is filled with its own number
+    fn rom(cart_type: u8, banks: usize) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..banks).flat_map(|bank| [bank as u8; 0x4000]).collect();
+        rom[0x147] = cart_type;
+        rom[0x149] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn unknown_type() {
+        let cart = Cartridge::new(rom(0xFC, 2));
+        assert_eq!(cart.mbc, Mbc::None);
+        assert_eq!(cart.read_rom(0x4000), 1);
+        let mut cart = Cartridge::new(rom(0x20, 8));
+        assert_eq!(cart.mbc, Mbc::Mbc1);
+        cart.write_rom(0x2000, 5);
+        assert_eq!(cart.read_rom(0x4000), 5);
+    }
+
+    #[test]
+    fn mbc1() {
+        let mut cart = Cartridge::new(rom(0x03, 64));
+        assert_eq!(cart.read_rom(0x4000), 1);
+        // Bank 0 maps to 1
+        cart.write_rom(0x2000, 0);
+        assert_eq!(cart.read_rom(0x4000), 1);
+        cart.write_rom(0x2000, 0x05);
+        cart.write_rom(0x4000, 0x01);
+        assert_eq!(cart.read_rom(0x4000), 0x25);
+        assert_eq!(cart.read_rom(0x0000), 0);
+        cart.write_rom(0x6000, 1);
+        assert_eq!(cart.read_rom(0x0000), 0x20);
+
+        // RAM is locked until enabled
+        cart.write_ram(0xA000, 0x42);
+        assert_eq!(cart.read_ram(0xA000), 0xFF);
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_ram(0xA000, 0x42);
+        assert_eq!(cart.read_ram(0xA000), 0x42);
+        cart.write_rom(0x6000, 0);
+        assert_eq!(cart.read_ram(0xA000), 0);
+
+        // The debugger sees it while it's disabled
+        cart.write_rom(0x0000, 0x00);
+        assert_eq!(cart.read_ram(0xA000), 0xFF);
+        cart.poke_ram(0xA001, 0x24);
+        assert_eq!(cart.peek_ram(0xA001), Some(0x24));
+    }
+
+    #[test]
+    fn mbc5() {
+        let mut cart = Cartridge::new(rom(0x1B, 512));
+        cart.write_rom(0x2000, 0);
+        assert_eq!(cart.read_rom(0x4000), 0);
+        cart.write_rom(0x2000, 0x03);
+        cart.write_rom(0x3000, 0x01);
+        assert_eq!(cart.read_rom(0x4000), 0x03); // bank 0x103 truncated to u8
+        assert_eq!(cart.rom_bank, 0x103);
+    }
+
+    #[test]
+    fn battery() {
+        let mut cart = Cartridge::new(rom(0x1B, 2));
+        assert!(cart.battery());
+        assert!(!cart.load_ram(&[1; 0x2000]));
+        assert!(cart.load_ram(&[1; 0x8000]));
+        assert_eq!(cart.ram()[0x7FFF], 1);
+        assert!(!Cartridge::new(rom(0x01, 2)).battery());
+    }
+}
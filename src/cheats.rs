@@ -0,0 +1,309 @@
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// What a code does to the game
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Effect {
+    /// Game Genie, ROM reads at `addr` give `val` instead, only where the ROM has
+    /// `compare` when there is one
+    RomPatch {
+        addr: u16,
+        val: u8,
+        compare: Option<u8>,
+    },
+    /// GameShark, `val` is written to `addr` every VBlank, `bank` is the CGB WRAM bank
+    /// for 0xD000-0xDFFF
+    RamWrite {
+        bank: Option<u8>,
+        addr: u16,
+        val: u8,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cheat {
+    pub code: String,
+    pub name: String,
+    pub enabled: bool,
+    pub effect: Effect,
+}
+
+/// Code, name and whether it's on
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        match self.name.as_str() {
+            "" => write!(f, "{} ({})", self.code, state),
+            name => write!(f, "{} {} ({})", self.code, name, state),
+        }
+    }
+}
+
+/// Parse a Game Genie code like "00A-17B-C49" or "00A-17B", or a GameShark code
+/// like "010238CD"
+pub fn parse(code: &str) -> Result<Effect, String> {
+    let digits: String = code.chars().filter(|&c| c != '-').collect();
+    let nibble = |i: usize| -> Result<u8, String> {
+        let c = digits.as_bytes()[i] as char;
+        c.to_digit(16)
+            .map(|n| n as u8)
+            .ok_or_else(|| format!("{}: {} isn't a hex digit", code, c))
+    };
+    let byte = |i: usize| Ok::<u8, String>(nibble(i)? << 4 | nibble(i + 1)?);
+
+    match digits.len() {
+        // Game Genie: VV AAA A? CC, the address digits are scrambled and the compare
+        // byte is rotated and XORed
+        6 | 9 if code.contains('-') => {
+            let val = byte(0)?;
+            let high = nibble(5)? ^ 0xF;
+            let addr = (high as u16) << 12
+                | (nibble(2)? as u16) << 8
+                | (nibble(3)? as u16) << 4
+                | nibble(4)? as u16;
+            if addr >= 0x8000 {
+                return Err(format!("{}: Game Genie codes patch the ROM", code));
+            }
+            let compare = match digits.len() {
+                9 => Some((nibble(6)? << 4 | nibble(8)?).rotate_right(2) ^ 0xBA),
+                _ => None,
+            };
+            Ok(Effect::RomPatch { addr, val, compare })
+        }
+        // GameShark: type, value, address low and high
+        8 => {
+            let bank = match byte(0)? {
+                0x00 | 0x01 => None,
+                t @ (0x80..=0x87 | 0x90..=0x97) => Some((t & 0x07).max(1)),
+                t => return Err(format!("{}: unknown GameShark type {:02X}", code, t)),
+            };
+            let val = byte(2)?;
+            let addr = u16::from_le_bytes([byte(4)?, byte(6)?]);
+            if addr < 0x8000 {
+                return Err(format!("{}: GameShark codes write to RAM", code));
+            }
+            Ok(Effect::RamWrite { bank, addr, val })
+        }
+        _ => Err(format!("{}: not a Game Genie or GameShark code", code)),
+    }
+}
+
+/// How a game's cheats are saved
+#[derive(Serialize, Deserialize, Default)]
+struct CheatFile {
+    #[serde(default)]
+    cheat: Vec<CheatEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheatEntry {
+    code: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "enabled")]
+    enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// The cheats of the game that's running
+#[derive(Clone, Default)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats { list: Vec::new() }
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Add an enabled cheat, a code that's already there is enabled again
+    pub fn add(&mut self, code: &str, name: &str) -> Result<&Cheat, String> {
+        let effect = parse(code)?;
+        let i = match self.list.iter().position(|cheat| cheat.effect == effect) {
+            Some(i) => i,
+            None => {
+                self.list.push(Cheat {
+                    code: code.to_uppercase(),
+                    name: name.to_string(),
+                    enabled: true,
+                    effect,
+                });
+                self.list.len() - 1
+            }
+        };
+        let cheat = &mut self.list[i];
+        cheat.enabled = true;
+        Ok(cheat)
+    }
+
+    /// Turn the cheat at `i` on or off
+    pub fn toggle(&mut self, i: usize) -> Option<&Cheat> {
+        let cheat = self.list.get_mut(i)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat)
+    }
+
+    /// What the CPU sees at a ROM address holding `val`
+    pub fn read_rom(&self, addr: u16, val: u8) -> u8 {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .find_map(|cheat| match cheat.effect {
+                Effect::RomPatch {
+                    addr: at,
+                    val: new,
+                    compare,
+                } if at == addr && compare.is_none_or(|old| old == val) => Some(new),
+                _ => None,
+            })
+            .unwrap_or(val)
+    }
+
+    /// Bank, address and value of the enabled RAM writes
+    pub fn ram_writes(&self) -> impl Iterator<Item = (Option<u8>, u16, u8)> + '_ {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.effect {
+                Effect::RamWrite { bank, addr, val } => Some((bank, addr, val)),
+                _ => None,
+            })
+    }
+
+    /// Read a game's cheat file, one that doesn't exist has no cheats
+    pub fn load(path: &Path) -> Result<Cheats, String> {
+        let file: CheatFile = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => CheatFile::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let mut cheats = Cheats::new();
+        for entry in file.cheat {
+            let effect = parse(&entry.code).map_err(|e| format!("{}: {}", path.display(), e))?;
+            cheats.list.push(Cheat {
+                code: entry.code,
+                name: entry.name,
+                enabled: entry.enabled,
+                effect,
+            });
+        }
+        Ok(cheats)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = CheatFile {
+            cheat: self
+                .list
+                .iter()
+                .map(|cheat| CheatEntry {
+                    code: cheat.code.clone(),
+                    name: cheat.name.clone(),
+                    enabled: cheat.enabled,
+                })
+                .collect(),
+        };
+        let text = toml::to_string(&file).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_genie() {
+        assert_eq!(
+            parse("3EA-2BB-A62"),
+            Ok(Effect::RomPatch {
+                addr: 0x4A2B,
+                val: 0x3E,
+                compare: Some(0x12),
+            })
+        );
+        assert_eq!(
+            parse("00a-17f"),
+            Ok(Effect::RomPatch {
+                addr: 0x0A17,
+                val: 0x00,
+                compare: None,
+            })
+        );
+        // 0xF ^ 0x7 puts it in VRAM
+        assert!(parse("00A-177").is_err());
+        assert!(parse("3EA-2BB-A6G").is_err());
+
+        let mut cheats = Cheats::new();
+        cheats.add("3EA-2BB-A62", "").unwrap();
+        assert_eq!(cheats.read_rom(0x4A2B, 0x12), 0x3E);
+        // Another bank has something else there
+        assert_eq!(cheats.read_rom(0x4A2B, 0x13), 0x13);
+        assert_eq!(cheats.read_rom(0x4A2C, 0x12), 0x12);
+        cheats.toggle(0);
+        assert_eq!(cheats.read_rom(0x4A2B, 0x12), 0x12);
+    }
+
+    #[test]
+    fn gameshark() {
+        assert_eq!(
+            parse("010238CD"),
+            Ok(Effect::RamWrite {
+                bank: None,
+                addr: 0xCD38,
+                val: 0x02,
+            })
+        );
+        assert_eq!(
+            parse("9263A2D0"),
+            Ok(Effect::RamWrite {
+                bank: Some(2),
+                addr: 0xD0A2,
+                val: 0x63,
+            })
+        );
+        assert!(parse("0102383D").is_err());
+        assert!(parse("45023800").is_err());
+        assert!(parse("0102380").is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut cheats = Cheats::new();
+        cheats.add("010238cd", "Lives").unwrap();
+        cheats.add("00A-17F", "").unwrap();
+        cheats.toggle(1);
+        // Adding it again turns it back on
+        cheats.add("00A-17F", "").unwrap();
+        cheats.toggle(0);
+        assert_eq!(cheats.list().len(), 2);
+
+        let path = std::env::temp_dir().join(format!("gb_cheats_{}.toml", std::process::id()));
+        cheats.save(&path).unwrap();
+        let loaded = Cheats::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.list(), cheats.list());
+        assert_eq!(loaded.list()[0].code, "010238CD");
+        assert!(!loaded.list()[0].enabled);
+        assert_eq!(loaded.ram_writes().count(), 0);
+
+        assert!(Cheats::load(&path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml::Table;
+
+use crate::cartridge::Cartridge;
+
+/// Settings from the config file or the command line, anything left out falls back
+/// to the next level: command line, then the game's overrides, then the global config.
+#[derive(Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub keymap: Option<Table>, // same tables as a keymap file
+    pub palette: Option<String>,
+    pub scale: Option<u32>,
+    pub stretch: Option<bool>,
+    pub filters: Option<Vec<String>>,
+    pub sgb: Option<bool>,
+    pub block_opposite: Option<bool>,
+    pub turbo_rate: Option<u32>,
+    pub audio_latency: Option<u32>, // ms, kept for when there's sound output
+    pub save_dir: Option<PathBuf>,
+    pub boot_rom: Option<PathBuf>,
+    /// Overrides keyed by the header title, or title/global checksum like "TETRIS/16BF"
+    pub games: HashMap<String, Settings>,
+}
+
+impl Settings {
+    /// Settings with the ones in `over` taking precedence, keymaps are merged per binding
+    pub fn merge(&self, over: &Settings) -> Settings {
+        let keymap = match (&self.keymap, &over.keymap) {
+            (Some(base), Some(over)) => Some(merge_keymaps(base, over)),
+            (base, over) => over.clone().or_else(|| base.clone()),
+        };
+        Settings {
+            keymap,
+            palette: over.palette.clone().or_else(|| self.palette.clone()),
+            scale: over.scale.or(self.scale),
+            stretch: over.stretch.or(self.stretch),
+            filters: over.filters.clone().or_else(|| self.filters.clone()),
+            sgb: over.sgb.or(self.sgb),
+            block_opposite: over.block_opposite.or(self.block_opposite),
+            turbo_rate: over.turbo_rate.or(self.turbo_rate),
+            audio_latency: over.audio_latency.or(self.audio_latency),
+            save_dir: over.save_dir.clone().or_else(|| self.save_dir.clone()),
+            boot_rom: over.boot_rom.clone().or_else(|| self.boot_rom.clone()),
+            games: HashMap::new(),
+        }
+    }
+
+    /// Global settings with the game's overrides applied, title/checksum beats title
+    pub fn for_game(&self, cart: &Cartridge) -> Settings {
+        let title = cart.title();
+        let exact = format!("{}/{:04X}", title, cart.global_checksum());
+        [&title, &exact]
+            .into_iter()
+            .filter_map(|key| self.games.get(key))
+            .fold(self.merge(&Settings::default()), |settings, game| {
+                settings.merge(game)
+            })
+    }
+}
+
+fn merge_keymaps(base: &Table, over: &Table) -> Table {
+    let mut keymap = base.clone();
+    for (section, bindings) in over {
+        match (keymap.get_mut(section), bindings) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(bindings)) => {
+                base.extend(bindings.clone())
+            }
+            _ => {
+                keymap.insert(section.clone(), bindings.clone());
+            }
+        }
+    }
+    keymap
+}
+
+/// $XDG_CONFIG_HOME/gb/config.toml, or wherever the platform keeps configs
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("gb").join("config.toml"))
+}
+
+pub fn load(path: &Path) -> Result<Settings, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Read the config file at the default path, not having one is fine
+pub fn load_default() -> Result<Settings, String> {
+    match default_path() {
+        Some(path) if path.exists() => load(&path),
+        _ => Ok(Settings::default()),
+    }
+}
+
+/// Read a TOML file into a table, like a keymap
+pub fn load_table(path: &Path) -> Result<Table, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let global: Settings = toml::from_str(
+            "scale = 2\n\
+             palette = \"green\"\n\
+             [keymap.keyboard]\n\
+             a = \"K\"\n\
+             b = \"J\"\n",
+        )
+        .unwrap();
+        let cli: Settings = toml::from_str("scale = 4\n[keymap.keyboard]\na = \"L\"").unwrap();
+        let settings = global.merge(&cli);
+        assert_eq!(settings.scale, Some(4));
+        assert_eq!(settings.palette.as_deref(), Some("green"));
+        let keyboard = &settings.keymap.unwrap()["keyboard"];
+        assert_eq!(keyboard["a"].as_str(), Some("L"));
+        assert_eq!(keyboard["b"].as_str(), Some("J"));
+
+        assert!(toml::from_str::<Settings>("zoom = 2").is_err());
+        let audio: Settings = toml::from_str("audio_latency = 50").unwrap();
+        assert_eq!(global.merge(&audio).audio_latency, Some(50));
+        // A config file that was asked for has to be there
+        assert!(load(Path::new("/nonexistent/config.toml")).is_err());
+    }
+
+    #[test]
+    fn per_game() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13A].copy_from_slice(b"TETRIS");
+        rom[0x14E] = 0x16;
+        rom[0x14F] = 0xBF;
+        let cart = Cartridge::new(rom);
+
+        let config: Settings = toml::from_str(
+            "scale = 2\n\
+             stretch = true\n\
+             [games.TETRIS]\n\
+             scale = 3\n\
+             palette = \"cgb-orange\"\n\
+             [games.\"TETRIS/16BF\"]\n\
+             scale = 5\n\
+             [games.ZELDA]\n\
+             scale = 6\n",
+        )
+        .unwrap();
+        let settings = config.for_game(&cart);
+        assert_eq!(settings.scale, Some(5));
+        assert_eq!(settings.palette.as_deref(), Some("cgb-orange"));
+        assert_eq!(settings.stretch, Some(true));
+        assert!(settings.games.is_empty());
+    }
+}
@@ -1,2245 +1,542 @@
-use crate::memory::Mmu;
+use crate::decode::{decode, decode_cb, disassemble, Alu, Cond, Instr, Rot, R16, R8};
+use crate::memory::{Bus, Mmu};
+use crate::profiler::Profiler;
 use crate::register::Flag::*;
 use crate::register::Reg;
 
-pub struct Cpu {
+pub struct Cpu<B = Mmu> {
     reg: Reg,
-    pub membus: Mmu,
+    pub membus: B,
     ime: bool,
     ime_next: bool,
     halted: bool,
+    locked: Option<u16>, // PC of the illegal opcode that hung the CPU
+    ticks: u32,          // M-cycles the current instruction has run so far
+    pub trace: bool,     // print each instruction before it runs
+    pub profiler: Option<Profiler>,
+}
+
+/// The profiler isn't part of save states, clones start without one
+impl<B: Clone> Clone for Cpu<B> {
+    fn clone(&self) -> Self {
+        Cpu {
+            reg: self.reg.clone(),
+            membus: self.membus.clone(),
+            ime: self.ime,
+            ime_next: self.ime_next,
+            halted: self.halted,
+            locked: self.locked,
+            ticks: self.ticks,
+            trace: self.trace,
+            profiler: None,
+        }
+    }
 }
 
 impl Cpu {
     /// Init CPU from existing memory
     pub fn from(mem: Mmu) -> Self {
+        let reg = if mem.boot_rom_mapped() {
+            Reg::zeroed()
+        } else if mem.cgb() {
+            Reg::new_cgb()
+        } else if mem.sgb.is_some() {
+            Reg::new_sgb()
+        } else {
+            Reg::new()
+        };
+        Cpu::with_reg(mem, reg)
+    }
+
+    /// Go back to a save state, the device in the link port, the cheats and the
+    /// profiler stay as they are
+    pub fn restore(&mut self, state: &Cpu) {
+        let device = self.membus.serial.disconnect();
+        let cheats = std::mem::take(&mut self.membus.cheats);
+        let profiler = self.profiler.take();
+        *self = state.clone();
+        self.membus.cheats = cheats;
+        self.profiler = profiler;
+        if let Some(device) = device {
+            self.membus.serial.connect(device);
+        }
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    fn with_reg(mem: B, reg: Reg) -> Self {
         Cpu {
-            reg: Reg::new(),
+            reg,
             membus: mem,
             ime: false,
             ime_next: false,
             halted: false,
+            locked: None,
+            ticks: 0,
+            trace: false,
+            profiler: None,
         }
     }
 
-    // CPU cycle
-    pub fn cycle(&mut self) {
-        // blarggs test - serial output
-        if self.membus.read(0xff02) == 0x81 {
-            let c = self.membus.read(0xff01);
-            if let Some(c) = char::from_u32(c as u32) {
-                print!("{}", c);
-            }
-            self.membus.write(0xff02, 0x0);
-        }
-
-        // Check should leave HALT
-        if self.halted && self.membus.interrupt_addr().is_some() {
-            self.halted = false;
-            if !self.ime {
-                //println!("halt bug");
-            }
-        }
-
-        // Handle interrupt
-        if self.ime {
-            if let Some(addr) = self.membus.interrupt_addr() {
-                self.ime = false;
-                self.ime_next = false;
-
-                self.push_stack(self.reg.pc);
-                self.reg.pc = addr as u16;
-                self.membus.do_cycles(5);
-            }
-        }
+    /// Address of the illegal opcode the CPU locked up on, if it did
+    pub fn locked(&self) -> Option<u16> {
+        self.locked
+    }
 
-        // Check if interrupt scheduled
-        self.ime = self.ime_next;
+    pub fn reg(&self) -> &Reg {
+        &self.reg
+    }
 
-        // NOP if halted
-        if self.halted {
-            self.membus.do_cycles(1);
-            return;
-        }
+    /// Interrupts are enabled
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
 
-        // Noraml flow: fetch opcode and execute
-        let opcode = self.read_byte();
-        let m_cycles = self.exec(opcode);
-        self.membus.do_cycles(m_cycles);
+    /// Waiting in HALT for an interrupt
+    pub fn halted(&self) -> bool {
+        self.halted
     }
 
-    // Execute opcode
-    fn exec(&mut self, opcode: u8) -> u32 {
-        match opcode {
-            0x00 => 1,
-            0x01 => {
-                let nn = self.read_word();
-                self.reg.set_bc(nn);
-                3
-            }
-            0x02 => {
-                self.membus.write(self.reg.bc(), self.reg.a);
-                2
-            }
-            0x03 => {
-                self.reg.set_bc(self.reg.bc().wrapping_add(1));
-                2
-            }
-            0x04 => {
-                self.reg.b = self.alu_inc(self.reg.b);
-                1
-            }
-            0x05 => {
-                self.reg.b = self.alu_dec(self.reg.b);
-                1
-            }
-            0x06 => {
-                self.reg.b = self.read_byte();
-                2
-            }
-            0x07 => {
-                self.reg.a = self.alu_rlc(self.reg.a);
-                self.reg.set_flag(Z, false);
-                1
-            }
-            0x08 => {
-                let nn = self.read_word();
-                self.membus.write(nn, self.reg.sp as u8);
-                self.membus.write(nn + 1, (self.reg.sp >> 8) as u8);
-                5
-            }
-            0x09 => {
-                self.alu_add16(self.reg.bc());
-                2
-            }
-            0x0A => {
-                self.reg.a = self.membus.read(self.reg.bc());
-                2
-            }
-            0x0B => {
-                self.reg.set_bc(self.reg.bc().wrapping_sub(1));
-                2
-            }
-            0x0C => {
-                self.reg.c = self.alu_inc(self.reg.c);
-                1
-            }
-            0x0D => {
-                self.reg.c = self.alu_dec(self.reg.c);
-                1
-            }
-            0x0E => {
-                self.reg.c = self.read_byte();
-                2
-            }
-            0x0F => {
-                self.reg.a = self.alu_rrc(self.reg.a);
-                self.reg.set_flag(Z, false);
-                1
-            }
-            0x10 => 1, // STOP
-            0x11 => {
-                let nn = self.read_word();
-                self.reg.set_de(nn);
-                3
-            }
-            0x12 => {
-                self.membus.write(self.reg.de(), self.reg.a);
-                2
-            }
-            0x13 => {
-                self.reg.set_de(self.reg.de().wrapping_add(1));
-                2
-            }
-            0x14 => {
-                self.reg.d = self.alu_inc(self.reg.d);
-                1
-            }
-            0x15 => {
-                self.reg.d = self.alu_dec(self.reg.d);
-                1
-            }
-            0x16 => {
-                self.reg.d = self.read_byte();
-                2
-            }
-            0x17 => {
-                self.reg.a = self.alu_rl(self.reg.a);
-                self.reg.set_flag(Z, false);
-                1
-            }
-            0x18 => {
-                let e = self.read_byte() as i8;
-                self.reg.pc = self.reg.pc.wrapping_add(e as i16 as u16);
-                3
-            }
-            0x19 => {
-                self.alu_add16(self.reg.de());
-                2
-            }
-            0x1A => {
-                self.reg.a = self.membus.read(self.reg.de());
-                2
-            }
-            0x1B => {
-                self.reg.set_de(self.reg.de().wrapping_sub(1));
-                2
-            }
-            0x1C => {
-                self.reg.e = self.alu_inc(self.reg.e);
-                1
-            }
-            0x1D => {
-                self.reg.e = self.alu_dec(self.reg.e);
-                1
-            }
-            0x1E => {
-                self.reg.e = self.read_byte();
-                2
-            }
-            0x1F => {
-                self.reg.a = self.alu_rr(self.reg.a);
-                self.reg.set_flag(Z, false);
-                1
-            }
-            0x20 => {
-                let e = self.read_byte() as i8;
-                if !self.reg.flag(Z) {
-                    self.reg.pc = self.reg.pc.wrapping_add(e as u16);
-                    3
-                } else {
-                    2
-                }
-            }
-            0x21 => {
-                let nn = self.read_word();
-                self.reg.set_hl(nn);
-                3
-            }
-            0x22 => {
-                self.membus.write(self.reg.hl(), self.reg.a);
-                self.reg.set_hl(self.reg.hl().wrapping_add(1));
-                2
-            }
-            0x23 => {
-                self.reg.set_hl(self.reg.hl().wrapping_add(1));
-                2
-            }
-            0x24 => {
-                self.reg.h = self.alu_inc(self.reg.h);
-                1
-            }
-            0x25 => {
-                self.reg.h = self.alu_dec(self.reg.h);
-                1
-            }
-            0x26 => {
-                self.reg.h = self.read_byte();
-                2
-            }
-            0x27 => {
-                // DAA instruction credit: https://forums.nesdev.org/viewtopic.php?t=15944
-                if !self.reg.flag(N) {
-                    if self.reg.flag(C) || self.reg.a > 0x99 {
-                        self.reg.a = self.reg.a.wrapping_add(0x60);
-                        self.reg.set_flag(C, true);
-                    }
-                    if self.reg.flag(H) || (self.reg.a & 0x0f) > 0x09 {
-                        self.reg.a = self.reg.a.wrapping_add(0x6);
-                    }
-                } else {
-                    if self.reg.flag(C) {
-                        self.reg.a = self.reg.a.wrapping_sub(0x60);
-                    }
-                    if self.reg.flag(H) {
-                        self.reg.a = self.reg.a.wrapping_sub(0x6);
-                    }
-                }
-                self.reg.set_flag(Z, self.reg.a == 0);
-                self.reg.set_flag(H, false);
-                1
-            }
-            0x28 => {
-                let e = self.read_byte();
-                if self.reg.flag(Z) {
-                    self.reg.pc = self.reg.pc.wrapping_add(e as i8 as i16 as u16);
-                    3
-                } else {
-                    2
-                }
-            }
-            0x29 => {
-                self.alu_add16(self.reg.hl());
-                2
-            }
-            0x2A => {
-                self.reg.a = self.membus.read(self.reg.hl());
-                self.reg.set_hl(self.reg.hl().wrapping_add(1));
-                2
-            }
-            0x2B => {
-                self.reg.set_hl(self.reg.hl().wrapping_sub(1));
-                2
-            }
-            0x2C => {
-                self.reg.l = self.alu_inc(self.reg.l);
-                1
-            }
-            0x2D => {
-                self.reg.l = self.alu_dec(self.reg.l);
-                1
-            }
-            0x2E => {
-                self.reg.l = self.read_byte();
-                2
-            }
-            0x2F => {
-                self.reg.a = !self.reg.a;
-                self.reg.set_flag(N, true);
-                self.reg.set_flag(H, true);
-                1
-            }
-            0x30 => {
-                if !self.reg.flag(C) {
-                    let e = self.read_byte() as i8;
-                    self.reg.pc = self.reg.pc.wrapping_add(e as i16 as u16);
-                    3
-                } else {
-                    self.reg.pc = self.reg.pc.wrapping_add(1);
-                    2
-                }
-            }
-            0x31 => {
-                let nn = self.read_word();
-                self.reg.sp = nn;
-                3
-            }
-            0x32 => {
-                self.membus.write(self.reg.hl(), self.reg.a);
-                self.reg.set_hl(self.reg.hl().wrapping_sub(1));
-                2
-            }
-            0x33 => {
-                self.reg.sp = self.reg.sp.wrapping_add(1);
-                2
-            }
-            0x34 => {
-                let res = self.alu_inc(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                3
-            }
-            0x35 => {
-                let res = self.alu_dec(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                3
-            }
-            0x36 => {
-                let byte = self.read_byte();
-                self.membus.write(self.reg.hl(), byte);
-                3
-            }
-            0x37 => {
-                self.reg.set_flag(N, false);
-                self.reg.set_flag(H, false);
-                self.reg.set_flag(C, true);
-                1
-            }
-            0x38 => {
-                let e = self.read_byte();
-                if self.reg.flag(C) {
-                    self.reg.pc = self.reg.pc.wrapping_add(e as i16 as u16);
-                    3
-                } else {
-                    2
-                }
-            }
-            0x39 => {
-                self.alu_add16(self.reg.sp);
-                2
-            }
-            0x3A => {
-                self.reg.a = self.membus.read(self.reg.hl());
-                self.reg.set_hl(self.reg.hl().wrapping_sub(1));
-                2
-            }
-            0x3B => {
-                self.reg.sp = self.reg.sp.wrapping_sub(1);
-                2
-            }
-            0x3C => {
-                self.reg.a = self.alu_inc(self.reg.a);
-                1
-            }
-            0x3D => {
-                self.reg.a = self.alu_dec(self.reg.a);
-                1
-            }
-            0x3E => {
-                self.reg.a = self.read_byte();
-                2
-            }
-            0x3F => {
-                self.reg.set_flag(N, false);
-                self.reg.set_flag(H, false);
-                self.reg.set_flag(C, !self.reg.flag(C));
-                1
-            }
-            0x40 => 1,
-            0x41 => {
-                self.reg.b = self.reg.c;
-                1
-            }
-            0x42 => {
-                self.reg.b = self.reg.d;
-                1
-            }
-            0x43 => {
-                self.reg.b = self.reg.e;
-                1
-            }
-            0x44 => {
-                self.reg.b = self.reg.h;
-                1
-            }
-            0x45 => {
-                self.reg.b = self.reg.l;
-                1
-            }
-            0x46 => {
-                self.reg.b = self.membus.read(self.reg.hl());
-                2
-            }
-            0x47 => {
-                self.reg.b = self.reg.a;
-                1
-            }
-            0x48 => {
-                self.reg.c = self.reg.b;
-                1
-            }
-            0x49 => 1,
-            0x4A => {
-                self.reg.c = self.reg.d;
-                1
-            }
-            0x4B => {
-                self.reg.c = self.reg.e;
-                1
-            }
-            0x4C => {
-                self.reg.c = self.reg.h;
-                1
-            }
-            0x4D => {
-                self.reg.c = self.reg.l;
-                1
-            }
-            0x4E => {
-                self.reg.c = self.membus.read(self.reg.hl());
-                2
-            }
-            0x4F => {
-                self.reg.c = self.reg.a;
-                1
-            }
-            0x50 => {
-                self.reg.d = self.reg.b;
-                1
-            }
-            0x51 => {
-                self.reg.d = self.reg.c;
-                1
-            }
-            0x52 => 1,
-            0x53 => {
-                self.reg.d = self.reg.e;
-                1
-            }
-            0x54 => {
-                self.reg.d = self.reg.h;
-                1
-            }
-            0x55 => {
-                self.reg.d = self.reg.l;
-                1
-            }
-            0x56 => {
-                self.reg.d = self.membus.read(self.reg.hl());
-                2
-            }
-            0x57 => {
-                self.reg.d = self.reg.a;
-                1
-            }
-            0x58 => {
-                self.reg.e = self.reg.b;
-                1
-            }
-            0x59 => {
-                self.reg.e = self.reg.c;
-                1
-            }
-            0x5A => {
-                self.reg.e = self.reg.d;
-                1
-            }
-            0x5B => 1,
-            0x5C => {
-                self.reg.e = self.reg.h;
-                1
-            }
-            0x5D => {
-                self.reg.e = self.reg.l;
-                1
-            }
-            0x5E => {
-                self.reg.e = self.membus.read(self.reg.hl());
-                2
-            }
-            0x5F => {
-                self.reg.e = self.reg.a;
-                1
-            }
-            0x60 => {
-                self.reg.h = self.reg.b;
-                1
-            }
-            0x61 => {
-                self.reg.h = self.reg.c;
-                1
-            }
-            0x62 => {
-                self.reg.h = self.reg.d;
-                1
-            }
-            0x63 => {
-                self.reg.h = self.reg.e;
-                1
-            }
-            0x64 => 1,
-            0x65 => {
-                self.reg.h = self.reg.l;
-                1
-            }
-            0x66 => {
-                self.reg.h = self.membus.read(self.reg.hl());
-                2
-            }
-            0x67 => {
-                self.reg.h = self.reg.a;
-                1
-            }
-            0x68 => {
-                self.reg.l = self.reg.b;
-                1
-            }
-            0x69 => {
-                self.reg.l = self.reg.c;
-                1
-            }
-            0x6A => {
-                self.reg.l = self.reg.d;
-                1
-            }
-            0x6B => {
-                self.reg.l = self.reg.e;
-                1
-            }
-            0x6C => {
-                self.reg.l = self.reg.h;
-                1
-            }
-            0x6D => 1,
-            0x6E => {
-                self.reg.l = self.membus.read(self.reg.hl());
-                2
-            }
-            0x6F => {
-                self.reg.l = self.reg.a;
-                1
-            }
-            0x70 => {
-                self.membus.write(self.reg.hl(), self.reg.b);
-                2
-            }
-            0x71 => {
-                self.membus.write(self.reg.hl(), self.reg.c);
-                2
-            }
-            0x72 => {
-                self.membus.write(self.reg.hl(), self.reg.d);
-                2
-            }
-            0x73 => {
-                self.membus.write(self.reg.hl(), self.reg.e);
-                2
-            }
-            0x74 => {
-                self.membus.write(self.reg.hl(), self.reg.h);
-                2
-            }
-            0x75 => {
-                self.membus.write(self.reg.hl(), self.reg.l);
-                2
-            }
-            0x76 => {
-                self.halted = true;
-                1
-            }
-            0x77 => {
-                self.membus.write(self.reg.hl(), self.reg.a);
-                2
-            }
-            0x78 => {
-                self.reg.a = self.reg.b;
-                1
-            }
-            0x79 => {
-                self.reg.a = self.reg.c;
-                1
-            }
-            0x7A => {
-                self.reg.a = self.reg.d;
-                1
-            }
-            0x7B => {
-                self.reg.a = self.reg.e;
-                1
-            }
-            0x7C => {
-                self.reg.a = self.reg.h;
-                1
-            }
-            0x7D => {
-                self.reg.a = self.reg.l;
-                1
-            }
-            0x7E => {
-                self.reg.a = self.membus.read(self.reg.hl());
-                2
-            }
-            0x7F => 1,
-            0x80 => {
-                self.alu_add(self.reg.b);
-                1
-            }
-            0x81 => {
-                self.alu_add(self.reg.c);
-                1
-            }
-            0x82 => {
-                self.alu_add(self.reg.d);
-                1
-            }
-            0x83 => {
-                self.alu_add(self.reg.e);
-                1
-            }
-            0x84 => {
-                self.alu_add(self.reg.h);
-                1
-            }
-            0x85 => {
-                self.alu_add(self.reg.l);
-                1
-            }
-            0x86 => {
-                self.alu_add(self.membus.read(self.reg.hl()));
-                2
-            }
-            0x87 => {
-                self.alu_add(self.reg.a);
-                1
-            }
-            0x88 => {
-                self.alu_adc(self.reg.b);
-                1
-            }
-            0x89 => {
-                self.alu_adc(self.reg.c);
-                1
-            }
-            0x8A => {
-                self.alu_adc(self.reg.d);
-                1
-            }
-            0x8B => {
-                self.alu_adc(self.reg.e);
-                1
-            }
-            0x8C => {
-                self.alu_adc(self.reg.h);
-                1
-            }
-            0x8D => {
-                self.alu_adc(self.reg.l);
-                1
-            }
-            0x8E => {
-                self.alu_adc(self.membus.read(self.reg.hl()));
-                2
-            }
-            0x8F => {
-                self.alu_adc(self.reg.a);
-                1
-            }
-            0x90 => {
-                self.alu_sub(self.reg.b);
-                1
-            }
-            0x91 => {
-                self.alu_sub(self.reg.c);
-                1
-            }
-            0x92 => {
-                self.alu_sub(self.reg.d);
-                1
-            }
-            0x93 => {
-                self.alu_sub(self.reg.e);
-                1
-            }
-            0x94 => {
-                self.alu_sub(self.reg.h);
-                1
-            }
-            0x95 => {
-                self.alu_sub(self.reg.l);
-                1
-            }
-            0x96 => {
-                self.alu_sub(self.membus.read(self.reg.hl()));
-                2
-            }
-            0x97 => {
-                self.alu_sub(self.reg.a);
-                1
-            }
-            0x98 => {
-                self.alu_sbc(self.reg.b);
-                1
-            }
-            0x99 => {
-                self.alu_sbc(self.reg.c);
-                1
-            }
-            0x9A => {
-                self.alu_sbc(self.reg.d);
-                1
-            }
-            0x9B => {
-                self.alu_sbc(self.reg.e);
-                1
-            }
-            0x9C => {
-                self.alu_sbc(self.reg.h);
-                1
-            }
-            0x9D => {
-                self.alu_sbc(self.reg.l);
-                1
-            }
-            0x9E => {
-                self.alu_sbc(self.membus.read(self.reg.hl()));
-                2
-            }
-            0x9F => {
-                self.alu_sbc(self.reg.a);
-                1
-            }
-            0xA0 => {
-                self.alu_and(self.reg.b);
-                1
-            }
-            0xA1 => {
-                self.alu_and(self.reg.c);
-                1
-            }
-            0xA2 => {
-                self.alu_and(self.reg.d);
-                1
-            }
-            0xA3 => {
-                self.alu_and(self.reg.e);
-                1
-            }
-            0xA4 => {
-                self.alu_and(self.reg.h);
-                1
-            }
-            0xA5 => {
-                self.alu_and(self.reg.l);
-                1
-            }
-            0xA6 => {
-                self.alu_and(self.membus.read(self.reg.hl()));
-                2
-            }
-            0xA7 => {
-                self.alu_and(self.reg.a);
-                1
-            }
-            0xA8 => {
-                self.alu_xor(self.reg.b);
-                1
-            }
-            0xA9 => {
-                self.alu_xor(self.reg.c);
-                1
-            }
-            0xAA => {
-                self.alu_xor(self.reg.d);
-                1
-            }
-            0xAB => {
-                self.alu_xor(self.reg.e);
-                1
-            }
-            0xAC => {
-                self.alu_xor(self.reg.h);
-                1
-            }
-            0xAD => {
-                self.alu_xor(self.reg.l);
-                1
-            }
-            0xAE => {
-                self.alu_xor(self.membus.read(self.reg.hl()));
-                2
-            }
-            0xAF => {
-                self.alu_xor(self.reg.a);
-                1
-            }
-            0xB0 => {
-                self.alu_or(self.reg.b);
-                1
-            }
-            0xB1 => {
-                self.alu_or(self.reg.c);
-                1
-            }
-            0xB2 => {
-                self.alu_or(self.reg.d);
-                1
-            }
-            0xB3 => {
-                self.alu_or(self.reg.e);
-                1
-            }
-            0xB4 => {
-                self.alu_or(self.reg.h);
-                1
-            }
-            0xB5 => {
-                self.alu_or(self.reg.l);
-                1
-            }
-            0xB6 => {
-                self.alu_or(self.membus.read(self.reg.hl()));
-                2
-            }
-            0xB7 => {
-                self.alu_or(self.reg.a);
-                1
-            }
-            0xB8 => {
-                self.alu_cp(self.reg.b);
-                1
-            }
-            0xB9 => {
-                self.alu_cp(self.reg.c);
-                1
-            }
-            0xBA => {
-                self.alu_cp(self.reg.d);
-                1
-            }
-            0xBB => {
-                self.alu_cp(self.reg.e);
-                1
-            }
-            0xBC => {
-                self.alu_cp(self.reg.h);
-                1
-            }
-            0xBD => {
-                self.alu_cp(self.reg.l);
-                1
-            }
-            0xBE => {
-                self.alu_cp(self.membus.read(self.reg.hl()));
-                2
-            }
-            0xBF => {
-                self.alu_cp(self.reg.a);
-                1
-            }
-            0xC0 => {
-                if !self.reg.flag(Z) {
-                    self.reg.pc = self.pop_stack();
-                    5
-                } else {
-                    2
-                }
-            }
-            0xC1 => {
-                let res = self.pop_stack();
-                self.reg.set_bc(res);
-                3
-            }
-            0xC2 => {
-                let nn = self.read_word();
-                if !self.reg.flag(Z) {
-                    self.reg.pc = nn;
-                    4
-                } else {
-                    3
-                }
-            }
-            0xC3 => {
-                let nn = self.read_word();
-                self.reg.pc = nn;
-                4
-            }
-            0xC4 => {
-                let nn = self.read_word();
-                if !self.reg.flag(Z) {
-                    self.push_stack(self.reg.pc);
-                    self.reg.pc = nn;
-                    6
-                } else {
-                    3
-                }
-            }
-            0xC5 => {
-                self.push_stack(self.reg.bc());
-                4
-            }
-            0xC6 => {
-                let n = self.read_byte();
-                self.alu_add(n);
-                2
-            }
-            0xC7 => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x00;
-                4
-            }
-            0xC8 => {
-                if self.reg.flag(Z) {
-                    self.reg.pc = self.pop_stack();
-                    5
-                } else {
-                    2
-                }
-            }
-            0xC9 => {
-                self.reg.pc = self.pop_stack();
-                4
-            }
-            0xCA => {
-                let nn = self.read_word();
-                if self.reg.flag(Z) {
-                    self.reg.pc = nn;
-                    4
-                } else {
-                    3
-                }
-            }
-            0xCB => {
-                let op = self.read_byte();
-                self.exec_cb(op)
-            }
-            0xCC => {
-                let nn = self.read_word();
-                if self.reg.flag(Z) {
-                    self.push_stack(self.reg.pc);
-                    self.reg.pc = nn;
-                    6
-                } else {
-                    3
-                }
-            }
-            0xCD => {
-                let nn = self.read_word();
-                self.push_stack(self.reg.pc);
-                self.reg.pc = nn;
-                6
-            }
-            0xCE => {
-                let n = self.read_byte();
-                self.alu_adc(n);
-                2
-            }
-            0xCF => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x08;
-                4
-            }
-            0xD0 => {
-                if !self.reg.flag(C) {
-                    self.reg.pc = self.pop_stack();
-                    5
-                } else {
-                    2
-                }
-            }
-            0xD1 => {
-                let res = self.pop_stack();
-                self.reg.set_de(res);
-                3
-            }
-            0xD2 => {
-                let nn = self.read_word();
-                if !self.reg.flag(C) {
-                    self.reg.pc = nn;
-                    4
-                } else {
-                    3
-                }
-            }
-            0xD4 => {
-                let nn = self.read_word();
-                if !self.reg.flag(C) {
-                    self.push_stack(self.reg.pc);
-                    self.reg.pc = nn;
-                    6
-                } else {
-                    3
-                }
-            }
-            0xD5 => {
-                self.push_stack(self.reg.de());
-                4
-            }
-            0xD6 => {
-                let n = self.read_byte();
-                self.alu_sub(n);
-                2
-            }
-            0xD7 => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x10;
-                4
-            }
-            0xD8 => {
-                if self.reg.flag(C) {
-                    self.reg.pc = self.pop_stack();
-                    5
-                } else {
-                    2
-                }
-            }
-            0xD9 => {
-                self.reg.pc = self.pop_stack();
-                self.ime = true;
-                4
-            }
-            0xDA => {
-                let nn = self.read_word();
-                if self.reg.flag(C) {
-                    self.reg.pc = nn;
-                    4
-                } else {
-                    3
-                }
-            }
-            0xDC => {
-                let nn = self.read_word();
-                if self.reg.flag(C) {
-                    self.push_stack(self.reg.pc);
-                    self.reg.pc = nn;
-                    6
-                } else {
-                    3
-                }
-            }
-            0xDE => {
-                let n = self.read_byte();
-                self.alu_sbc(n);
-                2
-            }
-            0xDF => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x18;
-                4
-            }
-            0xE0 => {
-                let n = self.read_byte();
-                let addr = 0xFF00 | n as u16;
-                self.membus.write(addr, self.reg.a);
-                3
-            }
-            0xE1 => {
-                let res = self.pop_stack();
-                self.reg.set_hl(res);
-                3
-            }
-            0xE2 => {
-                let addr = (0xFF << 8) | (self.reg.c as u16);
-                self.membus.write(addr, self.reg.a);
-                2
-            }
-            0xE5 => {
-                self.push_stack(self.reg.hl());
-                4
-            }
-            0xE6 => {
-                let n = self.read_byte();
-                self.alu_and(n);
-                2
-            }
-            0xE7 => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x20;
-                4
-            }
-            0xE8 => {
-                self.reg.sp = self.alu_add16imm(self.reg.sp);
-                4
-            }
-            0xE9 => {
-                self.reg.pc = self.reg.hl();
-                1
-            }
-            0xEA => {
-                let nn = self.read_word();
-                self.membus.write(nn, self.reg.a);
-                4
-            }
-            0xEE => {
-                let n = self.read_byte();
-                self.alu_xor(n);
-                2
-            }
-            0xEF => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x28;
-                4
-            }
-            0xF0 => {
-                let addr = 0xFF00 | (self.read_byte() as u16);
-                self.reg.a = self.membus.read(addr);
-                3
-            }
-            0xF1 => {
-                let res = self.pop_stack();
-                self.reg.set_af(res);
-                3
-            }
-            0xF2 => {
-                let addr = 0xFF00 | (self.reg.c as u16);
-                self.reg.a = self.membus.read(addr);
-                2
-            }
-            0xF3 => {
-                self.ime_next = false;
-                1
-            }
-            0xF5 => {
-                self.push_stack(self.reg.af());
-                4
-            }
-            0xF6 => {
-                let n = self.read_byte();
-                self.alu_or(n);
-                2
-            }
-            0xF7 => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x30;
-                4
-            }
-            0xF8 => {
-                let res = self.alu_add16imm(self.reg.sp);
-                self.reg.set_hl(res);
-                3
-            }
-            0xF9 => {
-                self.reg.sp = self.reg.hl();
-                2
-            }
-            0xFA => {
-                let nn = self.read_word();
-                self.reg.a = self.membus.read(nn);
-                4
-            }
-            0xFB => {
-                self.ime_next = true;
-                1
-            }
-            0xFE => {
-                let n = self.read_byte();
-                self.alu_cp(n);
-                2
-            }
-            0xFF => {
-                self.push_stack(self.reg.pc);
-                self.reg.pc = 0x38;
-                4
-            }
-            _ => panic!("Unimplemented opcode: 0x{:02X}", opcode),
-        }
-    }
-
-    fn exec_cb(&mut self, opcode: u8) -> u32 {
-        match opcode {
-            0x00 => {
-                self.reg.b = self.alu_rlc(self.reg.b);
-                2
-            }
-            0x01 => {
-                self.reg.c = self.alu_rlc(self.reg.c);
-                2
-            }
-            0x02 => {
-                self.reg.d = self.alu_rlc(self.reg.d);
-                2
-            }
-            0x03 => {
-                self.reg.e = self.alu_rlc(self.reg.e);
-                2
-            }
-            0x04 => {
-                self.reg.h = self.alu_rlc(self.reg.h);
-                2
-            }
-            0x05 => {
-                self.reg.l = self.alu_rlc(self.reg.l);
-                2
-            }
-            0x06 => {
-                let res = self.alu_rlc(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x07 => {
-                self.reg.a = self.alu_rlc(self.reg.a);
-                2
-            }
-            0x08 => {
-                self.reg.b = self.alu_rrc(self.reg.b);
-                2
-            }
-            0x09 => {
-                self.reg.c = self.alu_rrc(self.reg.c);
-                2
-            }
-            0x0A => {
-                self.reg.d = self.alu_rrc(self.reg.d);
-                2
-            }
-            0x0B => {
-                self.reg.e = self.alu_rrc(self.reg.e);
-                2
-            }
-            0x0C => {
-                self.reg.h = self.alu_rrc(self.reg.h);
-                2
-            }
-            0x0D => {
-                self.reg.l = self.alu_rrc(self.reg.l);
-                2
-            }
-            0x0E => {
-                let res = self.alu_rrc(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x0F => {
-                self.reg.a = self.alu_rrc(self.reg.a);
-                2
-            }
-            0x10 => {
-                self.reg.b = self.alu_rl(self.reg.b);
-                2
-            }
-            0x11 => {
-                self.reg.c = self.alu_rl(self.reg.c);
-                2
-            }
-            0x12 => {
-                self.reg.d = self.alu_rl(self.reg.d);
-                2
-            }
-            0x13 => {
-                self.reg.e = self.alu_rl(self.reg.e);
-                2
-            }
-            0x14 => {
-                self.reg.h = self.alu_rl(self.reg.h);
-                2
-            }
-            0x15 => {
-                self.reg.l = self.alu_rl(self.reg.l);
-                2
-            }
-            0x16 => {
-                let res = self.alu_rl(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x17 => {
-                self.reg.a = self.alu_rl(self.reg.a);
-                2
-            }
-            0x18 => {
-                self.reg.b = self.alu_rr(self.reg.b);
-                2
-            }
-            0x19 => {
-                self.reg.c = self.alu_rr(self.reg.c);
-                2
-            }
-            0x1A => {
-                self.reg.d = self.alu_rr(self.reg.d);
-                2
-            }
-            0x1B => {
-                self.reg.e = self.alu_rr(self.reg.e);
-                2
-            }
-            0x1C => {
-                self.reg.h = self.alu_rr(self.reg.h);
-                2
-            }
-            0x1D => {
-                self.reg.l = self.alu_rr(self.reg.l);
-                2
-            }
-            0x1E => {
-                let res = self.alu_rr(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x1F => {
-                self.reg.a = self.alu_rr(self.reg.a);
-                2
-            }
-            0x20 => {
-                self.reg.b = self.alu_sla(self.reg.b);
-                2
-            }
-            0x21 => {
-                self.reg.c = self.alu_sla(self.reg.c);
-                2
-            }
-            0x22 => {
-                self.reg.d = self.alu_sla(self.reg.d);
-                2
-            }
-            0x23 => {
-                self.reg.e = self.alu_sla(self.reg.e);
-                2
-            }
-            0x24 => {
-                self.reg.h = self.alu_sla(self.reg.h);
-                2
-            }
-            0x25 => {
-                self.reg.l = self.alu_sla(self.reg.l);
-                2
-            }
-            0x26 => {
-                let res = self.alu_sla(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x27 => {
-                self.reg.a = self.alu_sla(self.reg.a);
-                2
-            }
-            0x28 => {
-                self.reg.b = self.alu_sra(self.reg.b);
-                2
-            }
-            0x29 => {
-                self.reg.c = self.alu_sra(self.reg.c);
-                2
-            }
-            0x2A => {
-                self.reg.d = self.alu_sra(self.reg.d);
-                2
-            }
-            0x2B => {
-                self.reg.e = self.alu_sra(self.reg.e);
-                2
-            }
-            0x2C => {
-                self.reg.h = self.alu_sra(self.reg.h);
-                2
-            }
-            0x2D => {
-                self.reg.l = self.alu_sra(self.reg.l);
-                2
-            }
-            0x2E => {
-                let res = self.alu_sra(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x2F => {
-                self.reg.a = self.alu_sra(self.reg.a);
-                2
-            }
-            0x30 => {
-                self.reg.b = self.alu_swap(self.reg.b);
-                2
-            }
-            0x31 => {
-                self.reg.c = self.alu_swap(self.reg.c);
-                2
-            }
-            0x32 => {
-                self.reg.d = self.alu_swap(self.reg.d);
-                2
-            }
-            0x33 => {
-                self.reg.e = self.alu_swap(self.reg.e);
-                2
-            }
-            0x34 => {
-                self.reg.h = self.alu_swap(self.reg.h);
-                2
-            }
-            0x35 => {
-                self.reg.l = self.alu_swap(self.reg.l);
-                2
-            }
-            0x36 => {
-                let res = self.alu_swap(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x37 => {
-                self.reg.a = self.alu_swap(self.reg.a);
-                2
-            }
-            0x38 => {
-                self.reg.b = self.alu_srl(self.reg.b);
-                2
-            }
-            0x39 => {
-                self.reg.c = self.alu_srl(self.reg.c);
-                2
-            }
-            0x3A => {
-                self.reg.d = self.alu_srl(self.reg.d);
-                2
-            }
-            0x3B => {
-                self.reg.e = self.alu_srl(self.reg.e);
-                2
-            }
-            0x3C => {
-                self.reg.h = self.alu_srl(self.reg.h);
-                2
-            }
-            0x3D => {
-                self.reg.l = self.alu_srl(self.reg.l);
-                2
-            }
-            0x3E => {
-                let res = self.alu_srl(self.membus.read(self.reg.hl()));
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x3F => {
-                self.reg.a = self.alu_srl(self.reg.a);
-                2
-            }
-            0x40 => {
-                self.alu_bit(0, self.reg.b);
-                2
-            }
-            0x41 => {
-                self.alu_bit(0, self.reg.c);
-                2
-            }
-            0x42 => {
-                self.alu_bit(0, self.reg.d);
-                2
-            }
-            0x43 => {
-                self.alu_bit(0, self.reg.e);
-                2
-            }
-            0x44 => {
-                self.alu_bit(0, self.reg.h);
-                2
-            }
-            0x45 => {
-                self.alu_bit(0, self.reg.l);
-                2
-            }
-            0x46 => {
-                self.alu_bit(0, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x47 => {
-                self.alu_bit(0, self.reg.a);
-                2
-            }
-            0x48 => {
-                self.alu_bit(1, self.reg.b);
-                2
-            }
-            0x49 => {
-                self.alu_bit(1, self.reg.c);
-                2
-            }
-            0x4A => {
-                self.alu_bit(1, self.reg.d);
-                2
-            }
-            0x4B => {
-                self.alu_bit(1, self.reg.e);
-                2
-            }
-            0x4C => {
-                self.alu_bit(1, self.reg.h);
-                2
-            }
-            0x4D => {
-                self.alu_bit(1, self.reg.l);
-                2
-            }
-            0x4E => {
-                self.alu_bit(1, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x4F => {
-                self.alu_bit(1, self.reg.a);
-                2
-            }
-            0x50 => {
-                self.alu_bit(2, self.reg.b);
-                2
-            }
-            0x51 => {
-                self.alu_bit(2, self.reg.c);
-                2
-            }
-            0x52 => {
-                self.alu_bit(2, self.reg.d);
-                2
-            }
-            0x53 => {
-                self.alu_bit(2, self.reg.e);
-                2
-            }
-            0x54 => {
-                self.alu_bit(2, self.reg.h);
-                2
-            }
-            0x55 => {
-                self.alu_bit(2, self.reg.l);
-                2
-            }
-            0x56 => {
-                self.alu_bit(2, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x57 => {
-                self.alu_bit(2, self.reg.a);
-                2
-            }
-            0x58 => {
-                self.alu_bit(3, self.reg.b);
-                2
-            }
-            0x59 => {
-                self.alu_bit(3, self.reg.c);
-                2
-            }
-            0x5A => {
-                self.alu_bit(3, self.reg.d);
-                2
-            }
-            0x5B => {
-                self.alu_bit(3, self.reg.e);
-                2
-            }
-            0x5C => {
-                self.alu_bit(3, self.reg.h);
-                2
-            }
-            0x5D => {
-                self.alu_bit(3, self.reg.l);
-                2
-            }
-            0x5E => {
-                self.alu_bit(3, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x5F => {
-                self.alu_bit(3, self.reg.a);
-                2
-            }
-            0x60 => {
-                self.alu_bit(4, self.reg.b);
-                2
-            }
-            0x61 => {
-                self.alu_bit(4, self.reg.c);
-                2
-            }
-            0x62 => {
-                self.alu_bit(4, self.reg.d);
-                2
-            }
-            0x63 => {
-                self.alu_bit(4, self.reg.e);
-                2
-            }
-            0x64 => {
-                self.alu_bit(4, self.reg.h);
-                2
-            }
-            0x65 => {
-                self.alu_bit(4, self.reg.l);
-                2
-            }
-            0x66 => {
-                self.alu_bit(4, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x67 => {
-                self.alu_bit(4, self.reg.a);
-                2
-            }
-            0x68 => {
-                self.alu_bit(5, self.reg.b);
-                2
-            }
-            0x69 => {
-                self.alu_bit(5, self.reg.c);
-                2
-            }
-            0x6A => {
-                self.alu_bit(5, self.reg.d);
-                2
-            }
-            0x6B => {
-                self.alu_bit(5, self.reg.e);
-                2
-            }
-            0x6C => {
-                self.alu_bit(5, self.reg.h);
-                2
-            }
-            0x6D => {
-                self.alu_bit(5, self.reg.l);
-                2
-            }
-            0x6E => {
-                self.alu_bit(5, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x6F => {
-                self.alu_bit(5, self.reg.a);
-                2
-            }
-            0x70 => {
-                self.alu_bit(6, self.reg.b);
-                2
-            }
-            0x71 => {
-                self.alu_bit(6, self.reg.c);
-                2
-            }
-            0x72 => {
-                self.alu_bit(6, self.reg.d);
-                2
-            }
-            0x73 => {
-                self.alu_bit(6, self.reg.e);
-                2
-            }
-            0x74 => {
-                self.alu_bit(6, self.reg.h);
-                2
-            }
-            0x75 => {
-                self.alu_bit(6, self.reg.l);
-                2
-            }
-            0x76 => {
-                self.alu_bit(6, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x77 => {
-                self.alu_bit(6, self.reg.a);
-                2
-            }
-            0x78 => {
-                self.alu_bit(7, self.reg.b);
-                2
-            }
-            0x79 => {
-                self.alu_bit(7, self.reg.c);
-                2
-            }
-            0x7A => {
-                self.alu_bit(7, self.reg.d);
-                2
-            }
-            0x7B => {
-                self.alu_bit(7, self.reg.e);
-                2
-            }
-            0x7C => {
-                self.alu_bit(7, self.reg.h);
-                2
-            }
-            0x7D => {
-                self.alu_bit(7, self.reg.l);
-                2
-            }
-            0x7E => {
-                self.alu_bit(7, self.membus.read(self.reg.hl()));
-                3
-            }
-            0x7F => {
-                self.alu_bit(7, self.reg.a);
-                2
-            }
-            0x80 => {
-                self.reg.b = self.reg.b & !(1 << 0);
-                2
-            }
-            0x81 => {
-                self.reg.c = self.reg.c & !(1 << 0);
-                2
-            }
-            0x82 => {
-                self.reg.d = self.reg.d & !(1 << 0);
-                2
-            }
-            0x83 => {
-                self.reg.e = self.reg.e & !(1 << 0);
-                2
-            }
-            0x84 => {
-                self.reg.h = self.reg.h & !(1 << 0);
-                2
-            }
-            0x85 => {
-                self.reg.l = self.reg.l & !(1 << 0);
-                2
-            }
-            0x86 => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 0);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x87 => {
-                self.reg.a = self.reg.a & !(1 << 0);
-                2
-            }
-            0x88 => {
-                self.reg.b = self.reg.b & !(1 << 1);
-                2
-            }
-            0x89 => {
-                self.reg.c = self.reg.c & !(1 << 1);
-                2
-            }
-            0x8A => {
-                self.reg.d = self.reg.d & !(1 << 1);
-                2
-            }
-            0x8B => {
-                self.reg.e = self.reg.e & !(1 << 1);
-                2
-            }
-            0x8C => {
-                self.reg.h = self.reg.h & !(1 << 1);
-                2
-            }
-            0x8D => {
-                self.reg.l = self.reg.l & !(1 << 1);
-                2
-            }
-            0x8E => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 1);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x8F => {
-                self.reg.a = self.reg.a & !(1 << 1);
-                2
-            }
-            0x90 => {
-                self.reg.b = self.reg.b & !(1 << 2);
-                2
-            }
-            0x91 => {
-                self.reg.c = self.reg.c & !(1 << 2);
-                2
-            }
-            0x92 => {
-                self.reg.d = self.reg.d & !(1 << 2);
-                2
-            }
-            0x93 => {
-                self.reg.e = self.reg.e & !(1 << 2);
-                2
-            }
-            0x94 => {
-                self.reg.h = self.reg.h & !(1 << 2);
-                2
-            }
-            0x95 => {
-                self.reg.l = self.reg.l & !(1 << 2);
-                2
-            }
-            0x96 => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 2);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x97 => {
-                self.reg.a = self.reg.a & !(1 << 2);
-                2
-            }
-            0x98 => {
-                self.reg.b = self.reg.b & !(1 << 3);
-                2
-            }
-            0x99 => {
-                self.reg.c = self.reg.c & !(1 << 3);
-                2
-            }
-            0x9A => {
-                self.reg.d = self.reg.d & !(1 << 3);
-                2
-            }
-            0x9B => {
-                self.reg.e = self.reg.e & !(1 << 3);
-                2
-            }
-            0x9C => {
-                self.reg.h = self.reg.h & !(1 << 3);
-                2
-            }
-            0x9D => {
-                self.reg.l = self.reg.l & !(1 << 3);
-                2
-            }
-            0x9E => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 3);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0x9F => {
-                self.reg.a = self.reg.a & !(1 << 3);
-                2
-            }
-            0xA0 => {
-                self.reg.b = self.reg.b & !(1 << 4);
-                2
-            }
-            0xA1 => {
-                self.reg.c = self.reg.c & !(1 << 4);
-                2
-            }
-            0xA2 => {
-                self.reg.d = self.reg.d & !(1 << 4);
-                2
-            }
-            0xA3 => {
-                self.reg.e = self.reg.e & !(1 << 4);
-                2
-            }
-            0xA4 => {
-                self.reg.h = self.reg.h & !(1 << 4);
-                2
-            }
-            0xA5 => {
-                self.reg.l = self.reg.l & !(1 << 4);
-                2
-            }
-            0xA6 => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 4);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xA7 => {
-                self.reg.a = self.reg.a & !(1 << 4);
-                2
-            }
-            0xA8 => {
-                self.reg.b = self.reg.b & !(1 << 5);
-                2
-            }
-            0xA9 => {
-                self.reg.c = self.reg.c & !(1 << 5);
-                2
-            }
-            0xAA => {
-                self.reg.d = self.reg.d & !(1 << 5);
-                2
-            }
-            0xAB => {
-                self.reg.e = self.reg.e & !(1 << 5);
-                2
-            }
-            0xAC => {
-                self.reg.h = self.reg.h & !(1 << 5);
-                2
-            }
-            0xAD => {
-                self.reg.l = self.reg.l & !(1 << 5);
-                2
-            }
-            0xAE => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 5);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xAF => {
-                self.reg.a = self.reg.a & !(1 << 5);
-                2
-            }
-            0xB0 => {
-                self.reg.b = self.reg.b & !(1 << 6);
-                2
-            }
-            0xB1 => {
-                self.reg.c = self.reg.c & !(1 << 6);
-                2
-            }
-            0xB2 => {
-                self.reg.d = self.reg.d & !(1 << 6);
-                2
-            }
-            0xB3 => {
-                self.reg.e = self.reg.e & !(1 << 6);
-                2
-            }
-            0xB4 => {
-                self.reg.h = self.reg.h & !(1 << 6);
-                2
-            }
-            0xB5 => {
-                self.reg.l = self.reg.l & !(1 << 6);
-                2
-            }
-            0xB6 => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 6);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xB7 => {
-                self.reg.a = self.reg.a & !(1 << 6);
-                2
-            }
-            0xB8 => {
-                self.reg.b = self.reg.b & !(1 << 7);
-                2
-            }
-            0xB9 => {
-                self.reg.c = self.reg.c & !(1 << 7);
-                2
-            }
-            0xBA => {
-                self.reg.d = self.reg.d & !(1 << 7);
-                2
-            }
-            0xBB => {
-                self.reg.e = self.reg.e & !(1 << 7);
-                2
-            }
-            0xBC => {
-                self.reg.h = self.reg.h & !(1 << 7);
-                2
-            }
-            0xBD => {
-                self.reg.l = self.reg.l & !(1 << 7);
-                2
-            }
-            0xBE => {
-                let res = self.membus.read(self.reg.hl()) & !(1 << 7);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xBF => {
-                self.reg.a = self.reg.a & !(1 << 7);
-                2
-            }
-            0xC0 => {
-                self.reg.b = self.reg.b | (1 << 0);
-                2
-            }
-            0xC1 => {
-                self.reg.c = self.reg.c | (1 << 0);
-                2
-            }
-            0xC2 => {
-                self.reg.d = self.reg.d | (1 << 0);
-                2
-            }
-            0xC3 => {
-                self.reg.e = self.reg.e | (1 << 0);
-                2
-            }
-            0xC4 => {
-                self.reg.h = self.reg.h | (1 << 0);
-                2
-            }
-            0xC5 => {
-                self.reg.l = self.reg.l | (1 << 0);
-                2
-            }
-            0xC6 => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 0);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xC7 => {
-                self.reg.a = self.reg.a | (1 << 0);
-                2
-            }
-            0xC8 => {
-                self.reg.b = self.reg.b | (1 << 1);
-                2
-            }
-            0xC9 => {
-                self.reg.c = self.reg.c | (1 << 1);
-                2
-            }
-            0xCA => {
-                self.reg.d = self.reg.d | (1 << 1);
-                2
-            }
-            0xCB => {
-                self.reg.e = self.reg.e | (1 << 1);
-                2
-            }
-            0xCC => {
-                self.reg.h = self.reg.h | (1 << 1);
-                2
-            }
-            0xCD => {
-                self.reg.l = self.reg.l | (1 << 1);
-                2
-            }
-            0xCE => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 1);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xCF => {
-                self.reg.a = self.reg.a | (1 << 1);
-                2
-            }
-            0xD0 => {
-                self.reg.b = self.reg.b | (1 << 2);
-                2
-            }
-            0xD1 => {
-                self.reg.c = self.reg.c | (1 << 2);
-                2
-            }
-            0xD2 => {
-                self.reg.d = self.reg.d | (1 << 2);
-                2
+    // CPU cycle
+    pub fn cycle(&mut self) {
+        // Locked up for good, not even interrupts get through, the rest keeps running
+        if self.locked.is_some() {
+            self.membus.tick();
+            return;
+        }
+
+        // Check should leave HALT
+        if self.halted && self.membus.pending_interrupt().is_some() {
+            self.halted = false;
+            if !self.ime {
+                //println!("halt bug");
             }
-            0xD3 => {
-                self.reg.e = self.reg.e | (1 << 2);
-                2
+        }
+
+        // Handle interrupt, 2 wait cycles, PC pushed and then jumped to
+        if self.ime {
+            if let Some(addr) = self.membus.pending_interrupt() {
+                self.membus.ack_interrupt(addr);
+                self.ime = false;
+                self.ime_next = false;
+
+                self.tick();
+                self.push_stack(self.reg.pc);
+                self.reg.pc = addr as u16;
+                self.tick();
+                // Counted toward the handler
+                self.profile_call();
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.run(self.membus.bank(addr as u16), addr as u16, 5);
+                }
             }
-            0xD4 => {
-                self.reg.h = self.reg.h | (1 << 2);
-                2
+        }
+
+        // Check if interrupt scheduled
+        self.ime = self.ime_next;
+
+        // NOP if halted
+        if self.halted {
+            self.membus.tick();
+            if let Some(profiler) = &mut self.profiler {
+                profiler.halt();
             }
-            0xD5 => {
-                self.reg.l = self.reg.l | (1 << 2);
-                2
+            return;
+        }
+
+        // Noraml flow: fetch opcode and execute. Memory accesses tick the rest of the
+        // system as they happen, cycles spent inside the CPU are made up at the end.
+        if self.trace {
+            let (instr, _) = disassemble(|addr| self.membus.read(addr), self.reg.pc);
+            println!("{:04X}: {}", self.reg.pc, instr);
+        }
+        let pc = self.reg.pc;
+        self.ticks = 0;
+        let opcode = self.read_byte();
+        let m_cycles = self.exec(opcode);
+        debug_assert!(self.ticks <= m_cycles, "opcode 0x{:02X} overran", opcode);
+        for _ in self.ticks..m_cycles {
+            self.membus.tick();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.run(self.membus.bank(pc), pc, m_cycles);
+        }
+    }
+
+    // Execute opcode, returns the M-cycles it took
+    fn exec(&mut self, opcode: u8) -> u32 {
+        // (HL) operands take an extra cycle for each access
+        let mem = |r: R8| (r == R8::HlInd) as u32;
+        match decode(opcode) {
+            Instr::Nop => 1,
+            Instr::Stop => {
+                // STOP, only the CGB speed switch is emulated. The padding byte after
+                // it runs as the next instruction, games put a NOP there.
+                self.membus.stop();
+                1
             }
-            0xD6 => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 2);
-                self.membus.write(self.reg.hl(), res);
-                4
+            Instr::Halt => {
+                self.halted = true;
+                1
             }
-            0xD7 => {
-                self.reg.a = self.reg.a | (1 << 2);
-                2
+            Instr::Di => {
+                self.ime_next = false;
+                1
             }
-            0xD8 => {
-                self.reg.b = self.reg.b | (1 << 3);
-                2
+            Instr::Ei => {
+                self.ime_next = true;
+                1
             }
-            0xD9 => {
-                self.reg.c = self.reg.c | (1 << 3);
-                2
+            Instr::Prefix => {
+                let op = self.read_byte();
+                self.exec_cb(op)
             }
-            0xDA => {
-                self.reg.d = self.reg.d | (1 << 3);
-                2
+            Instr::LdR8(dst, src) => {
+                let val = self.r8(src);
+                self.set_r8(dst, val);
+                1 + mem(dst) + mem(src)
             }
-            0xDB => {
-                self.reg.e = self.reg.e | (1 << 3);
-                2
+            Instr::LdR8Imm(r) => {
+                let n = self.read_byte();
+                self.set_r8(r, n);
+                2 + mem(r)
             }
-            0xDC => {
-                self.reg.h = self.reg.h | (1 << 3);
-                2
+            Instr::LdR16Imm(rr) => {
+                let nn = self.read_word();
+                self.set_r16(rr, nn);
+                3
             }
-            0xDD => {
-                self.reg.l = self.reg.l | (1 << 3);
+            Instr::LdIndA(rr) => {
+                let addr = self.ind_addr(rr);
+                self.write(addr, self.reg.a);
                 2
             }
-            0xDE => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 3);
-                self.membus.write(self.reg.hl(), res);
-                4
-            }
-            0xDF => {
-                self.reg.a = self.reg.a | (1 << 3);
+            Instr::LdAInd(rr) => {
+                let addr = self.ind_addr(rr);
+                self.reg.a = self.read(addr);
                 2
             }
-            0xE0 => {
-                self.reg.b = self.reg.b | (1 << 4);
-                2
+            Instr::LdImmSp => {
+                let nn = self.read_word();
+                self.write(nn, self.reg.sp as u8);
+                self.write(nn.wrapping_add(1), (self.reg.sp >> 8) as u8);
+                5
             }
-            0xE1 => {
-                self.reg.c = self.reg.c | (1 << 4);
-                2
+            Instr::LdhImmA => {
+                let addr = 0xFF00 | self.read_byte() as u16;
+                self.write(addr, self.reg.a);
+                3
             }
-            0xE2 => {
-                self.reg.d = self.reg.d | (1 << 4);
-                2
+            Instr::LdhAImm => {
+                let addr = 0xFF00 | self.read_byte() as u16;
+                self.reg.a = self.read(addr);
+                3
             }
-            0xE3 => {
-                self.reg.e = self.reg.e | (1 << 4);
+            Instr::LdhCA => {
+                self.write(0xFF00 | self.reg.c as u16, self.reg.a);
                 2
             }
-            0xE4 => {
-                self.reg.h = self.reg.h | (1 << 4);
+            Instr::LdhAC => {
+                self.reg.a = self.read(0xFF00 | self.reg.c as u16);
                 2
             }
-            0xE5 => {
-                self.reg.l = self.reg.l | (1 << 4);
-                2
+            Instr::LdImmA => {
+                let nn = self.read_word();
+                self.write(nn, self.reg.a);
+                4
             }
-            0xE6 => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 4);
-                self.membus.write(self.reg.hl(), res);
+            Instr::LdAImm => {
+                let nn = self.read_word();
+                self.reg.a = self.read(nn);
                 4
             }
-            0xE7 => {
-                self.reg.a = self.reg.a | (1 << 4);
-                2
+            Instr::LdHlSp => {
+                let res = self.alu_add16imm(self.reg.sp);
+                self.reg.set_hl(res);
+                3
             }
-            0xE8 => {
-                self.reg.b = self.reg.b | (1 << 5);
+            Instr::LdSpHl => {
+                self.reg.sp = self.reg.hl();
                 2
             }
-            0xE9 => {
-                self.reg.c = self.reg.c | (1 << 5);
-                2
+            Instr::IncR8(r) => {
+                let val = self.r8(r);
+                let res = self.alu_inc(val);
+                self.set_r8(r, res);
+                1 + mem(r) * 2
             }
-            0xEA => {
-                self.reg.d = self.reg.d | (1 << 5);
-                2
+            Instr::DecR8(r) => {
+                let val = self.r8(r);
+                let res = self.alu_dec(val);
+                self.set_r8(r, res);
+                1 + mem(r) * 2
             }
-            0xEB => {
-                self.reg.e = self.reg.e | (1 << 5);
+            Instr::IncR16(rr) => {
+                self.set_r16(rr, self.r16(rr).wrapping_add(1));
                 2
             }
-            0xEC => {
-                self.reg.h = self.reg.h | (1 << 5);
+            Instr::DecR16(rr) => {
+                self.set_r16(rr, self.r16(rr).wrapping_sub(1));
                 2
             }
-            0xED => {
-                self.reg.l = self.reg.l | (1 << 5);
+            Instr::AddHl(rr) => {
+                self.alu_add16(self.r16(rr));
                 2
             }
-            0xEE => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 5);
-                self.membus.write(self.reg.hl(), res);
+            Instr::AddSp => {
+                self.reg.sp = self.alu_add16imm(self.reg.sp);
                 4
             }
-            0xEF => {
-                self.reg.a = self.reg.a | (1 << 5);
-                2
-            }
-            0xF0 => {
-                self.reg.b = self.reg.b | (1 << 6);
-                2
+            Instr::Alu(op, r) => {
+                let val = self.r8(r);
+                self.alu(op, val);
+                1 + mem(r)
             }
-            0xF1 => {
-                self.reg.c = self.reg.c | (1 << 6);
-                2
+            Instr::AluImm(op) => {
+                let n = self.read_byte();
+                self.alu(op, n);
+                2
+            }
+            instr @ (Instr::Rlca | Instr::Rrca | Instr::Rla | Instr::Rra) => {
+                let op = match instr {
+                    Instr::Rlca => Rot::Rlc,
+                    Instr::Rrca => Rot::Rrc,
+                    Instr::Rla => Rot::Rl,
+                    _ => Rot::Rr,
+                };
+                // Same as the CB rotates on A, except Z is always cleared
+                self.reg.a = self.rot(op, self.reg.a);
+                self.reg.set_flag(Z, false);
+                1
             }
-            0xF2 => {
-                self.reg.d = self.reg.d | (1 << 6);
-                2
+            Instr::Daa => {
+                self.alu_daa();
+                1
             }
-            0xF3 => {
-                self.reg.e = self.reg.e | (1 << 6);
-                2
+            Instr::Cpl => {
+                self.reg.a = !self.reg.a;
+                self.reg.set_flag(N, true);
+                self.reg.set_flag(H, true);
+                1
             }
-            0xF4 => {
-                self.reg.h = self.reg.h | (1 << 6);
-                2
+            Instr::Scf => {
+                self.reg.set_flag(N, false);
+                self.reg.set_flag(H, false);
+                self.reg.set_flag(C, true);
+                1
             }
-            0xF5 => {
-                self.reg.l = self.reg.l | (1 << 6);
-                2
+            Instr::Ccf => {
+                self.reg.set_flag(N, false);
+                self.reg.set_flag(H, false);
+                self.reg.set_flag(C, !self.reg.flag(C));
+                1
             }
-            0xF6 => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 6);
-                self.membus.write(self.reg.hl(), res);
-                4
+            Instr::Jr(cc) => {
+                let e = self.read_byte() as i8;
+                if self.cond(cc) {
+                    self.reg.pc = self.reg.pc.wrapping_add(e as u16);
+                    3
+                } else {
+                    2
+                }
             }
-            0xF7 => {
-                self.reg.a = self.reg.a | (1 << 6);
-                2
+            Instr::Jp(cc) => {
+                let nn = self.read_word();
+                if self.cond(cc) {
+                    self.reg.pc = nn;
+                    4
+                } else {
+                    3
+                }
             }
-            0xF8 => {
-                self.reg.b = self.reg.b | (1 << 7);
-                2
+            Instr::JpHl => {
+                self.reg.pc = self.reg.hl();
+                1
             }
-            0xF9 => {
-                self.reg.c = self.reg.c | (1 << 7);
-                2
+            Instr::Call(cc) => {
+                let nn = self.read_word();
+                if self.cond(cc) {
+                    self.push_stack(self.reg.pc);
+                    self.reg.pc = nn;
+                    self.profile_call();
+                    6
+                } else {
+                    3
+                }
             }
-            0xFA => {
-                self.reg.d = self.reg.d | (1 << 7);
-                2
+            Instr::Ret(None) => {
+                self.reg.pc = self.pop_stack();
+                self.profile_ret();
+                4
             }
-            0xFB => {
-                self.reg.e = self.reg.e | (1 << 7);
-                2
+            Instr::Ret(cc) => {
+                // The condition is checked in a cycle of its own
+                self.tick();
+                if self.cond(cc) {
+                    self.reg.pc = self.pop_stack();
+                    self.profile_ret();
+                    5
+                } else {
+                    2
+                }
             }
-            0xFC => {
-                self.reg.h = self.reg.h | (1 << 7);
-                2
+            Instr::Reti => {
+                // Enabled right away, and it stays enabled
+                self.reg.pc = self.pop_stack();
+                self.profile_ret();
+                self.ime = true;
+                self.ime_next = true;
+                4
             }
-            0xFD => {
-                self.reg.l = self.reg.l | (1 << 7);
-                2
+            Instr::Rst(addr) => {
+                self.push_stack(self.reg.pc);
+                self.reg.pc = addr as u16;
+                self.profile_call();
+                4
             }
-            0xFE => {
-                let res = self.membus.read(self.reg.hl()) | (1 << 7);
-                self.membus.write(self.reg.hl(), res);
+            Instr::Push(rr) => {
+                self.push_stack(self.r16(rr));
                 4
             }
-            0xFF => {
-                self.reg.a = self.reg.a | (1 << 7);
-                2
+            Instr::Pop(rr) => {
+                let val = self.pop_stack();
+                self.set_r16(rr, val);
+                3
             }
+            Instr::Illegal(_) => {
+                let pc = self.reg.pc.wrapping_sub(1);
+                eprintln!(
+                    "CPU locked up on illegal opcode 0x{:02X} at {:04X}",
+                    opcode, pc
+                );
+                self.locked = Some(pc);
+                1
+            }
+            // CB instructions only come out of decode_cb
+            Instr::Rot(..) | Instr::Bit(..) | Instr::Res(..) | Instr::Set(..) => unreachable!(),
+        }
+    }
+
+    // Execute opcode after the 0xCB prefix, the cycles include the prefix
+    fn exec_cb(&mut self, opcode: u8) -> u32 {
+        let (r, val) = match decode_cb(opcode) {
+            Instr::Rot(op, r) => {
+                let val = self.r8(r);
+                (r, self.rot(op, val))
+            }
+            Instr::Bit(bit, r) => {
+                let val = self.r8(r);
+                self.alu_bit(bit, val);
+                return if r == R8::HlInd { 3 } else { 2 };
+            }
+            Instr::Res(bit, r) => {
+                let val = self.r8(r);
+                (r, val & !(1 << bit))
+            }
+            Instr::Set(bit, r) => {
+                let val = self.r8(r);
+                (r, val | (1 << bit))
+            }
+            _ => unreachable!(),
+        };
+        self.set_r8(r, val);
+        if r == R8::HlInd {
+            4
+        } else {
+            2
+        }
+    }
+
+    // Operands
+    fn r8(&mut self, r: R8) -> u8 {
+        match r {
+            R8::B => self.reg.b,
+            R8::C => self.reg.c,
+            R8::D => self.reg.d,
+            R8::E => self.reg.e,
+            R8::H => self.reg.h,
+            R8::L => self.reg.l,
+            R8::HlInd => self.read(self.reg.hl()),
+            R8::A => self.reg.a,
+        }
+    }
+
+    fn set_r8(&mut self, r: R8, val: u8) {
+        match r {
+            R8::B => self.reg.b = val,
+            R8::C => self.reg.c = val,
+            R8::D => self.reg.d = val,
+            R8::E => self.reg.e = val,
+            R8::H => self.reg.h = val,
+            R8::L => self.reg.l = val,
+            R8::HlInd => self.write(self.reg.hl(), val),
+            R8::A => self.reg.a = val,
+        }
+    }
+
+    fn r16(&self, rr: R16) -> u16 {
+        match rr {
+            R16::BC => self.reg.bc(),
+            R16::DE => self.reg.de(),
+            R16::HL | R16::HLInc | R16::HLDec => self.reg.hl(),
+            R16::SP => self.reg.sp,
+            R16::AF => self.reg.af(),
+        }
+    }
+
+    fn set_r16(&mut self, rr: R16, val: u16) {
+        match rr {
+            R16::BC => self.reg.set_bc(val),
+            R16::DE => self.reg.set_de(val),
+            R16::HL | R16::HLInc | R16::HLDec => self.reg.set_hl(val),
+            R16::SP => self.reg.sp = val,
+            R16::AF => self.reg.set_af(val),
+        }
+    }
+
+    /// Address in a register pair, (HL+) and (HL-) move HL after
+    fn ind_addr(&mut self, rr: R16) -> u16 {
+        let addr = self.r16(rr);
+        match rr {
+            R16::HLInc => self.reg.set_hl(addr.wrapping_add(1)),
+            R16::HLDec => self.reg.set_hl(addr.wrapping_sub(1)),
+            _ => {}
+        }
+        addr
+    }
+
+    fn cond(&self, cc: Option<Cond>) -> bool {
+        match cc {
+            None => true,
+            Some(Cond::NZ) => !self.reg.flag(Z),
+            Some(Cond::Z) => self.reg.flag(Z),
+            Some(Cond::NC) => !self.reg.flag(C),
+            Some(Cond::C) => self.reg.flag(C),
         }
     }
 
     // Read/write ops
+    /// One M-cycle of the rest of the system
+    fn tick(&mut self) {
+        self.membus.tick();
+        self.ticks += 1;
+    }
+
+    /// Memory accesses take an M-cycle each
+    fn read(&mut self, addr: u16) -> u8 {
+        let byte = self.membus.read(addr);
+        self.tick();
+        byte
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.membus.write(addr, val);
+        self.tick();
+    }
+
     /// Read byte at the PC
     fn read_byte(&mut self) -> u8 {
-        let byte = self.membus.read(self.reg.pc);
+        let byte = self.read(self.reg.pc);
         self.reg.pc = self.reg.pc.wrapping_add(1);
         byte
     }
@@ -2251,22 +548,86 @@ impl Cpu {
         ((msb as u16) << 8) | (lsb as u16)
     }
 
+    /// Tell the profiler about a call that just jumped to PC
+    fn profile_call(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            let pc = self.reg.pc;
+            profiler.call(self.membus.bank(pc), pc, self.reg.sp);
+        }
+    }
+
+    fn profile_ret(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.ret(self.reg.sp);
+        }
+    }
+
+    /// Pushes start with an internal cycle decrementing SP
     fn push_stack(&mut self, value: u16) {
+        self.tick();
         self.reg.sp = self.reg.sp.wrapping_sub(1);
-        self.membus.write(self.reg.sp, (value >> 8) as u8);
+        self.write(self.reg.sp, (value >> 8) as u8);
         self.reg.sp = self.reg.sp.wrapping_sub(1);
-        self.membus.write(self.reg.sp, value as u8);
+        self.write(self.reg.sp, value as u8);
     }
 
     fn pop_stack(&mut self) -> u16 {
-        let lsb = self.membus.read(self.reg.sp);
+        let lsb = self.read(self.reg.sp);
         self.reg.sp = self.reg.sp.wrapping_add(1);
-        let msb = self.membus.read(self.reg.sp);
+        let msb = self.read(self.reg.sp);
         self.reg.sp = self.reg.sp.wrapping_add(1);
         ((msb as u16) << 8) | (lsb as u16)
     }
 
     // 8-bit ALU ops
+    fn alu(&mut self, op: Alu, b: u8) {
+        match op {
+            Alu::Add => self.alu_add(b),
+            Alu::Adc => self.alu_adc(b),
+            Alu::Sub => self.alu_sub(b),
+            Alu::Sbc => self.alu_sbc(b),
+            Alu::And => self.alu_and(b),
+            Alu::Xor => self.alu_xor(b),
+            Alu::Or => self.alu_or(b),
+            Alu::Cp => self.alu_cp(b),
+        }
+    }
+
+    fn rot(&mut self, op: Rot, b: u8) -> u8 {
+        match op {
+            Rot::Rlc => self.alu_rlc(b),
+            Rot::Rrc => self.alu_rrc(b),
+            Rot::Rl => self.alu_rl(b),
+            Rot::Rr => self.alu_rr(b),
+            Rot::Sla => self.alu_sla(b),
+            Rot::Sra => self.alu_sra(b),
+            Rot::Swap => self.alu_swap(b),
+            Rot::Srl => self.alu_srl(b),
+        }
+    }
+
+    // DAA instruction credit: https://forums.nesdev.org/viewtopic.php?t=15944
+    fn alu_daa(&mut self) {
+        if !self.reg.flag(N) {
+            if self.reg.flag(C) || self.reg.a > 0x99 {
+                self.reg.a = self.reg.a.wrapping_add(0x60);
+                self.reg.set_flag(C, true);
+            }
+            if self.reg.flag(H) || (self.reg.a & 0x0f) > 0x09 {
+                self.reg.a = self.reg.a.wrapping_add(0x6);
+            }
+        } else {
+            if self.reg.flag(C) {
+                self.reg.a = self.reg.a.wrapping_sub(0x60);
+            }
+            if self.reg.flag(H) {
+                self.reg.a = self.reg.a.wrapping_sub(0x6);
+            }
+        }
+        self.reg.set_flag(Z, self.reg.a == 0);
+        self.reg.set_flag(H, false);
+    }
+
     fn alu_add(&mut self, b: u8) {
         let a = self.reg.a;
         let res = a.wrapping_add(b);
@@ -2462,9 +823,19 @@ impl Cpu {
     }
 }
 
+#[cfg(test)]
+mod reference;
+#[cfg(test)]
+mod single_step;
+#[cfg(test)]
+mod test_bus;
+
 #[cfg(test)]
 mod tests {
+    use super::test_bus::FlatRam;
     use super::*;
+    use crate::register::Reg;
+    use proptest::prelude::*;
 
     #[test]
     fn halt_bug() {
@@ -2476,4 +847,262 @@ mod tests {
             cpu.cycle();
         }
     }
+
+    /// Program in WRAM with the CPU about to run it
+    fn run_from_wram(program: &[u8]) -> Cpu {
+        let mut mem = Mmu::new();
+        for (i, &byte) in program.iter().enumerate() {
+            mem.write(0xC000 + i as u16, byte);
+        }
+        let mut cpu = Cpu::from(mem);
+        cpu.reg.pc = 0xC000;
+        cpu
+    }
+
+    #[test]
+    fn access_timing() {
+        // INC (HL) on TIMA: read, TIMA ticks, then the write wins
+        let mut cpu = run_from_wram(&[0x34]);
+        cpu.reg.set_hl(0xFF05);
+        let mem = &mut cpu.membus;
+        mem.write(0xFF07, 0b101); // TIMA every 4 M-cycles
+        mem.write(0xFF04, 0);
+        mem.write(0xFF05, 0);
+        mem.do_cycles(2);
+        cpu.cycle();
+        assert_eq!(cpu.membus.read(0xFF05), 1);
+
+        // Same thing with a read after the increment
+        let mut cpu = run_from_wram(&[0x7E]);
+        cpu.reg.set_hl(0xFF05);
+        let mem = &mut cpu.membus;
+        mem.write(0xFF07, 0b101);
+        mem.write(0xFF04, 0);
+        mem.write(0xFF05, 0);
+        mem.do_cycles(3);
+        cpu.cycle();
+        assert_eq!(cpu.reg.a, 1);
+    }
+
+    #[test]
+    fn illegal_opcode() {
+        let mut cpu = run_from_wram(&[0x00, 0xDD, 0x00]);
+        cpu.ime = true;
+        cpu.cycle();
+        assert_eq!(cpu.locked(), None);
+        cpu.cycle();
+        assert_eq!(cpu.locked(), Some(0xC001));
+
+        // Interrupts don't wake it up, the PPU keeps going
+        cpu.membus.write(0xFF0F, 0x01);
+        let ly = cpu.membus.read(0xFF44);
+        for _ in 0..200 {
+            cpu.cycle();
+        }
+        assert_eq!(cpu.reg.pc, 0xC002);
+        assert_ne!(cpu.membus.read(0xFF44), ly);
+    }
+
+    #[test]
+    fn instruction_lengths() {
+        // The disassembler and execution agree on where the next instruction is,
+        // for everything that doesn't jump
+        for opcode in 0..=0xFF {
+            let instr = decode(opcode);
+            if matches!(
+                instr,
+                Instr::Jr(_)
+                    | Instr::Jp(_)
+                    | Instr::JpHl
+                    | Instr::Call(_)
+                    | Instr::Ret(_)
+                    | Instr::Reti
+                    | Instr::Rst(_)
+                    | Instr::Illegal(_)
+            ) {
+                continue;
+            }
+            let mut cpu = Cpu::with_reg(FlatRam::filled(&[opcode, 0x00, 0x00]), Reg::new());
+            cpu.reg.pc = 0;
+            let (_, len) = disassemble(|addr| cpu.membus.ram[addr as usize], 0);
+            let op = cpu.read_byte();
+            cpu.exec(op);
+            assert_eq!(cpu.reg.pc, len, "opcode {:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn halt_interrupt() {
+        // EI, HALT at 0x100, the VBlank handler at 0x40 is a NOP
+        let mut mem = FlatRam::new();
+        mem.ram[0x100..0x102].copy_from_slice(&[0xFB, 0x76]);
+        mem.ram[0xFFFF] = 0x01;
+        let mut cpu = Cpu::with_reg(mem, Reg::new());
+        for _ in 0..10 {
+            cpu.cycle();
+        }
+        assert!(cpu.halted);
+
+        // Waking up doesn't use up the request before it's taken
+        cpu.membus.ram[0xFF0F] = 0x01;
+        cpu.cycle();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.reg.pc, 0x41);
+        assert_eq!(cpu.membus.ram[0xFF0F], 0x00);
+        assert_eq!(cpu.pop_stack(), 0x102);
+    }
+
+    #[test]
+    fn profile_calls() {
+        // CALL 0x200 and HALT, RST 0x38 and RET there, RET at 0x38
+        let mut mem = FlatRam::new();
+        mem.ram[0x100..0x104].copy_from_slice(&[0xCD, 0x00, 0x02, 0x76]);
+        mem.ram[0x200..0x202].copy_from_slice(&[0xFF, 0xC9]);
+        mem.ram[0x38] = 0xC9;
+        let mut cpu = Cpu::with_reg(mem, Reg::new());
+        cpu.profiler = Some(Profiler::new("T", Default::default()));
+        for _ in 0..8 {
+            cpu.cycle();
+        }
+        assert!(cpu.halted);
+        assert_eq!(
+            cpu.profiler.as_ref().unwrap().folded(),
+            "T 7\nT;00:0200 8\nT;00:0200;00:0038 4\nT;HALT 3\n"
+        );
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        // Any program runs without panicking or an instruction overrunning its cycles
+        #[test]
+        fn random_programs(
+            program in proptest::collection::vec(any::<u8>(), 1..256),
+            regs in any::<[u8; 8]>(),
+        ) {
+            let mut cpu = Cpu::with_reg(FlatRam::filled(&program), Reg::new());
+            cpu.reg.a = regs[0];
+            cpu.reg.f = regs[1] & 0xF0;
+            cpu.reg.set_bc(u16::from_be_bytes([regs[2], regs[3]]));
+            cpu.reg.set_de(u16::from_be_bytes([regs[4], regs[5]]));
+            cpu.reg.set_hl(u16::from_be_bytes([regs[6], regs[7]]));
+            for _ in 0..1000 {
+                let before = cpu.membus.cycles.len();
+                cpu.cycle();
+                // 6 for the longest instruction, 5 to take an interrupt first
+                let m_cycles = cpu.membus.cycles.len() - before;
+                prop_assert!((1..=11).contains(&m_cycles), "{} M-cycles", m_cycles);
+            }
+        }
+    }
+
+    // Runs an opcode with the decoder and with the old match on the same state
+    fn compare_opcode(opcode: u16, regs: [u8; 8], sp: u16, bytes: [u8; 3], mem: [u8; 4]) {
+        let mut cpu = run_from_wram(&[]);
+        let (a, f, b, c, d, e, h, l) = (
+            regs[0], regs[1], regs[2], regs[3], regs[4], regs[5], regs[6], regs[7],
+        );
+        cpu.reg = Reg {
+            a,
+            f: f & 0xF0,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            pc: 0xC000,
+            sp,
+        };
+        // Keep the accessed memory out of the program
+        let program = if opcode > 0xFF {
+            vec![0xCB, opcode as u8, bytes[0]]
+        } else {
+            vec![opcode as u8, bytes[0], bytes[1], bytes[2]]
+        };
+        for addr in [
+            cpu.reg.bc(),
+            cpu.reg.de(),
+            cpu.reg.hl(),
+            sp,
+            sp.wrapping_sub(2),
+        ] {
+            for (i, &byte) in mem.iter().enumerate() {
+                cpu.membus.write(addr.wrapping_add(i as u16), byte);
+            }
+        }
+        for (i, &byte) in program.iter().enumerate() {
+            cpu.membus.write(0xC000 + i as u16, byte);
+        }
+        let mut reference = cpu.clone();
+
+        let op = cpu.read_byte();
+        let cycles = cpu.exec(op);
+        let op = reference.read_byte();
+        let reference_cycles = reference.exec_reference(op);
+
+        let state = |cpu: &Cpu| {
+            let reg = &cpu.reg;
+            (
+                reg.af(),
+                reg.bc(),
+                reg.de(),
+                reg.hl(),
+                reg.pc,
+                reg.sp,
+                cpu.ime,
+                cpu.ime_next,
+                cpu.halted,
+            )
+        };
+        assert_eq!(state(&cpu), state(&reference), "opcode {:03X}", opcode);
+        assert_eq!(cycles, reference_cycles, "opcode {:03X}", opcode);
+        for addr in [
+            cpu.reg.bc(),
+            cpu.reg.de(),
+            cpu.reg.hl(),
+            sp,
+            sp.wrapping_sub(2),
+        ] {
+            for addr in addr.wrapping_sub(2)..addr.saturating_add(2) {
+                assert_eq!(
+                    cpu.membus.read(addr),
+                    reference.membus.read(addr),
+                    "opcode {:03X} at {:04X}",
+                    opcode,
+                    addr
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Every opcode, 0x100 and up are the CB ones
+        #[test]
+        fn decoder_matches_reference(
+            regs in any::<[u8; 8]>(),
+            sp in 0xC100..0xDFF0u16,
+            indirect in 0xC100..0xDFF0u16,
+            bytes in any::<[u8; 3]>(),
+            mem in any::<[u8; 4]>(),
+            pairs in any::<[bool; 3]>(),
+        ) {
+            let mut regs = regs;
+            // Point some of the pairs into WRAM, the rest can go anywhere
+            for (i, &in_wram) in pairs.iter().enumerate() {
+                if in_wram {
+                    regs[2 + i * 2] = (indirect >> 8) as u8;
+                    regs[3 + i * 2] = indirect as u8;
+                }
+            }
+            for opcode in 0..0x200 {
+                if opcode < 0x100 && matches!(decode(opcode as u8), Instr::Illegal(_)) {
+                    continue;
+                }
+                compare_opcode(opcode, regs, sp, bytes, mem);
+            }
+        }
+    }
 }
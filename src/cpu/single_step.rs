@@ -0,0 +1,203 @@
+// Per-instruction tests from https://github.com/SingleStepTests/sm83, one JSON file
+// per opcode ("00.json", "cb 00.json", ...) in $SM83_TESTS or ../testroms/sm83/v1.
+//
+// Each test has the state before and after one instruction and what was on the bus
+// in every M-cycle. The SM83 fetches the next opcode in the last cycle of an
+// instruction, so the tests start with PC past the opcode and end with the next
+// one fetched.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::test_bus::{Access, FlatRam};
+use super::Cpu;
+use crate::memory::Bus;
+use crate::register::Reg;
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    after: State,
+    cycles: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    pc: u16,
+    sp: u16,
+    ime: u8,
+    ie: Option<u8>,
+    ei: Option<u8>, // EI was just run, IME is set after the next instruction
+    ram: Vec<(u16, u8)>,
+}
+
+/// Cycle entries are [address, data, pins] where pins are like "r-m" or "-wm"
+fn expected_access(cycle: &Value) -> Option<Access> {
+    let addr = cycle.get(0)?.as_u64()? as u16;
+    let val = cycle.get(1)?.as_u64()? as u8;
+    let pins = cycle.get(2)?.as_str()?;
+    match pins.as_bytes() {
+        [b'r', ..] => Some(Access {
+            addr,
+            val,
+            write: false,
+        }),
+        [_, b'w', ..] => Some(Access {
+            addr,
+            val,
+            write: true,
+        }),
+        _ => None,
+    }
+}
+
+fn run(test: &Test) -> Result<(), String> {
+    let initial = &test.initial;
+    let mut mem = FlatRam::new();
+    for &(addr, val) in &initial.ram {
+        mem.ram[addr as usize] = val;
+    }
+    if let Some(ie) = initial.ie {
+        mem.ram[0xFFFF] = ie;
+    }
+    let reg = Reg {
+        a: initial.a,
+        f: initial.f,
+        b: initial.b,
+        c: initial.c,
+        d: initial.d,
+        e: initial.e,
+        h: initial.h,
+        l: initial.l,
+        // Back to the opcode, it's fetched again below
+        pc: initial.pc.wrapping_sub(1),
+        sp: initial.sp,
+    };
+    let mut cpu = Cpu::with_reg(mem, reg);
+    cpu.ime = initial.ime == 1;
+    cpu.ime_next = cpu.ime;
+
+    let opcode = cpu.read_byte();
+    let m_cycles = cpu.exec(opcode);
+    for _ in cpu.ticks..m_cycles {
+        cpu.membus.tick();
+    }
+    // The fetch the instruction overlaps with
+    cpu.read_byte();
+
+    let after = &test.after;
+    let reg = &cpu.reg;
+    let got = [reg.a, reg.f, reg.b, reg.c, reg.d, reg.e, reg.h, reg.l];
+    let expected = [
+        after.a, after.f, after.b, after.c, after.d, after.e, after.h, after.l,
+    ];
+    if got != expected || (reg.pc, reg.sp) != (after.pc, after.sp) {
+        return Err(format!(
+            "registers AFBCDEHL {:02X?} PC {:04X} SP {:04X}, expected {:02X?} PC {:04X} SP {:04X}",
+            got, reg.pc, reg.sp, expected, after.pc, after.sp
+        ));
+    }
+    let ime = after.ime == 1 || after.ei == Some(1);
+    if cpu.ime_next != ime {
+        return Err(format!("IME {}, expected {}", cpu.ime_next, ime));
+    }
+    for &(addr, val) in &after.ram {
+        let got = cpu.membus.ram[addr as usize];
+        if got != val {
+            return Err(format!("{:04X} = {:02X}, expected {:02X}", addr, got, val));
+        }
+    }
+
+    // Our log starts with the opcode fetch and ends with the next one
+    let cycles = &cpu.membus.cycles[1..];
+    if cycles.len() != test.cycles.len() {
+        return Err(format!(
+            "{} M-cycles, expected {}",
+            cycles.len(),
+            test.cycles.len()
+        ));
+    }
+    for (i, (got, cycle)) in cycles.iter().zip(&test.cycles).enumerate() {
+        let expected = expected_access(cycle);
+        if *got != expected {
+            return Err(format!("cycle {}: {:?}, expected {:?}", i, got, expected));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs the SM83 test vectors, run with --ignored"]
+fn sm83_test_vectors() {
+    let dir = env::var_os("SM83_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("../testroms/sm83/v1"));
+    let entries = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("SM83 test vectors in {}: {}", dir.display(), e));
+
+    let mut files: Vec<PathBuf> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    let mut failures = Vec::new();
+    for path in files {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        // STOP and HALT stop fetching, there's no next instruction to compare
+        if name == "10" || name == "76" {
+            continue;
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        let tests: Vec<Test> =
+            serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        // The first failure of each opcode is enough
+        if let Some((test, e)) = tests
+            .iter()
+            .find_map(|test| run(test).err().map(|e| (test, e)))
+        {
+            failures.push(format!("{}: {}", test.name, e));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn harness() {
+    // INC (HL) in the same format, with the opcode at PC - 1
+    let test: Test = serde_json::from_str(
+        r#"{
+            "name": "34 0000",
+            "initial": {
+                "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 0, "h": 192, "l": 16,
+                "pc": 257, "sp": 65534, "ime": 0, "ie": 0,
+                "ram": [[256, 52], [257, 0], [49168, 15]]
+            },
+            "final": {
+                "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 32, "h": 192, "l": 16,
+                "pc": 258, "sp": 65534, "ime": 0, "ie": 0,
+                "ram": [[256, 52], [257, 0], [49168, 16]]
+            },
+            "cycles": [[49168, 15, "r-m"], [49168, 16, "-wm"], [257, 0, "r-m"]]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(run(&test), Ok(()));
+
+    let mut wrong = test;
+    wrong.after.ram[2].1 = 17;
+    assert!(run(&wrong).is_err());
+}
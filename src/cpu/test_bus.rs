@@ -0,0 +1,72 @@
+// Buses for running the CPU without the rest of the Game Boy
+
+use std::cell::Cell;
+
+use crate::memory::Bus;
+
+/// 64K of RAM with IF at 0xFF0F and IE at 0xFFFF, logs what happened in each M-cycle
+pub struct FlatRam {
+    pub ram: Vec<u8>,
+    access: Cell<Option<Access>>, // in the current cycle
+    pub cycles: Vec<Option<Access>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Access {
+    pub addr: u16,
+    pub val: u8,
+    pub write: bool,
+}
+
+impl FlatRam {
+    pub fn new() -> Self {
+        FlatRam {
+            ram: vec![0; 0x10000],
+            access: Cell::new(None),
+            cycles: Vec::new(),
+        }
+    }
+
+    /// RAM filled with `bytes` from 0 and repeated to the end
+    pub fn filled(bytes: &[u8]) -> Self {
+        let mut mem = FlatRam::new();
+        for (byte, val) in mem.ram.iter_mut().zip(bytes.iter().cycle()) {
+            *byte = *val;
+        }
+        mem
+    }
+}
+
+impl Bus for FlatRam {
+    fn read(&self, addr: u16) -> u8 {
+        let val = self.ram[addr as usize];
+        self.access.set(Some(Access {
+            addr,
+            val,
+            write: false,
+        }));
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram[addr as usize] = val;
+        self.access.set(Some(Access {
+            addr,
+            val,
+            write: true,
+        }));
+    }
+
+    fn tick(&mut self) {
+        self.cycles.push(self.access.take());
+    }
+
+    fn pending_interrupt(&self) -> Option<u8> {
+        let pending = self.ram[0xFF0F] & self.ram[0xFFFF] & 0x1F;
+        (pending != 0).then(|| 0x40 + pending.trailing_zeros() as u8 * 8)
+    }
+
+    fn ack_interrupt(&mut self, addr: u8) {
+        self.ram[0xFF0F] &= !(1 << ((addr - 0x40) / 8));
+    }
+}
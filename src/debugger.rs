@@ -0,0 +1,409 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::cpu::Cpu;
+use crate::decode::disassemble;
+use crate::line_log;
+use crate::ram_search::{Filter, RamSearch, ValueType};
+use crate::vram_viewer;
+
+const HELP: &str = "\
+continue, c              back to the game
+step, s [N]              run N instructions, up to a million
+regs, r                  registers and the next instruction
+mem, m ADDR [LEN]        show LEN bytes from ADDR
+poke, w ADDR BYTE...     write bytes from ADDR
+search new [TYPE]        snapshot RAM, TYPE is 8 (default), 16, bcd8 or bcd16
+search eq|ne|gt|lt [N]   keep values compared to N, or to the last snapshot
+search changed [N]       keep values that changed, by N when it's given
+search unchanged         keep values that stayed the same
+search                   list what's left
+oam                      list the objects in OAM
+vram DIR                 save the tiles, tile maps and OAM table to DIR
+lines [FROM [TO]]        registers and interrupts of each line in the last frame
+lines csv FILE           save them for all 154 lines to FILE
+profile                  where the game spent its time so far, with --profile
+cheats                   list the cheats
+cheat add CODE [NAME]    add a Game Genie or GameShark code
+cheat N                  turn cheat N on or off
+Addresses and bytes are hex, other numbers are decimal unless they start with $ or 0x.";
+
+/// Results of a search that get listed
+const MAX_LISTED: usize = 32;
+/// Instructions a step runs at most, the window doesn't update until it's done
+const MAX_STEPS: u32 = 1_000_000;
+
+/// Commands typed on stdin, the game is paused while the debugger is active
+pub struct Debugger {
+    lines: Receiver<String>,
+    pub active: bool,
+    search: Option<RamSearch>,
+}
+
+impl Debugger {
+    /// Read commands from stdin in the background
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Debugger::with_input(rx)
+    }
+
+    fn with_input(lines: Receiver<String>) -> Self {
+        Debugger {
+            lines,
+            active: false,
+            search: None,
+        }
+    }
+
+    /// Pause the game and show where it is
+    pub fn enter(&mut self, cpu: &Cpu) {
+        self.active = true;
+        println!("Debugger, type help for the commands");
+        println!("{}", registers(cpu));
+        prompt();
+    }
+
+    /// Run the commands typed since the last frame
+    pub fn poll(&mut self, cpu: &mut Cpu) {
+        while let Ok(line) = self.lines.try_recv() {
+            match self.command(&line, cpu) {
+                Ok(out) if out.is_empty() => {}
+                Ok(out) => println!("{}", out),
+                Err(e) => eprintln!("{}", e),
+            }
+            if self.active {
+                prompt();
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str, cpu: &mut Cpu) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => Ok(String::new()),
+            ["help" | "h"] => Ok(HELP.to_string()),
+            ["continue" | "c"] => {
+                self.active = false;
+                Ok(String::new())
+            }
+            ["step" | "s", count @ ..] => {
+                let count = match count {
+                    [] => 1,
+                    [n] => parse_number(n)?,
+                    _ => return Err("usage: step [N]".to_string()),
+                };
+                let mut ran = 0;
+                while ran < count.min(MAX_STEPS) && cpu.locked().is_none() {
+                    cpu.cycle();
+                    ran += 1;
+                }
+                if ran == count {
+                    return Ok(registers(cpu));
+                }
+                let why = match cpu.locked() {
+                    Some(pc) => format!("the CPU locked up at {:04X}", pc),
+                    None => format!("{} at most", MAX_STEPS),
+                };
+                Ok(format!(
+                    "Ran {} of {} instructions, {}\n{}",
+                    ran,
+                    count,
+                    why,
+                    registers(cpu)
+                ))
+            }
+            ["regs" | "r"] => Ok(registers(cpu)),
+            ["mem" | "m", addr, len @ ..] => {
+                let addr = parse_hex(addr)?;
+                let len = match len {
+                    [] => 0x40,
+                    [n] => parse_number(n)?,
+                    _ => return Err("usage: mem ADDR [LEN]".to_string()),
+                };
+                Ok(hex_dump(cpu, addr, len))
+            }
+            ["poke" | "w", addr, bytes @ ..] if !bytes.is_empty() => {
+                let addr = parse_hex(addr)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| bad_number(byte)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                for (i, &byte) in bytes.iter().enumerate() {
+                    cpu.membus.poke(addr.wrapping_add(i as u16), byte);
+                }
+                Ok(hex_dump(cpu, addr, bytes.len() as u32))
+            }
+            ["search", args @ ..] => self.search(args, cpu),
+            ["oam"] => Ok(vram_viewer::oam_table(&cpu.membus.gpu)),
+            ["vram", dir] => {
+                vram_viewer::dump(&cpu.membus.gpu, Path::new(dir))?;
+                Ok(format!("Dumped VRAM to {}", dir))
+            }
+            ["lines", "csv", path] => {
+                line_log::save_csv(cpu.membus.gpu.line_log(), Path::new(path))?;
+                Ok(format!("Saved the line log to {}", path))
+            }
+            ["lines", range @ ..] => {
+                let lines = cpu.membus.gpu.line_log();
+                let (first, last) = match range {
+                    [] => (0, lines.len() - 1),
+                    [from] => (parse_number(from)? as usize, lines.len() - 1),
+                    [from, to] => (parse_number(from)? as usize, parse_number(to)? as usize),
+                    _ => return Err("usage: lines [FROM [TO]]".to_string()),
+                };
+                if first > last || last >= lines.len() {
+                    return Err(format!("lines go from 0 to {}", lines.len() - 1));
+                }
+                Ok(line_log::table(lines, first, last))
+            }
+            ["profile"] => match &cpu.profiler {
+                Some(profiler) => Ok(profiler.report()),
+                None => Err("the profiler is off, start with --profile DIR".to_string()),
+            },
+            ["cheats"] => {
+                let cheats = cpu.membus.cheats.list();
+                if cheats.is_empty() {
+                    return Ok("No cheats".to_string());
+                }
+                let mut out = String::new();
+                for (i, cheat) in cheats.iter().enumerate() {
+                    let _ = write!(out, "\n{:>2}: {}", i + 1, cheat);
+                }
+                Ok(out[1..].to_string())
+            }
+            ["cheat", "add", code, name @ ..] => {
+                let cheat = cpu.membus.cheats.add(code, &name.join(" "))?;
+                Ok(format!("Added {}", cheat.code))
+            }
+            ["cheat", n] => {
+                let i = parse_number(n)?;
+                let cheat = i
+                    .checked_sub(1)
+                    .and_then(|i| cpu.membus.cheats.toggle(i as usize))
+                    .ok_or_else(|| format!("no cheat {}", i))?;
+                Ok(cheat.to_string())
+            }
+            _ => Err(format!(
+                "unknown command: {}, type help for the commands",
+                line
+            )),
+        }
+    }
+
+    fn search(&mut self, args: &[&str], cpu: &Cpu) -> Result<String, String> {
+        let mem = &cpu.membus;
+        let value = |args: &[&str]| match args {
+            [] => Ok(None),
+            [n] => parse_number(n).map(Some),
+            _ => Err("expected one number".to_string()),
+        };
+        let filter = match args {
+            [] => None,
+            ["new", value_type @ ..] => {
+                let value_type = match value_type {
+                    [] => ValueType::U8,
+                    [name] => ValueType::parse(name)
+                        .ok_or_else(|| format!("unknown value type {}", name))?,
+                    _ => return Err("usage: search new [TYPE]".to_string()),
+                };
+                self.search = Some(RamSearch::new(mem, value_type));
+                None
+            }
+            ["eq", n @ ..] => Some(Filter::Equal(value(n)?)),
+            ["ne", n @ ..] => Some(Filter::NotEqual(value(n)?)),
+            ["gt", n @ ..] => Some(Filter::Greater(value(n)?)),
+            ["lt", n @ ..] => Some(Filter::Less(value(n)?)),
+            ["changed"] => Some(Filter::Changed(None)),
+            ["changed", n] => {
+                let by = match n.strip_prefix('-') {
+                    Some(n) => -(parse_number(n)? as i64),
+                    None => parse_number(n.trim_start_matches('+'))? as i64,
+                };
+                Some(Filter::Changed(Some(by)))
+            }
+            ["unchanged"] => Some(Filter::Unchanged),
+            _ => return Err(format!("unknown search: {}", args.join(" "))),
+        };
+
+        let search = self
+            .search
+            .as_mut()
+            .ok_or("no search yet, start one with search new")?;
+        if let Some(filter) = filter {
+            search.filter(mem, filter);
+        }
+
+        let candidates = search.candidates();
+        let mut out = format!("{} found", candidates.len());
+        for &(addr, val) in candidates.iter().take(MAX_LISTED) {
+            let _ = match search.value_type() {
+                ValueType::U8 => write!(out, "\n{:04X}: {} (${:02X})", addr, val, val),
+                ValueType::U16 => write!(out, "\n{:04X}: {} (${:04X})", addr, val, val),
+                ValueType::Bcd8 | ValueType::Bcd16 => write!(out, "\n{:04X}: {}", addr, val),
+            };
+        }
+        if candidates.len() > MAX_LISTED {
+            let _ = write!(out, "\n...");
+        }
+        Ok(out)
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
+
+/// Registers, the state of the CPU and the next instruction
+fn registers(cpu: &Cpu) -> String {
+    let reg = cpu.reg();
+    let mut out = format!(
+        "AF {:04X} BC {:04X} DE {:04X} HL {:04X} SP {:04X} PC {:04X} IME {}",
+        reg.af(),
+        reg.bc(),
+        reg.de(),
+        reg.hl(),
+        reg.sp,
+        reg.pc,
+        cpu.ime() as u8
+    );
+    if cpu.halted() {
+        out.push_str(" halted");
+    }
+    if let Some(pc) = cpu.locked() {
+        let _ = write!(
+            out,
+            " locked up at {:04X} (opcode {:02X})",
+            pc,
+            cpu.membus.peek(pc)
+        );
+    }
+    let (instr, _) = disassemble(|addr| cpu.membus.peek(addr), reg.pc);
+    let _ = write!(out, "\n{:04X}: {}", reg.pc, instr);
+    out
+}
+
+/// Rows of 16 bytes
+fn hex_dump(cpu: &Cpu, addr: u16, len: u32) -> String {
+    let mut out = String::new();
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row as u16);
+        let _ = write!(out, "{:04X}:", start);
+        for i in 0..(len - row).min(16) {
+            let _ = write!(
+                out,
+                " {:02X}",
+                cpu.membus.peek(start.wrapping_add(i as u16))
+            );
+        }
+        out.push('\n');
+    }
+    out.pop();
+    out
+}
+
+fn bad_number(text: &str) -> String {
+    format!("bad number {}", text)
+}
+
+/// Hex with or without a $ or 0x in front
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| bad_number(text))
+}
+
+/// Decimal, or hex after $ or 0x
+fn parse_number(text: &str) -> Result<u32, String> {
+    let number = match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(digits) => u32::from_str_radix(digits, 16),
+        None => text.parse(),
+    };
+    number.map_err(|_| bad_number(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::memory::Mmu;
+
+    fn run(debugger: &mut Debugger, cpu: &mut Cpu, line: &str) -> String {
+        debugger
+            .command(line, cpu)
+            .unwrap_or_else(|e| panic!("{}: {}", line, e))
+    }
+
+    #[test]
+    fn memory_editor() {
+        let mut debugger = Debugger::with_input(mpsc::channel().1);
+        let mut cpu = Cpu::from(Mmu::new());
+        run(&mut debugger, &mut cpu, "poke C010 12 AB");
+        assert_eq!(cpu.membus.read(0xC011), 0xAB);
+        assert_eq!(
+            run(&mut debugger, &mut cpu, "m $C00E 20"),
+            "C00E: 00 00 12 AB 00 00 00 00 00 00 00 00 00 00 00 00\nC01E: 00 00 00 00"
+        );
+        assert!(debugger.command("poke C010 1G", &mut cpu).is_err());
+        assert!(debugger.command("jump", &mut cpu).is_err());
+        assert!(run(&mut debugger, &mut cpu, "r").contains("PC 0100"));
+
+        // Stepping stops when the CPU locks up
+        let mut rom = vec![0; 0x8000];
+        rom[0x101] = 0xD3;
+        cpu.membus.insert(Cartridge::new(rom));
+        assert!(run(&mut debugger, &mut cpu, "step 10")
+            .starts_with("Ran 2 of 10 instructions, the CPU locked up at 0101\n"));
+    }
+
+    #[test]
+    fn search() {
+        let mut debugger = Debugger::with_input(mpsc::channel().1);
+        let mut cpu = Cpu::from(Mmu::new());
+        assert!(debugger.command("search eq 3", &mut cpu).is_err());
+
+        cpu.membus.write(0xC123, 0x99);
+        cpu.membus.write(0xC124, 0x01);
+        run(&mut debugger, &mut cpu, "search new bcd16");
+        assert_eq!(
+            run(&mut debugger, &mut cpu, "search eq 199"),
+            "1 found\nC123: 199"
+        );
+        cpu.membus.write(0xC123, 0x00);
+        cpu.membus.write(0xC124, 0x02);
+        assert_eq!(
+            run(&mut debugger, &mut cpu, "search changed +1"),
+            "1 found\nC123: 200"
+        );
+        assert_eq!(run(&mut debugger, &mut cpu, "search changed -1"), "0 found");
+
+        run(&mut debugger, &mut cpu, "search new 16");
+        assert!(run(&mut debugger, &mut cpu, "search").ends_with("\n..."));
+        assert!(debugger.command("search new 32", &mut cpu).is_err());
+    }
+
+    #[test]
+    fn lines() {
+        let mut debugger = Debugger::with_input(mpsc::channel().1);
+        let mut cpu = Cpu::from(Mmu::new());
+        assert_eq!(run(&mut debugger, &mut cpu, "lines").lines().count(), 155);
+        assert_eq!(run(&mut debugger, &mut cpu, "lines 150").lines().count(), 5);
+        assert!(run(&mut debugger, &mut cpu, "lines 3 4")
+            .ends_with("\n  4   00   00  00  00  00  00  00   00   00 "));
+        assert!(debugger.command("lines 5 154", &mut cpu).is_err());
+        assert!(debugger.command("lines 5 4", &mut cpu).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+
+// Opcodes are split into fields that pick the instruction and its operands:
+//
+//   x = bits 7-6, y = bits 5-3, z = bits 2-0, p = bits 5-4, q = bit 3
+//
+// https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+
+/// 8-bit operands, (HL) is the byte HL points at
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    HlInd,
+    A,
+}
+
+/// 16-bit operands, HLInc and HLDec are (HL+) and (HL-)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum R16 {
+    BC,
+    DE,
+    HL,
+    SP,
+    AF,
+    HLInc,
+    HLDec,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cond {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Alu {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/// CB prefixed shifts and rotates
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rot {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+const R: [R8; 8] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::HlInd, R8::A];
+const RP: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::SP];
+const RP2: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::AF];
+const RPM: [R16; 4] = [R16::BC, R16::DE, R16::HLInc, R16::HLDec];
+const CC: [Cond; 4] = [Cond::NZ, Cond::Z, Cond::NC, Cond::C];
+const ALU: [Alu; 8] = [
+    Alu::Add,
+    Alu::Adc,
+    Alu::Sub,
+    Alu::Sbc,
+    Alu::And,
+    Alu::Xor,
+    Alu::Or,
+    Alu::Cp,
+];
+const ROT: [Rot; 8] = [
+    Rot::Rlc,
+    Rot::Rrc,
+    Rot::Rl,
+    Rot::Rr,
+    Rot::Sla,
+    Rot::Sra,
+    Rot::Swap,
+    Rot::Srl,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instr {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Prefix,
+    LdR8(R8, R8),
+    LdR8Imm(R8),
+    LdR16Imm(R16),
+    LdIndA(R16), // LD (BC/DE/HL+/HL-),A
+    LdAInd(R16),
+    LdImmSp, // LD (nn),SP
+    LdhImmA, // LDH (n),A
+    LdhAImm,
+    LdhCA, // LDH (C),A
+    LdhAC,
+    LdImmA, // LD (nn),A
+    LdAImm,
+    LdHlSp, // LD HL,SP+e
+    LdSpHl,
+    IncR8(R8),
+    DecR8(R8),
+    IncR16(R16),
+    DecR16(R16),
+    AddHl(R16),
+    AddSp,
+    Alu(Alu, R8),
+    AluImm(Alu),
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jr(Option<Cond>),
+    Jp(Option<Cond>),
+    JpHl,
+    Call(Option<Cond>),
+    Ret(Option<Cond>),
+    Reti,
+    Rst(u8),
+    Push(R16),
+    Pop(R16),
+    Rot(Rot, R8),
+    Bit(u8, R8),
+    Res(u8, R8),
+    Set(u8, R8),
+    Illegal(u8),
+}
+
+pub fn decode(opcode: u8) -> Instr {
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 7;
+    let z = opcode & 7;
+    let p = (y >> 1) as usize;
+    let q = y & 1;
+    let (y, z) = (y as usize, z as usize);
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => Instr::Nop,
+            1 => Instr::LdImmSp,
+            2 => Instr::Stop,
+            3 => Instr::Jr(None),
+            _ => Instr::Jr(Some(CC[y - 4])),
+        },
+        (0, 1) if q == 0 => Instr::LdR16Imm(RP[p]),
+        (0, 1) => Instr::AddHl(RP[p]),
+        (0, 2) if q == 0 => Instr::LdIndA(RPM[p]),
+        (0, 2) => Instr::LdAInd(RPM[p]),
+        (0, 3) if q == 0 => Instr::IncR16(RP[p]),
+        (0, 3) => Instr::DecR16(RP[p]),
+        (0, 4) => Instr::IncR8(R[y]),
+        (0, 5) => Instr::DecR8(R[y]),
+        (0, 6) => Instr::LdR8Imm(R[y]),
+        (0, _) => [
+            Instr::Rlca,
+            Instr::Rrca,
+            Instr::Rla,
+            Instr::Rra,
+            Instr::Daa,
+            Instr::Cpl,
+            Instr::Scf,
+            Instr::Ccf,
+        ][y],
+        // LD (HL),(HL) is where HALT went
+        (1, 6) if y == 6 => Instr::Halt,
+        (1, _) => Instr::LdR8(R[y], R[z]),
+        (2, _) => Instr::Alu(ALU[y], R[z]),
+        (_, 0) => match y {
+            0..=3 => Instr::Ret(Some(CC[y])),
+            4 => Instr::LdhImmA,
+            5 => Instr::AddSp,
+            6 => Instr::LdhAImm,
+            _ => Instr::LdHlSp,
+        },
+        (_, 1) if q == 0 => Instr::Pop(RP2[p]),
+        (_, 1) => [Instr::Ret(None), Instr::Reti, Instr::JpHl, Instr::LdSpHl][p],
+        (_, 2) => match y {
+            0..=3 => Instr::Jp(Some(CC[y])),
+            4 => Instr::LdhCA,
+            5 => Instr::LdImmA,
+            6 => Instr::LdhAC,
+            _ => Instr::LdAImm,
+        },
+        (_, 3) => match y {
+            0 => Instr::Jp(None),
+            1 => Instr::Prefix,
+            6 => Instr::Di,
+            7 => Instr::Ei,
+            _ => Instr::Illegal(opcode),
+        },
+        (_, 4) if y < 4 => Instr::Call(Some(CC[y])),
+        (_, 5) if q == 0 => Instr::Push(RP2[p]),
+        (_, 5) if p == 0 => Instr::Call(None),
+        (_, 4) | (_, 5) => Instr::Illegal(opcode),
+        (_, 6) => Instr::AluImm(ALU[y]),
+        _ => Instr::Rst(y as u8 * 8),
+    }
+}
+
+/// Opcodes after the 0xCB prefix
+pub fn decode_cb(opcode: u8) -> Instr {
+    let y = (opcode >> 3) & 7;
+    let r = R[(opcode & 7) as usize];
+    match opcode >> 6 {
+        0 => Instr::Rot(ROT[y as usize], r),
+        1 => Instr::Bit(y, r),
+        2 => Instr::Res(y, r),
+        _ => Instr::Set(y, r),
+    }
+}
+
+impl Instr {
+    /// Bytes after the opcode
+    pub fn operand_len(self) -> u16 {
+        match self {
+            Instr::LdR8Imm(_)
+            | Instr::LdhImmA
+            | Instr::LdhAImm
+            | Instr::LdHlSp
+            | Instr::AddSp
+            | Instr::AluImm(_)
+            | Instr::Jr(_)
+            | Instr::Prefix => 1,
+            Instr::LdR16Imm(_)
+            | Instr::LdImmSp
+            | Instr::LdImmA
+            | Instr::LdAImm
+            | Instr::Jp(_)
+            | Instr::Call(_) => 2,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for R8 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            R8::HlInd => write!(f, "(HL)"),
+            r => write!(f, "{:?}", r),
+        }
+    }
+}
+
+impl fmt::Display for R16 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            R16::HLInc => write!(f, "HL+"),
+            R16::HLDec => write!(f, "HL-"),
+            r => write!(f, "{:?}", r),
+        }
+    }
+}
+
+impl fmt::Display for Alu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // ADD, ADC and SBC name A, the rest only take the operand
+        let name = format!("{:?}", self).to_uppercase();
+        match self {
+            Alu::Add | Alu::Adc | Alu::Sbc => write!(f, "{} A,", name),
+            _ => write!(f, "{} ", name),
+        }
+    }
+}
+
+/// Disassemble the instruction at `pc`, returns it with its length in bytes
+pub fn disassemble(read: impl Fn(u16) -> u8, pc: u16) -> (String, u16) {
+    let opcode = read(pc);
+    let instr = decode(opcode);
+    if instr == Instr::Prefix {
+        return (
+            disassemble_instr(decode_cb(read(pc.wrapping_add(1))), 0, pc),
+            2,
+        );
+    }
+    let len = 1 + instr.operand_len();
+    let operand = match len {
+        2 => read(pc.wrapping_add(1)) as u16,
+        3 => u16::from_le_bytes([read(pc.wrapping_add(1)), read(pc.wrapping_add(2))]),
+        _ => 0,
+    };
+    (disassemble_instr(instr, operand, pc), len)
+}
+
+fn disassemble_instr(instr: Instr, operand: u16, pc: u16) -> String {
+    let n = operand as u8;
+    let cond = |cc: Option<Cond>| cc.map_or(String::new(), |cc| format!("{:?},", cc));
+    match instr {
+        Instr::Nop => "NOP".to_string(),
+        Instr::Stop => "STOP".to_string(),
+        Instr::Halt => "HALT".to_string(),
+        Instr::Di => "DI".to_string(),
+        Instr::Ei => "EI".to_string(),
+        Instr::Prefix => "PREFIX CB".to_string(),
+        Instr::LdR8(dst, src) => format!("LD {},{}", dst, src),
+        Instr::LdR8Imm(r) => format!("LD {},${:02X}", r, n),
+        Instr::LdR16Imm(rr) => format!("LD {},${:04X}", rr, operand),
+        Instr::LdIndA(rr) => format!("LD ({}),A", rr),
+        Instr::LdAInd(rr) => format!("LD A,({})", rr),
+        Instr::LdImmSp => format!("LD (${:04X}),SP", operand),
+        Instr::LdhImmA => format!("LDH ($FF{:02X}),A", n),
+        Instr::LdhAImm => format!("LDH A,($FF{:02X})", n),
+        Instr::LdhCA => "LDH (C),A".to_string(),
+        Instr::LdhAC => "LDH A,(C)".to_string(),
+        Instr::LdImmA => format!("LD (${:04X}),A", operand),
+        Instr::LdAImm => format!("LD A,(${:04X})", operand),
+        Instr::LdHlSp => format!("LD HL,SP{:+}", n as i8),
+        Instr::LdSpHl => "LD SP,HL".to_string(),
+        Instr::IncR8(r) => format!("INC {}", r),
+        Instr::DecR8(r) => format!("DEC {}", r),
+        Instr::IncR16(rr) => format!("INC {}", rr),
+        Instr::DecR16(rr) => format!("DEC {}", rr),
+        Instr::AddHl(rr) => format!("ADD HL,{}", rr),
+        Instr::AddSp => format!("ADD SP,{}", n as i8),
+        Instr::Alu(op, r) => format!("{}{}", op, r),
+        Instr::AluImm(op) => format!("{}${:02X}", op, n),
+        Instr::Rlca => "RLCA".to_string(),
+        Instr::Rrca => "RRCA".to_string(),
+        Instr::Rla => "RLA".to_string(),
+        Instr::Rra => "RRA".to_string(),
+        Instr::Daa => "DAA".to_string(),
+        Instr::Cpl => "CPL".to_string(),
+        Instr::Scf => "SCF".to_string(),
+        Instr::Ccf => "CCF".to_string(),
+        Instr::Jr(cc) => {
+            // Relative to the end of the instruction
+            let target = pc.wrapping_add(2).wrapping_add(n as i8 as u16);
+            format!("JR {}${:04X}", cond(cc), target)
+        }
+        Instr::Jp(cc) => format!("JP {}${:04X}", cond(cc), operand),
+        Instr::JpHl => "JP HL".to_string(),
+        Instr::Call(cc) => format!("CALL {}${:04X}", cond(cc), operand),
+        Instr::Ret(Some(cc)) => format!("RET {:?}", cc),
+        Instr::Ret(None) => "RET".to_string(),
+        Instr::Reti => "RETI".to_string(),
+        Instr::Rst(addr) => format!("RST ${:02X}", addr),
+        Instr::Push(rr) => format!("PUSH {}", rr),
+        Instr::Pop(rr) => format!("POP {}", rr),
+        Instr::Rot(op, r) => format!("{:?} {}", op, r).to_uppercase(),
+        Instr::Bit(bit, r) => format!("BIT {},{}", bit, r),
+        Instr::Res(bit, r) => format!("RES {},{}", bit, r),
+        Instr::Set(bit, r) => format!("SET {},{}", bit, r),
+        Instr::Illegal(opcode) => format!("ILLEGAL ${:02X}", opcode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disasm(bytes: &[u8]) -> (String, u16) {
+        disassemble(|addr| bytes.get(addr as usize).copied().unwrap_or(0), 0)
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode(0x00), Instr::Nop);
+        assert_eq!(decode(0x76), Instr::Halt);
+        assert_eq!(decode(0x7E), Instr::LdR8(R8::A, R8::HlInd));
+        assert_eq!(decode(0x3A), Instr::LdAInd(R16::HLDec));
+        assert_eq!(decode(0xF1), Instr::Pop(R16::AF));
+        assert_eq!(decode(0xD9), Instr::Reti);
+        assert_eq!(decode(0xDD), Instr::Illegal(0xDD));
+        assert_eq!(decode_cb(0x7E), Instr::Bit(7, R8::HlInd));
+        assert_eq!(decode_cb(0x37), Instr::Rot(Rot::Swap, R8::A));
+
+        let illegal: Vec<u8> = (0..=0xFF)
+            .filter(|&op| matches!(decode(op), Instr::Illegal(_)))
+            .collect();
+        assert_eq!(
+            illegal,
+            [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD]
+        );
+    }
+
+    #[test]
+    fn disassembly() {
+        assert_eq!(disasm(&[0x01, 0x34, 0x12]), ("LD BC,$1234".to_string(), 3));
+        assert_eq!(disasm(&[0x20, 0xFE]), ("JR NZ,$0000".to_string(), 2));
+        assert_eq!(disasm(&[0xE0, 0x40]), ("LDH ($FF40),A".to_string(), 2));
+        assert_eq!(disasm(&[0x8E]), ("ADC A,(HL)".to_string(), 1));
+        assert_eq!(disasm(&[0xB8]), ("CP B".to_string(), 1));
+        assert_eq!(disasm(&[0xCB, 0x1F]), ("RR A".to_string(), 2));
+        assert_eq!(disasm(&[0xF8, 0xFF]), ("LD HL,SP-1".to_string(), 2));
+    }
+}
@@ -0,0 +1,69 @@
+use sdl2::rect::Rect;
+
+use crate::graphics::Rgb;
+
+/// Where a frame is drawn in the window. By default it's the largest whole multiple
+/// of the frame that fits, so every pixel is the same size. Stretching fills as
+/// much as it can while keeping the aspect ratio. Either way it's centered.
+pub fn viewport(window: (u32, u32), frame: (u32, u32), stretch: bool) -> Rect {
+    let (win_w, win_h) = window;
+    let (frame_w, frame_h) = frame;
+    let (w, h) = if stretch {
+        // Compare win_w / frame_w and win_h / frame_h without dividing
+        if win_w * frame_h <= win_h * frame_w {
+            (win_w, win_w * frame_h / frame_w)
+        } else {
+            (win_h * frame_w / frame_h, win_h)
+        }
+    } else {
+        let scale = (win_w / frame_w).min(win_h / frame_h).max(1);
+        (frame_w * scale, frame_h * scale)
+    };
+    let x = (win_w as i32 - w as i32) / 2;
+    let y = (win_h as i32 - h as i32) / 2;
+    Rect::new(x, y, w, h)
+}
+
+/// Copy rows of pixels into an RGB24 texture buffer
+pub fn copy_pixels(pixels: &[Rgb], width: usize, buf: &mut [u8], pitch: usize) {
+    for (line, row) in pixels.chunks(width).zip(buf.chunks_mut(pitch)) {
+        for (&(r, g, b), px) in line.iter().zip(row.chunks_exact_mut(3)) {
+            px.copy_from_slice(&[r, g, b]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        // Integer scaling letterboxes the rest
+        assert_eq!(
+            viewport((500, 500), (160, 144), false),
+            Rect::new(10, 34, 480, 432)
+        );
+        // Never smaller than 1x, even if it's cut off
+        assert_eq!(
+            viewport((100, 100), (160, 144), false),
+            Rect::new(-30, -22, 160, 144)
+        );
+        assert_eq!(
+            viewport((500, 500), (160, 144), true),
+            Rect::new(0, 25, 500, 450)
+        );
+        assert_eq!(
+            viewport((1000, 450), (160, 144), true),
+            Rect::new(250, 0, 500, 450)
+        );
+    }
+
+    #[test]
+    fn copy() {
+        let pixels = [(1, 2, 3), (4, 5, 6), (1, 2, 3), (4, 5, 6)];
+        let mut buf = [0; 16];
+        copy_pixels(&pixels, 2, &mut buf, 8);
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 0, 0, 1, 2, 3, 4, 5, 6, 0, 0]);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::graphics::Rgb;
+
+/// Lines drawn between upscaled pixels
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Grid {
+    Off,
+    Pixels,
+    Scanlines,
+}
+
+/// Post processing done in software, on the way from the framebuffer to the screen
+pub struct PostFilter {
+    pub grid: Grid,
+    pub ghosting: bool,         // blend with the last frame like a slow LCD
+    pub tint: bool,             // shades of DMG green
+    pub color_correction: bool, // CGB LCD colors
+    pub scale: usize,           // screen pixels per Game Boy pixel
+    pixels: Vec<Rgb>,
+    last: Vec<Rgb>, // last frame after blending, for ghosting
+    out: Vec<Rgb>,
+}
+
+impl PostFilter {
+    pub fn new(scale: usize) -> Self {
+        PostFilter {
+            grid: Grid::Off,
+            ghosting: false,
+            tint: false,
+            color_correction: false,
+            scale,
+            pixels: Vec::new(),
+            last: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "grid" => self.grid = Grid::Pixels,
+            "scanlines" => self.grid = Grid::Scanlines,
+            "ghosting" => self.ghosting = true,
+            "tint" => self.tint = true,
+            "color-correction" => self.color_correction = true,
+            _ => return Err(format!("Unknown filter: {}", name)),
+        }
+        Ok(())
+    }
+
+    pub fn next_grid(&mut self) {
+        self.grid = match self.grid {
+            Grid::Off => Grid::Pixels,
+            Grid::Pixels => Grid::Scanlines,
+            Grid::Scanlines => Grid::Off,
+        };
+    }
+
+    /// Filter and upscale a frame, returns the pixels of the scaled up frame row by row
+    pub fn apply<const W: usize>(&mut self, frame: &[[Rgb; W]]) -> &[Rgb] {
+        self.pixels.clear();
+        self.pixels.extend(frame.iter().flatten().map(|&px| {
+            let px = if self.color_correction {
+                correct(px)
+            } else {
+                px
+            };
+            if self.tint {
+                tint(px)
+            } else {
+                px
+            }
+        }));
+
+        if self.ghosting && self.last.len() == self.pixels.len() {
+            for (new, &old) in self.pixels.iter_mut().zip(&self.last) {
+                *new = blend(*new, old);
+            }
+        }
+
+        let scale = self.scale;
+        self.out.clear();
+        for line in self.pixels.chunks(W) {
+            for sy in 0..scale {
+                for &px in line {
+                    for sx in 0..scale {
+                        // The last row and column of each pixel are the gaps
+                        let gap = scale > 1
+                            && match self.grid {
+                                Grid::Off => false,
+                                Grid::Pixels => sx == scale - 1 || sy == scale - 1,
+                                Grid::Scanlines => sy == scale - 1,
+                            };
+                        self.out.push(if gap { darken(px) } else { px });
+                    }
+                }
+            }
+        }
+        std::mem::swap(&mut self.last, &mut self.pixels);
+        &self.out
+    }
+}
+
+fn darken((r, g, b): Rgb) -> Rgb {
+    let dim = |c: u8| (c as u16 * 3 / 4) as u8;
+    (dim(r), dim(g), dim(b))
+}
+
+/// LCD pixels take more than a frame to change
+fn blend(new: Rgb, old: Rgb) -> Rgb {
+    let mix = |n: u8, o: u8| ((n as u16 * 5 + o as u16 * 3) / 8) as u8;
+    (mix(new.0, old.0), mix(new.1, old.1), mix(new.2, old.2))
+}
+
+/// Map brightness onto the DMG's green, from darkest to lightest
+fn tint((r, g, b): Rgb) -> Rgb {
+    const DARK: Rgb = (0x0F, 0x38, 0x0F);
+    const LIGHT: Rgb = (0x9B, 0xBC, 0x0F);
+    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+    let lerp =
+        |dark: u8, light: u8| (dark as u32 + (light as u32 - dark as u32) * luma / 255) as u8;
+    (
+        lerp(DARK.0, LIGHT.0),
+        lerp(DARK.1, LIGHT.1),
+        lerp(DARK.2, LIGHT.2),
+    )
+}
+
+/// The CGB screen mixes the channels and washes out colors
+fn correct((r, g, b): Rgb) -> Rgb {
+    let (r, g, b) = (r as u32, g as u32, b as u32);
+    (
+        ((r * 26 + g * 4 + b * 2) / 32) as u8,
+        ((g * 24 + b * 8) / 32) as u8,
+        ((r * 6 + g * 4 + b * 22) / 32) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid() {
+        let frame = [[(0xFF, 0xFF, 0xFF); 2]; 1];
+        let mut filter = PostFilter::new(2);
+        assert_eq!(filter.apply(&frame), [(0xFF, 0xFF, 0xFF); 8]);
+
+        filter.set("grid").unwrap();
+        let (white, gap) = ((0xFF, 0xFF, 0xFF), (0xBF, 0xBF, 0xBF));
+        assert_eq!(
+            filter.apply(&frame),
+            [white, gap, white, gap, gap, gap, gap, gap]
+        );
+        filter.next_grid();
+        assert_eq!(
+            filter.apply(&frame),
+            [white, white, white, white, gap, gap, gap, gap]
+        );
+        // The window got bigger
+        filter.scale = 3;
+        let out = filter.apply(&frame);
+        assert_eq!(out.len(), 18);
+        assert_eq!(&out[..6], [white; 6]);
+        assert_eq!(&out[12..], [gap; 6]);
+    }
+
+    #[test]
+    fn colors() {
+        let mut filter = PostFilter::new(1);
+        filter.set("ghosting").unwrap();
+        filter.apply(&[[(0, 0, 0)]]);
+        assert_eq!(filter.apply(&[[(0xFF, 0xFF, 0xFF)]]), [(0x9F, 0x9F, 0x9F)]);
+
+        assert_eq!(tint((0, 0, 0)), (0x0F, 0x38, 0x0F));
+        assert_eq!(tint((0xFF, 0xFF, 0xFF)), (0x9B, 0xBC, 0x0F));
+        // White stays white
+        assert_eq!(correct((0xFF, 0xFF, 0xFF)), (0xFF, 0xFF, 0xFF));
+        assert!(filter.set("blur").is_err());
+    }
+}
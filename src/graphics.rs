@@ -1,8 +1,45 @@
-pub const WIDTH: u32 = 166;
+pub const WIDTH: u32 = 160;
 pub const HEIGHT: u32 = 144;
 
-type Frame = [[u8; 166]; 144];
-type Tile = [u8; 16];
+use crate::palette::DmgPalette;
+
+pub type Rgb = (u8, u8, u8);
+
+/// RGB of each shade, lightest to darkest
+pub const SHADES: [Rgb; 4] = [
+    (0xFF, 0xFF, 0xFF),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0x00, 0x00, 0x00),
+];
+
+// PPU timing in dots (T-cycles)
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAW_DOTS: u32 = 172;
+const LINE_DOTS: u32 = 456;
+const LINES: u8 = 154;
+
+pub type Frame = [[Rgb; WIDTH as usize]; HEIGHT as usize];
+/// DMG shade of every pixel before it's turned into RGB
+pub type Shades = [[u8; WIDTH as usize]; HEIGHT as usize];
+pub type Tile = [u8; 16];
+
+/// Registers as a line was drawn (or as it started in VBlank), and the interrupts
+/// requested during it
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct LineRegs {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scx: u8,
+    pub scy: u8,
+    pub wx: u8,
+    pub wy: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub interrupts: u8, // IF bits
+}
+
 type TileColorMap = [[u8; 8]; 8];
 
 fn tile_rows(tile: Tile) -> [[u8; 2]; 8] {
@@ -13,7 +50,7 @@ fn tile_rows(tile: Tile) -> [[u8; 2]; 8] {
     rows
 }
 
-fn tile_color_map(tile: Tile) -> TileColorMap {
+pub fn tile_color_map(tile: Tile) -> TileColorMap {
     let mut map: [[u8; 8]; 8] = [[0; 8]; 8];
     let rows = tile_rows(tile);
     for (line, row) in rows.iter().enumerate() {
@@ -30,25 +67,98 @@ fn tile_color_map(tile: Tile) -> TileColorMap {
     map
 }
 
+/// Expand a CGB 15-bit color
+pub fn rgb555(color: u16) -> Rgb {
+    let channel = |shift: u16| {
+        let c = ((color >> shift) & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    };
+    (channel(0), channel(5), channel(10))
+}
+
+/// CGB palette memory, 8 palettes of 4 colors, accessed through an index register
+#[derive(Clone)]
+pub struct PaletteRam {
+    data: [u8; 64],
+    spec: u8, // index and auto increment bit
+}
+
+impl PaletteRam {
+    fn new() -> Self {
+        PaletteRam {
+            data: [0xFF; 64],
+            spec: 0,
+        }
+    }
+
+    pub fn spec(&self) -> u8 {
+        self.spec
+    }
+
+    pub fn write_spec(&mut self, val: u8) {
+        self.spec = val & 0xBF;
+    }
+
+    pub fn data(&self) -> u8 {
+        self.data[(self.spec & 0x3F) as usize]
+    }
+
+    /// The index still increments when the write is dropped during drawing
+    pub fn write_data(&mut self, val: u8, locked: bool) {
+        if !locked {
+            self.data[(self.spec & 0x3F) as usize] = val;
+        }
+        if self.spec & 0x80 > 0 {
+            self.spec = 0x80 | (self.spec + 1) & 0x3F;
+        }
+    }
+
+    pub fn color(&self, palette: u8, id: u8) -> Rgb {
+        let i = (palette as usize * 4 + id as usize) * 2;
+        rgb555(u16::from_le_bytes([self.data[i], self.data[i + 1]]))
+    }
+}
+
+#[derive(Clone)]
 pub struct Gpu {
-    vram: [u8; 8192],
-    pub lcdc: u8, // LCD control
-    pub ly: u8,   // LCD Y coord
-    pub lyc: u8,  // LY compare
-    pub stat: u8, // LCD status
-    pub scy: u8,  // Scroll Y
-    pub scx: u8,  // Scroll X
-    pub wy: u8,   // Window pos Y
-    pub wx: u8,   // Window pox X
-    pub bgp: u8,  // BG palette data
-    pub obp0: u8, // Obj palette 0
-    pub obp1: u8, // Obj palette 1
+    pub cgb: bool,
+    vram: [u8; 0x4000],
+    pub vram_bank: u8,
+    oam: [u8; 160],
+    pub lcdc: u8,            // LCD control
+    pub ly: u8,              // LCD Y coord
+    pub lyc: u8,             // LY compare
+    pub stat: u8,            // LCD status
+    pub scy: u8,             // Scroll Y
+    pub scx: u8,             // Scroll X
+    pub wy: u8,              // Window pos Y
+    pub wx: u8,              // Window pox X
+    pub bgp: u8,             // BG palette data
+    pub obp0: u8,            // Obj palette 0
+    pub obp1: u8,            // Obj palette 1
+    pub bcp: PaletteRam,     // CGB BG palettes
+    pub ocp: PaletteRam,     // CGB Obj palettes
+    pub palette: DmgPalette, // colors of the DMG shades
+    dots: u32,
+    window_line: u8,
+    stat_line: bool,
+    vblank_int: bool,
+    stat_int: bool,
+    hblank_started: bool,
+    frame: Frame,
+    shades: Shades, // colored by the SGB
+    frame_ready: bool,
+    lines: [LineRegs; LINES as usize], // of the frame being drawn
+    last_lines: [LineRegs; LINES as usize], // of the last drawn frame, its VBlank so far
 }
 
 impl Gpu {
     pub fn new() -> Self {
         Gpu {
-            vram: [0; 8192],
+            cgb: false,
+            vram: [0; 0x4000],
+            vram_bank: 0,
+            oam: [0; 160],
             lcdc: 0x91,
             ly: 0,
             lyc: 0,
@@ -60,15 +170,84 @@ impl Gpu {
             bgp: 0xFC,
             obp0: 0,
             obp1: 0,
+            bcp: PaletteRam::new(),
+            ocp: PaletteRam::new(),
+            palette: DmgPalette::grey(),
+            dots: 0,
+            window_line: 0,
+            stat_line: false,
+            vblank_int: false,
+            stat_int: false,
+            hblank_started: false,
+            frame: [[SHADES[0]; WIDTH as usize]; HEIGHT as usize],
+            shades: [[0; WIDTH as usize]; HEIGHT as usize],
+            frame_ready: false,
+            lines: [LineRegs::default(); LINES as usize],
+            last_lines: [LineRegs::default(); LINES as usize],
         }
     }
 
+    pub fn vram_at(&self, bank: u8, addr: u16) -> u8 {
+        self.vram[bank as usize * 0x2000 + addr as usize - 0x8000]
+    }
+
     pub fn read_vram(&self, addr: u16) -> u8 {
-        self.vram[addr as usize - 0x8000]
+        self.vram_at(self.vram_bank, addr)
     }
 
     pub fn write_vram(&mut self, addr: u16, val: u8) {
-        self.vram[addr as usize - 0x8000] = val;
+        self.vram[self.vram_bank as usize * 0x2000 + addr as usize - 0x8000] = val;
+    }
+
+    /// The 16 bytes of the tile at `addr` in a VRAM bank
+    pub fn tile(&self, bank: u8, addr: u16) -> Tile {
+        let mut tile = [0; 16];
+        for (i, byte) in tile.iter_mut().enumerate() {
+            *byte = self.vram_at(bank, addr + i as u16);
+        }
+        tile
+    }
+
+    pub fn oam(&self) -> &[u8; 160] {
+        &self.oam
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        self.oam[addr as usize - 0xFE00]
+    }
+
+    pub fn write_oam(&mut self, addr: u16, val: u8) {
+        self.oam[addr as usize - 0xFE00] = val;
+    }
+
+    /// VRAM is locked while the PPU draws
+    pub fn vram_accessible(&self) -> bool {
+        self.ppu_mode() != 3
+    }
+
+    /// OAM is locked during OAM scan and drawing
+    pub fn oam_accessible(&self) -> bool {
+        self.ppu_mode() < 2
+    }
+
+    pub fn write_lcdc(&mut self, val: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = val;
+        if was_enabled && !self.lcd_enabled() {
+            self.ly = 0;
+            self.dots = 0;
+            self.set_ppu_mode(0);
+        } else if !was_enabled && self.lcd_enabled() {
+            self.dots = 0;
+            self.window_line = 0;
+            self.set_ppu_mode(2);
+            self.compare_ly();
+        }
+    }
+
+    /// Lower 3 bits of STAT are read only
+    pub fn write_stat(&mut self, val: u8) {
+        self.stat = (val & 0x78) | (self.stat & 0b111);
     }
 
     /// LCDC.7
@@ -98,7 +277,7 @@ impl Gpu {
     }
 
     /// LCDC.3
-    fn bg_tile_map_area(&self) -> u16 {
+    pub fn bg_tile_map_area(&self) -> u16 {
         if self.lcdc & (1 << 3) == 0 {
             0x9800
         } else {
@@ -107,11 +286,11 @@ impl Gpu {
     }
 
     /// LCDC.2
-    fn obj_size(&self) -> u32 {
+    pub fn obj_height(&self) -> u8 {
         if self.lcdc & (1 << 2) == 0 {
-            8 * 8
+            8
         } else {
-            8 * 16
+            16
         }
     }
 
@@ -130,58 +309,319 @@ impl Gpu {
     }
 
     fn set_ppu_mode(&mut self, n: u8) {
-        self.stat &= n & 0b11;
+        self.stat = (self.stat & !0b11) | (n & 0b11);
+    }
+
+    /// STAT.2
+    fn compare_ly(&mut self) {
+        if self.ly == self.lyc {
+            self.stat |= 1 << 2;
+        } else {
+            self.stat &= !(1 << 2);
+        }
+    }
+
+    /// Taken once per VBlank
+    pub fn should_vblank_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_int)
+    }
+
+    /// Taken once per rising edge of the STAT interrupt line
+    pub fn should_stat_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.stat_int)
+    }
+
+    /// True once per HBlank of a visible line, clocks CGB HBlank DMA
+    pub fn hblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.hblank_started)
+    }
+
+    /// True once per finished frame
+    pub fn frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
     }
 
-    pub fn should_vblank_interrupt(&self) -> bool {
-        self.ppu_mode() == 1
+    pub fn shades(&self) -> &Shades {
+        &self.shades
     }
 
-    pub fn should_stat_interrupt(&self) -> bool {
-        (self.stat & (1 << 6) > 0 && self.stat & (1 << 2) > 0)    // LYC int
+    /// Registers of every line of the last frame drawn. In VBlank that's the frame
+    /// that was just shown, with the VBlank lines up to the current one.
+    pub fn line_log(&self) -> &[LineRegs] {
+        &self.last_lines
+    }
+
+    /// VBlank goes with the frame before it
+    fn logged_line(&mut self) -> &mut LineRegs {
+        let ly = self.ly as usize;
+        if ly < HEIGHT as usize {
+            &mut self.lines[ly]
+        } else {
+            &mut self.last_lines[ly]
+        }
+    }
+
+    /// Note interrupts requested on the current line
+    pub fn log_interrupts(&mut self, requested: u8) {
+        self.logged_line().interrupts |= requested;
+    }
+
+    fn log_line(&mut self) {
+        let regs = LineRegs {
+            lcdc: self.lcdc,
+            stat: self.stat,
+            scx: self.scx,
+            scy: self.scy,
+            wx: self.wx,
+            wy: self.wy,
+            bgp: self.bgp,
+            obp0: self.obp0,
+            obp1: self.obp1,
+            interrupts: 0,
+        };
+        let line = self.logged_line();
+        *line = LineRegs {
+            interrupts: line.interrupts,
+            ..regs
+        };
+    }
+
+    /// Data of the BG tiles on screen, read left to right and top to bottom.
+    /// This is how the SGB picks up VRAM transfers.
+    pub fn screen_tile_data(&self, len: usize) -> Vec<u8> {
+        let map = self.bg_tile_map_area();
+        (0..len / 16)
+            .map(|i| map + (i as u16 / 20) * 32 + i as u16 % 20)
+            .flat_map(|map_addr| {
+                let addr = self.bg_win_tile_addr(self.vram_at(0, map_addr));
+                (0..16).map(move |b| self.vram_at(0, addr + b))
+            })
+            .collect()
+    }
+
+    fn update_stat_line(&mut self) {
+        let line = (self.stat & (1 << 6) > 0 && self.stat & (1 << 2) > 0)    // LYC int
             || (self.stat & (1 << 5) > 0 && self.ppu_mode() == 2) // Mode 2 int
             || (self.stat & (1 << 4) > 0 && self.ppu_mode() == 1) // Mode 1 int
-            || (self.stat & (1 << 3) > 0 && self.ppu_mode() == 0) // Mode 0 int
+            || (self.stat & (1 << 3) > 0 && self.ppu_mode() == 0); // Mode 0 int
+        if line && !self.stat_line {
+            self.stat_int = true;
+        }
+        self.stat_line = line;
     }
 
-    fn object_tile(&self, id: u8) -> Tile {
-        let start_addr = 0x8000 + (16 * id as u16);
-        let mut tile = [0; 16];
-        for i in 0..tile.len() {
-            tile[i] = self.read_vram(start_addr + i as u16);
+    /// The PPU always runs at single speed, so it's stepped in dots
+    pub fn step(&mut self, dots: u32) {
+        if !self.lcd_enabled() {
+            // Nothing is drawn, but frames keep coming so the frontend can run
+            self.dots += dots;
+            if self.dots >= LINE_DOTS * LINES as u32 {
+                self.dots -= LINE_DOTS * LINES as u32;
+                let blank = if self.cgb {
+                    SHADES[0]
+                } else {
+                    self.palette.bg[0]
+                };
+                self.frame = [[blank; WIDTH as usize]; HEIGHT as usize];
+                self.shades = [[0; WIDTH as usize]; HEIGHT as usize];
+                self.frame_ready = true;
+            }
+            return;
+        }
+
+        for _ in 0..dots {
+            self.dot();
         }
-        tile
     }
 
-    fn bg_win_tile(&self, id: u8) -> Tile {
-        let mut tile = [0; 16];
-        let start_addr = if self.bg_win_addr_mode() {
+    fn dot(&mut self) {
+        self.dots += 1;
+        if self.ly < HEIGHT as u8 {
+            if self.dots == OAM_SCAN_DOTS {
+                self.set_ppu_mode(3);
+            } else if self.dots == OAM_SCAN_DOTS + DRAW_DOTS {
+                self.render_line();
+                self.set_ppu_mode(0);
+                self.hblank_started = true;
+            }
+        }
+
+        if self.dots == LINE_DOTS {
+            self.dots = 0;
+            self.ly = (self.ly + 1) % LINES;
+            if self.ly == HEIGHT as u8 {
+                self.set_ppu_mode(1);
+                self.vblank_int = true;
+                self.frame_ready = true;
+                // The frontend looks at the log once the frame is ready
+                self.last_lines = self.lines;
+                self.lines = [LineRegs::default(); LINES as usize];
+            } else if self.ly < HEIGHT as u8 {
+                if self.ly == 0 {
+                    self.window_line = 0;
+                }
+                self.set_ppu_mode(2);
+            }
+            self.compare_ly();
+            if self.ly >= HEIGHT as u8 {
+                self.log_line();
+            }
+        }
+        self.update_stat_line();
+    }
+
+    pub fn bg_win_tile_addr(&self, id: u8) -> u16 {
+        if self.bg_win_addr_mode() {
             0x8000 + (16 * id as u16)
         } else {
-            let id = if id > 127 { 127 - id as i8 } else { id as i8 };
-            let offset = 16 * id as i32;
+            let offset = 16 * id as i8 as i32;
             (0x9000 + offset) as u16
-        };
+        }
+    }
+
+    /// Color id of pixel x, y of the tile at addr
+    fn tile_pixel(&self, bank: u8, addr: u16, x: u8, y: u8) -> u8 {
+        let lo = self.vram_at(bank, addr + 2 * y as u16);
+        let hi = self.vram_at(bank, addr + 2 * y as u16 + 1);
+        let bit = 7 - x;
+        ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1)
+    }
+
+    fn render_line(&mut self) {
+        self.log_line();
+        let ly = self.ly as usize;
+        // Color id and CGB priority bit of each BG pixel, used to mix in objects
+        let mut bg_colors = [0; WIDTH as usize];
+        let mut bg_priority = [false; WIDTH as usize];
+
+        // On CGB LCDC.0 only takes away the BG's priority
+        if self.bg_win_enabled() || self.cgb {
+            let win_visible = self.win_enabled() && self.ly >= self.wy && self.wx <= 166;
+            for x in 0..WIDTH as usize {
+                let in_win = win_visible && x + 7 >= self.wx as usize;
+                let (map, px, py) = if in_win {
+                    let px = (x + 7 - self.wx as usize) as u8;
+                    (self.win_tile_map_area(), px, self.window_line)
+                } else {
+                    let px = self.scx.wrapping_add(x as u8);
+                    (self.bg_tile_map_area(), px, self.scy.wrapping_add(self.ly))
+                };
+                let map_addr = map + (py as u16 / 8) * 32 + px as u16 / 8;
+                let id = self.vram_at(0, map_addr);
+                // CGB BG map attributes live in bank 1
+                let attrs = if self.cgb {
+                    self.vram_at(1, map_addr)
+                } else {
+                    0
+                };
 
-        for i in 0..tile.len() {
-            tile[i] = self.read_vram(start_addr as u16 + i as u16);
+                let (mut tx, mut ty) = (px % 8, py % 8);
+                if attrs & (1 << 5) > 0 {
+                    tx = 7 - tx;
+                }
+                if attrs & (1 << 6) > 0 {
+                    ty = 7 - ty;
+                }
+                let bank = (attrs >> 3) & 1;
+                let color = self.tile_pixel(bank, self.bg_win_tile_addr(id), tx, ty);
+
+                bg_colors[x] = color;
+                bg_priority[x] = attrs & (1 << 7) > 0;
+                self.frame[ly][x] = if self.cgb {
+                    self.bcp.color(attrs & 0b111, color)
+                } else {
+                    self.shades[ly][x] = shade(self.bgp, color);
+                    self.palette.bg[self.shades[ly][x] as usize]
+                };
+            }
+            if win_visible {
+                self.window_line += 1;
+            }
+        } else {
+            self.frame[ly] = [self.palette.bg[0]; WIDTH as usize];
+            self.shades[ly] = [0; WIDTH as usize];
+        }
+
+        if self.obj_enabled() {
+            self.render_objects(&bg_colors, &bg_priority);
         }
-        tile
     }
 
-    pub fn draw_frame(&mut self) -> Frame {
-        let mut frame: Frame = [[0; 166]; 144];
+    fn render_objects(
+        &mut self,
+        bg_colors: &[u8; WIDTH as usize],
+        bg_priority: &[bool; WIDTH as usize],
+    ) {
+        let height = self.obj_height() as i16;
+        let ly = self.ly as i16;
 
-        self.ly = 0;
-        while self.ly <= 153 {
-            self.set_ppu_mode(2);
+        // At most 10 objects per line, in OAM order
+        let mut objs: Vec<(usize, [u8; 4])> = self
+            .oam
+            .chunks_exact(4)
+            .map(|o| [o[0], o[1], o[2], o[3]])
+            .enumerate()
+            .filter(|(_, o)| {
+                let y = o[0] as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(10)
+            .collect();
+
+        // Smaller X wins on DMG, then lower OAM index, so draw the winners last
+        if !self.cgb {
+            objs.sort_by_key(|(i, o)| (o[1], *i));
+        }
+        for (_, [y, x, tile, attrs]) in objs.into_iter().rev() {
+            let mut line = (ly - (y as i16 - 16)) as u8;
+            if attrs & (1 << 6) > 0 {
+                line = height as u8 - 1 - line;
+            }
+            let tile = if height == 16 { tile & 0xFE } else { tile };
+            let addr = 0x8000 + 16 * tile as u16;
+            let bank = if self.cgb { (attrs >> 3) & 1 } else { 0 };
+            let (palette, colors) = if attrs & (1 << 4) > 0 {
+                (self.obp1, self.palette.obj1)
+            } else {
+                (self.obp0, self.palette.obj0)
+            };
 
-            self.ly += 1;
+            for px in 0..8 {
+                let sx = x as i16 - 8 + px as i16;
+                if !(0..WIDTH as i16).contains(&sx) {
+                    continue;
+                }
+                let sx = sx as usize;
+                let px = if attrs & (1 << 5) > 0 { 7 - px } else { px };
+                let color = self.tile_pixel(bank, addr, px, line);
+                // Color 0 is transparent, BG colors 1-3 can be drawn over objects
+                let bg_wins = bg_colors[sx] != 0
+                    && (attrs & (1 << 7) > 0 || bg_priority[sx])
+                    && (!self.cgb || self.bg_win_enabled());
+                if color == 0 || bg_wins {
+                    continue;
+                }
+                let ly = ly as usize;
+                self.frame[ly][sx] = if self.cgb {
+                    self.ocp.color(attrs & 0b111, color)
+                } else {
+                    self.shades[ly][sx] = shade(palette, color);
+                    colors[self.shades[ly][sx] as usize]
+                };
+            }
         }
-        frame
     }
 }
 
+/// Map a color id through a palette register
+pub fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -220,4 +660,109 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn timing() {
+        let mut gpu = Gpu::new();
+        gpu.write_lcdc(0);
+        gpu.write_lcdc(0x91);
+        gpu.write_stat(1 << 4); // Mode 1 int
+
+        gpu.step(OAM_SCAN_DOTS);
+        assert_eq!(gpu.ppu_mode(), 3);
+        gpu.step(DRAW_DOTS);
+        assert_eq!(gpu.ppu_mode(), 0);
+        gpu.step(LINE_DOTS - OAM_SCAN_DOTS - DRAW_DOTS);
+        assert_eq!((gpu.ly, gpu.ppu_mode()), (1, 2));
+
+        gpu.step(143 * LINE_DOTS);
+        assert_eq!((gpu.ly, gpu.ppu_mode()), (144, 1));
+        assert!(gpu.should_vblank_interrupt());
+        assert!(!gpu.should_vblank_interrupt());
+        assert!(gpu.should_stat_interrupt());
+        assert!(gpu.frame_ready());
+
+        gpu.step(10 * LINE_DOTS);
+        assert_eq!((gpu.ly, gpu.ppu_mode()), (0, 2));
+        assert!(!gpu.should_stat_interrupt());
+    }
+
+    #[test]
+    fn render() {
+        let mut gpu = Gpu::new();
+        gpu.bgp = 0xE4;
+        gpu.obp0 = 0xE4;
+        // Tile 1 is solid color 3, map entry 0 uses it
+        for i in 0..16 {
+            gpu.write_vram(0x8010 + i, 0xFF);
+        }
+        gpu.write_vram(0x9800, 1);
+        // Objects using tile 1 on the first line, behind BG colors 1-3
+        for (i, (x, attrs)) in [(16, 1 << 7), (8, 1 << 7 | 1 << 4)].iter().enumerate() {
+            let addr = 0xFE00 + 4 * i as u16;
+            gpu.write_oam(addr, 16);
+            gpu.write_oam(addr + 1, *x);
+            gpu.write_oam(addr + 2, 1);
+            gpu.write_oam(addr + 3, *attrs);
+        }
+        gpu.write_lcdc(0);
+        gpu.write_lcdc(0x93);
+
+        while !gpu.frame_ready() {
+            gpu.step(4);
+        }
+        let frame = gpu.frame();
+        let (white, black) = (SHADES[0], SHADES[3]);
+        assert_eq!(frame[0][..8], [black; 8]); // BG wins over OBP1
+        assert_eq!(frame[0][8..16], [black; 8]);
+        assert_eq!(frame[0][16], white);
+        assert_eq!(frame[8][0], white);
+
+        // Signed addressing reads tile 1 from 0x9010
+        gpu.write_lcdc(0x81);
+        assert_eq!(gpu.bg_win_tile_addr(1), 0x9010);
+        assert_eq!(gpu.bg_win_tile_addr(0xFF), 0x8FF0);
+    }
+
+    #[test]
+    fn render_cgb() {
+        let mut gpu = Gpu::new();
+        gpu.cgb = true;
+        // Palette 2 color 3 is red, color 1 of object palette 0 is blue
+        gpu.bcp.write_spec(0x80 | (2 * 8 + 6));
+        gpu.bcp.write_data(0x1F, false);
+        gpu.bcp.write_data(0x00, false);
+        assert_eq!(gpu.bcp.spec(), 0x80 | (2 * 8 + 8));
+        gpu.ocp.write_spec(0x80 | 2);
+        gpu.ocp.write_data(0x00, false);
+        gpu.ocp.write_data(0x7C, false);
+
+        // Tile 1 in bank 1, left half color 3
+        gpu.vram_bank = 1;
+        for i in 0..8 {
+            gpu.write_vram(0x8010 + 2 * i, 0xF0);
+            gpu.write_vram(0x8011 + 2 * i, 0xF0);
+        }
+        // Map entry 0 uses it, x flipped, palette 2
+        gpu.write_vram(0x9800, 1 << 5 | 1 << 3 | 2);
+        gpu.vram_bank = 0;
+        gpu.write_vram(0x9800, 1);
+        gpu.write_vram(0x8020, 0xFF); // Tile 2, first line color 1
+
+        // Object over the BG, OBJ palette 0
+        gpu.write_oam(0xFE00, 16);
+        gpu.write_oam(0xFE01, 16);
+        gpu.write_oam(0xFE02, 2);
+        gpu.write_lcdc(0);
+        gpu.write_lcdc(0x93);
+
+        while !gpu.frame_ready() {
+            gpu.step(4);
+        }
+        let frame = gpu.frame();
+        assert_eq!(frame[0][3], SHADES[0]);
+        assert_eq!(frame[0][4], (0xFF, 0, 0));
+        assert_eq!(frame[0][8], (0, 0, 0xFF));
+        assert_eq!(frame[1][8], SHADES[0]);
+    }
 }
@@ -0,0 +1,34 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::graphics::Rgb;
+
+/// Write 8-bit grayscale or RGB pixels to a PNG
+pub fn write_png(
+    path: &Path,
+    width: u32,
+    color: png::ColorType,
+    data: &[u8],
+) -> Result<(), png::EncodingError> {
+    let file = File::create(path)?;
+    let height = data.len() as u32 / (width * color.samples() as u32);
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)
+}
+
+/// Save a frame as an RGB PNG
+pub fn write_frame<const W: usize>(
+    path: &Path,
+    frame: &[[Rgb; W]],
+) -> Result<(), png::EncodingError> {
+    let data: Vec<u8> = frame
+        .iter()
+        .flatten()
+        .flat_map(|&(r, g, b)| [r, g, b])
+        .collect();
+    write_png(path, W as u32, png::ColorType::Rgb, &data)
+}
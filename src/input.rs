@@ -0,0 +1,495 @@
+use std::collections::{HashMap, HashSet};
+
+use sdl2::controller::{Axis, Button as PadButton};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use toml::{Table, Value};
+
+use crate::buttons::{Button, DpadDirection, GbKeyEvent};
+
+/// Something a key or controller input can be bound to
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Joypad(GbKeyEvent),
+    Turbo(Button),
+    Quit,
+    Pause,
+    FastForward,
+    Rewind,
+    SaveState,
+    LoadState,
+    Screenshot,
+    Fullscreen,
+    NextPalette,
+    Grid,
+    Ghosting,
+    Tint,
+    ColorCorrection,
+    ReloadConfig,
+    Debug,
+    VramViewer,
+    Cheat(usize), // toggles the game's cheat at this index
+}
+
+/// Names used in keymaps
+const ACTIONS: [(&str, Action); 35] = [
+    ("a", Action::Joypad(GbKeyEvent::Button(Button::A))),
+    ("b", Action::Joypad(GbKeyEvent::Button(Button::B))),
+    ("start", Action::Joypad(GbKeyEvent::Button(Button::Start))),
+    ("select", Action::Joypad(GbKeyEvent::Button(Button::Select))),
+    ("up", Action::Joypad(GbKeyEvent::Dpad(DpadDirection::Up))),
+    (
+        "down",
+        Action::Joypad(GbKeyEvent::Dpad(DpadDirection::Down)),
+    ),
+    (
+        "left",
+        Action::Joypad(GbKeyEvent::Dpad(DpadDirection::Left)),
+    ),
+    (
+        "right",
+        Action::Joypad(GbKeyEvent::Dpad(DpadDirection::Right)),
+    ),
+    ("turbo_a", Action::Turbo(Button::A)),
+    ("turbo_b", Action::Turbo(Button::B)),
+    ("quit", Action::Quit),
+    ("pause", Action::Pause),
+    ("fast_forward", Action::FastForward),
+    ("rewind", Action::Rewind),
+    ("save_state", Action::SaveState),
+    ("load_state", Action::LoadState),
+    ("screenshot", Action::Screenshot),
+    ("fullscreen", Action::Fullscreen),
+    ("next_palette", Action::NextPalette),
+    ("grid", Action::Grid),
+    ("ghosting", Action::Ghosting),
+    ("tint", Action::Tint),
+    ("color_correction", Action::ColorCorrection),
+    ("reload_config", Action::ReloadConfig),
+    ("debug", Action::Debug),
+    ("vram_viewer", Action::VramViewer),
+    ("cheat_1", Action::Cheat(0)),
+    ("cheat_2", Action::Cheat(1)),
+    ("cheat_3", Action::Cheat(2)),
+    ("cheat_4", Action::Cheat(3)),
+    ("cheat_5", Action::Cheat(4)),
+    ("cheat_6", Action::Cheat(5)),
+    ("cheat_7", Action::Cheat(6)),
+    ("cheat_8", Action::Cheat(7)),
+    ("cheat_9", Action::Cheat(8)),
+];
+
+const DEFAULT_KEYMAP: &str = r#"
+[keyboard]
+a = "X"
+b = "Z"
+start = "Return"
+select = "Backspace"
+up = "Up"
+down = "Down"
+left = "Left"
+right = "Right"
+turbo_a = "S"
+turbo_b = "A"
+quit = "Escape"
+pause = "Space"
+fast_forward = "Tab"
+rewind = "R"
+save_state = "F5"
+load_state = "F7"
+screenshot = "F12"
+fullscreen = "F11"
+next_palette = "P"
+grid = "F1"
+ghosting = "F2"
+tint = "F3"
+color_correction = "F4"
+reload_config = "F9"
+debug = "F10"
+vram_viewer = "F8"
+cheat_1 = "1"
+cheat_2 = "2"
+cheat_3 = "3"
+cheat_4 = "4"
+cheat_5 = "5"
+cheat_6 = "6"
+cheat_7 = "7"
+cheat_8 = "8"
+cheat_9 = "9"
+
+[controller]
+a = "b"
+b = "a"
+start = "start"
+select = "back"
+up = ["dpup", "lefty-"]
+down = ["dpdown", "lefty+"]
+left = ["dpleft", "leftx-"]
+right = ["dpright", "leftx+"]
+turbo_a = "y"
+turbo_b = "x"
+fast_forward = "rightshoulder"
+rewind = "leftshoulder"
+deadzone = 8000
+"#;
+
+/// Maps keyboard and game controller input to actions
+pub struct Keymap {
+    keys: HashMap<Keycode, Vec<Action>>,
+    buttons: HashMap<PadButton, Vec<Action>>,
+    axes: HashMap<(Axis, bool), Vec<Action>>, // axis and whether it's the positive direction
+    deadzone: i16,
+    held: HashSet<Input>,
+    holding: HashMap<Action, u32>, // how many held inputs each action has
+}
+
+/// An input that's held down, controller ones by the controller's instance id
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Input {
+    Key(Keycode),
+    Button(u32, PadButton),
+    Axis(u32, Axis, bool),
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let mut keymap = Keymap {
+            keys: HashMap::new(),
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+            deadzone: 0,
+            held: HashSet::new(),
+            holding: HashMap::new(),
+        };
+        let defaults: Table = toml::from_str(DEFAULT_KEYMAP).unwrap();
+        keymap.apply(&defaults).unwrap();
+        keymap
+    }
+
+    /// Keymaps have a keyboard and a controller table binding action = "input",
+    /// or a list of inputs. Keys use SDL key names, controllers use SDL button names
+    /// or an axis name followed by the direction, like "leftx-". Actions that aren't
+    /// in the keymap keep their bindings, unless the keymap takes their input.
+    pub fn apply(&mut self, keymap: &Table) -> Result<(), String> {
+        for (section, bindings) in keymap {
+            let mut rebound = Vec::new();
+            if section != "keyboard" && section != "controller" {
+                return Err(format!("unknown keymap section {}", section));
+            }
+            let bindings = bindings
+                .as_table()
+                .ok_or_else(|| format!("keymap {} should be a table", section))?;
+
+            for (key, value) in bindings {
+                if section == "controller" && key == "deadzone" {
+                    self.deadzone = value
+                        .as_integer()
+                        .and_then(|d| i16::try_from(d).ok())
+                        .ok_or_else(|| format!("bad deadzone {}", value))?;
+                    continue;
+                }
+                let action = ACTIONS
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|&(_, action)| action)
+                    .ok_or_else(|| format!("unknown action {}", key))?;
+                let inputs = match value {
+                    Value::String(input) => vec![input.as_str()],
+                    Value::Array(inputs) => inputs.iter().filter_map(Value::as_str).collect(),
+                    _ => return Err(format!("expected a name or a list of names for {}", key)),
+                };
+                self.unbind(section, action);
+                for input in inputs {
+                    self.bind(section, input, action, &rebound)?;
+                }
+                rebound.push(action);
+            }
+        }
+        Ok(())
+    }
+
+    fn unbind(&mut self, section: &str, action: Action) {
+        if section == "keyboard" {
+            self.keys
+                .values_mut()
+                .for_each(|a| a.retain(|&b| b != action));
+        } else {
+            self.buttons
+                .values_mut()
+                .for_each(|a| a.retain(|&b| b != action));
+            self.axes
+                .values_mut()
+                .for_each(|a| a.retain(|&b| b != action));
+        }
+    }
+
+    /// Bind `input` to `action`, dropping what it did before unless it's one of the
+    /// actions in `keep`, the ones the same keymap bound already
+    fn bind(
+        &mut self,
+        section: &str,
+        input: &str,
+        action: Action,
+        keep: &[Action],
+    ) -> Result<(), String> {
+        let rebind = |actions: &mut Vec<Action>| {
+            actions.retain(|a| keep.contains(a));
+            actions.push(action);
+        };
+        if section == "keyboard" {
+            let key = Keycode::from_name(input).ok_or_else(|| format!("unknown key {}", input))?;
+            rebind(self.keys.entry(key).or_default());
+            return Ok(());
+        }
+        if section != "controller" {
+            return Err(format!("{} isn't in a section", input));
+        }
+
+        if let Some(button) = PadButton::from_string(input) {
+            rebind(self.buttons.entry(button).or_default());
+            return Ok(());
+        }
+        let (name, positive) = match input.split_at(input.len().saturating_sub(1)) {
+            (name, "+") => (name, true),
+            (name, "-") => (name, false),
+            _ => return Err(format!("unknown button {}", input)),
+        };
+        let axis = Axis::from_string(name).ok_or_else(|| format!("unknown axis {}", name))?;
+        rebind(self.axes.entry((axis, positive)).or_default());
+        Ok(())
+    }
+
+    fn bound(&self, input: Input) -> Vec<Action> {
+        let actions = match input {
+            Input::Key(key) => self.keys.get(&key),
+            Input::Button(_, button) => self.buttons.get(&button),
+            Input::Axis(_, axis, positive) => self.axes.get(&(axis, positive)),
+        };
+        actions.cloned().unwrap_or_default()
+    }
+
+    /// An action is pressed by the first input holding it
+    fn press(&mut self, input: Input) -> Vec<(Action, bool)> {
+        if !self.held.insert(input) {
+            return Vec::new();
+        }
+        let mut actions = Vec::new();
+        for action in self.bound(input) {
+            let count = self.holding.entry(action).or_default();
+            *count += 1;
+            if *count == 1 {
+                actions.push((action, true));
+            }
+        }
+        actions
+    }
+
+    /// An action is released when the last input holding it lets go
+    fn release(&mut self, input: Input) -> Vec<(Action, bool)> {
+        let was_held = self.held.remove(&input);
+        let mut actions = Vec::new();
+        for action in self.bound(input) {
+            let count = self.holding.entry(action).or_default();
+            if was_held {
+                *count = count.saturating_sub(1);
+            }
+            if *count == 0 {
+                actions.push((action, false));
+            }
+        }
+        actions
+    }
+
+    /// Actions pressed (true) or released (false) by an event
+    pub fn actions(&mut self, event: &Event) -> Vec<(Action, bool)> {
+        match *event {
+            Event::KeyDown {
+                keycode: Some(key),
+                repeat: false,
+                ..
+            } => self.press(Input::Key(key)),
+            Event::KeyUp {
+                keycode: Some(key), ..
+            } => self.release(Input::Key(key)),
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.press(Input::Button(which, button))
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.release(Input::Button(which, button))
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                let mut actions = Vec::new();
+                for positive in [false, true] {
+                    let input = Input::Axis(which, axis, positive);
+                    // Axes send a stream of motion, only changes press or release
+                    if axis_pressed(value, positive, self.deadzone) {
+                        actions.extend(self.press(input));
+                    } else if self.held.contains(&input) {
+                        actions.extend(self.release(input));
+                    }
+                }
+                actions
+            }
+            // Unplugging a controller lets go of everything it held
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let inputs: Vec<Input> = self
+                    .held
+                    .iter()
+                    .copied()
+                    .filter(|input| match *input {
+                        Input::Button(id, _) | Input::Axis(id, _, _) => id == which,
+                        Input::Key(_) => false,
+                    })
+                    .collect();
+                inputs
+                    .into_iter()
+                    .flat_map(|input| self.release(input))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn axis_pressed(value: i16, positive: bool, deadzone: i16) -> bool {
+    if positive {
+        value > deadzone
+    } else {
+        (value as i32) < -(deadzone as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_down(key: Keycode) -> Event {
+        Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(key),
+            scancode: None,
+            keymod: sdl2::keyboard::Mod::NOMOD,
+            repeat: false,
+        }
+    }
+
+    fn key_up(key: Keycode) -> Event {
+        Event::KeyUp {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(key),
+            scancode: None,
+            keymod: sdl2::keyboard::Mod::NOMOD,
+            repeat: false,
+        }
+    }
+
+    fn axis(axis: Axis, value: i16) -> Event {
+        axis_of(0, axis, value)
+    }
+
+    fn axis_of(which: u32, axis: Axis, value: i16) -> Event {
+        Event::ControllerAxisMotion {
+            timestamp: 0,
+            which,
+            axis,
+            value,
+        }
+    }
+
+    const A: Action = Action::Joypad(GbKeyEvent::Button(Button::A));
+    const LEFT: Action = Action::Joypad(GbKeyEvent::Dpad(DpadDirection::Left));
+
+    #[test]
+    fn keymap_file() {
+        let mut keymap = Keymap::new();
+        let parse = |text: &str| toml::from_str::<Table>(text).unwrap();
+        keymap
+            .apply(&parse(
+                "[keyboard]\na = [\"K\", \"L\"] # two keys\n[controller]\ndeadzone = 100\n",
+            ))
+            .unwrap();
+        assert_eq!(keymap.actions(&key_down(Keycode::X)), []);
+        assert_eq!(keymap.actions(&key_down(Keycode::K)), [(A, true)]);
+        // A stays down until both keys are up
+        assert_eq!(keymap.actions(&key_down(Keycode::L)), []);
+        assert_eq!(keymap.actions(&key_up(Keycode::K)), []);
+        assert_eq!(keymap.actions(&key_up(Keycode::L)), [(A, false)]);
+        assert_eq!(
+            keymap.actions(&key_down(Keycode::F5)),
+            [(Action::SaveState, true)]
+        );
+
+        // Space pauses by default, now it only fast forwards
+        keymap
+            .apply(&parse("[keyboard]\nfast_forward = \"Space\""))
+            .unwrap();
+        assert_eq!(
+            keymap.actions(&key_down(Keycode::Space)),
+            [(Action::FastForward, true)]
+        );
+        // Unless the same keymap binds both
+        keymap
+            .apply(&parse("[keyboard]\npause = \"P\"\nscreenshot = \"P\""))
+            .unwrap();
+        assert_eq!(keymap.actions(&key_down(Keycode::P)).len(), 2);
+
+        assert!(keymap.apply(&parse("[keyboard]\njump = \"J\"")).is_err());
+        assert!(keymap.apply(&parse("[keyboard]\na = \"NotAKey\"")).is_err());
+        assert!(keymap.apply(&parse("[controller]\na = \"leftx\"")).is_err());
+    }
+
+    #[test]
+    fn axes() {
+        let mut keymap = Keymap::new();
+        assert_eq!(keymap.actions(&axis(Axis::LeftX, -5000)), []);
+        assert_eq!(keymap.actions(&axis(Axis::LeftX, -9000)), [(LEFT, true)]);
+        // Only changes count
+        assert_eq!(keymap.actions(&axis(Axis::LeftX, -10000)), []);
+        assert_eq!(keymap.actions(&axis(Axis::LeftX, 0)), [(LEFT, false)]);
+
+        // Each controller's stick is its own
+        assert_eq!(
+            keymap.actions(&axis_of(1, Axis::LeftX, -9000)),
+            [(LEFT, true)]
+        );
+        assert_eq!(keymap.actions(&axis_of(2, Axis::LeftX, -9000)), []);
+        // Unplugging one leaves Left held by the other
+        let removed = |which| Event::ControllerDeviceRemoved {
+            timestamp: 0,
+            which,
+        };
+        assert_eq!(keymap.actions(&removed(2)), []);
+        assert_eq!(keymap.actions(&axis_of(1, Axis::LeftX, 0)), [(LEFT, false)]);
+        // and lets go of what only it held
+        keymap.actions(&axis_of(1, Axis::LeftX, -9000));
+        assert_eq!(keymap.actions(&removed(1)), [(LEFT, false)]);
+        assert_eq!(keymap.actions(&removed(1)), []);
+
+        // Up is on both the D-pad and the stick
+        let up = Action::Joypad(GbKeyEvent::Dpad(DpadDirection::Up));
+        let dpup = |down: bool| {
+            let button = PadButton::DPadUp;
+            if down {
+                Event::ControllerButtonDown {
+                    timestamp: 0,
+                    which: 0,
+                    button,
+                }
+            } else {
+                Event::ControllerButtonUp {
+                    timestamp: 0,
+                    which: 0,
+                    button,
+                }
+            }
+        };
+        assert_eq!(keymap.actions(&dpup(true)), [(up, true)]);
+        assert_eq!(keymap.actions(&axis(Axis::LeftY, -9000)), []);
+        assert_eq!(keymap.actions(&dpup(false)), []);
+        assert_eq!(keymap.actions(&axis(Axis::LeftY, 0)), [(up, false)]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::graphics::LineRegs;
+
+// The PPU keeps the registers of every line of the last frame, for finding out what
+// a raster effect did on which line
+
+const INTERRUPTS: [&str; 5] = ["vblank", "stat", "timer", "serial", "joypad"];
+
+/// Names of the interrupts in IF bits, like "vblank|timer"
+fn interrupt_names(bits: u8) -> String {
+    INTERRUPTS
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| bits & (1 << i) > 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Lines from `first` to `last` as a table
+pub fn table(lines: &[LineRegs], first: usize, last: usize) -> String {
+    let mut out = " LY LCDC STAT SCX SCY  WX  WY BGP OBP0 OBP1 interrupts".to_string();
+    for (ly, regs) in lines.iter().enumerate().take(last + 1).skip(first) {
+        let _ = write!(
+            out,
+            "\n{:3}   {:02X}   {:02X}  {:02X}  {:02X}  {:02X}  {:02X}  {:02X}   {:02X}   {:02X} {}",
+            ly,
+            regs.lcdc,
+            regs.stat,
+            regs.scx,
+            regs.scy,
+            regs.wx,
+            regs.wy,
+            regs.bgp,
+            regs.obp0,
+            regs.obp1,
+            interrupt_names(regs.interrupts)
+        );
+    }
+    out
+}
+
+/// One row per line with the registers in hex
+pub fn csv(lines: &[LineRegs]) -> String {
+    let mut out = "ly,lcdc,stat,scx,scy,wx,wy,bgp,obp0,obp1,interrupts\n".to_string();
+    for (ly, regs) in lines.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "{},{:02X},{:02X},{:02X},{:02X},{:02X},{:02X},{:02X},{:02X},{:02X},{}",
+            ly,
+            regs.lcdc,
+            regs.stat,
+            regs.scx,
+            regs.scy,
+            regs.wx,
+            regs.wy,
+            regs.bgp,
+            regs.obp0,
+            regs.obp1,
+            interrupt_names(regs.interrupts)
+        );
+    }
+    out
+}
+
+pub fn save_csv(lines: &[LineRegs], path: &Path) -> Result<(), String> {
+    fs::write(path, csv(lines)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let mut lines = [LineRegs::default(); 3];
+        lines[1] = LineRegs {
+            lcdc: 0x91,
+            stat: 0x83,
+            scx: 0x10,
+            bgp: 0xE4,
+            interrupts: 0b10101,
+            ..LineRegs::default()
+        };
+        assert_eq!(
+            csv(&lines).lines().nth(2),
+            Some("1,91,83,10,00,00,00,E4,00,00,vblank|timer|joypad")
+        );
+        assert_eq!(csv(&lines).lines().count(), 4);
+
+        let table = table(&lines, 1, 1);
+        assert_eq!(
+            table.lines().collect::<Vec<_>>(),
+            [
+                " LY LCDC STAT SCX SCY  WX  WY BGP OBP0 OBP1 interrupts",
+                "  1   91   83  10  00  00  00  E4   00   00 vblank|timer|joypad",
+            ]
+        );
+    }
+}
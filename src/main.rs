@@ -1,130 +1,729 @@
 mod buttons;
+mod cartridge;
+mod cheats;
+mod config;
 mod cpu;
+mod debugger;
+mod decode;
+mod display;
+mod filter;
 mod graphics;
+mod image;
+mod input;
+mod line_log;
 mod memory;
+mod movie;
+mod palette;
+mod printer;
+mod profiler;
+mod ram_search;
 mod register;
+mod serial;
+mod sgb;
 mod sound;
+mod timer;
+mod vram_viewer;
 
+use std::collections::VecDeque;
 use std::env;
-use std::time::Duration;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
 
-use buttons::{Button::*, DpadDirection::*, GbKeyEvent};
+use buttons::{Btns, TURBO_RATE};
+use cartridge::Cartridge;
+use cheats::Cheats;
+use config::Settings;
 use cpu::Cpu;
-use graphics::{HEIGHT, WIDTH};
+use debugger::Debugger;
+use filter::PostFilter;
+use graphics::{Rgb, HEIGHT, WIDTH};
+use input::{Action, Keymap};
 use memory::Mmu;
+use movie::{Player, Recorder};
+use palette::{DmgPalette, PRESETS};
+use printer::Printer;
+use profiler::{Profiler, Symbols};
+use sgb::{BORDER_HEIGHT, BORDER_WIDTH};
+
+/// 70224 T-cycles at 4.194304 MHz
+const FRAME_TIME: Duration = Duration::from_nanos(16_742_706);
+
+// Rewinding goes back through a save state every 5 frames, up to 10 seconds
+const REWIND_INTERVAL: u64 = 5;
+const REWIND_STATES: usize = 120;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let file_path = &args[1];
 
+    // Options, they take precedence over the config file
+    let mut cli = Settings::default();
+    let mut config_path = None;
+    let mut printer_dir = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut trace = false;
+    let mut debug = false;
+    let mut headless = None;
+    let mut vram_dump = None;
+    let mut line_log_path = None;
+    let mut profile_dir = None;
+    let mut symbols_path = None;
+    let mut cheat_codes = Vec::new();
+    let mut opts = args[2..].iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_str() {
+            "--config" => config_path = opts.next().map(PathBuf::from),
+            "--printer" => printer_dir = opts.next().map(PathBuf::from),
+            "--boot-rom" => cli.boot_rom = opts.next().map(PathBuf::from),
+            "--save-dir" => cli.save_dir = opts.next().map(PathBuf::from),
+            "--sgb" => cli.sgb = Some(true),
+            "--palette" => cli.palette = opts.next().cloned(),
+            "--scale" => {
+                cli.scale = Some(
+                    opts.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .expect("--scale needs a positive whole number"),
+                )
+            }
+            "--stretch" => cli.stretch = Some(true),
+            "--filter" => cli
+                .filters
+                .get_or_insert_with(Vec::new)
+                .extend(opts.next().cloned()),
+            "--keymap" => {
+                let path = opts.next().expect("--keymap needs a file");
+                let keymap =
+                    config::load_table(Path::new(path)).unwrap_or_else(|e| panic!("{}", e));
+                cli.keymap = Some(keymap);
+            }
+            "--block-opposite" => cli.block_opposite = Some(true),
+            "--turbo-rate" => {
+                cli.turbo_rate = Some(
+                    opts.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .expect("--turbo-rate needs a positive whole number"),
+                )
+            }
+            "--record" => record_path = opts.next().map(PathBuf::from),
+            "--play" => play_path = opts.next().map(PathBuf::from),
+            "--trace" => trace = true,
+            "--debug" => debug = true,
+            "--headless" => {
+                headless = Some(
+                    opts.next()
+                        .and_then(|n| n.parse::<u64>().ok())
+                        .expect("--headless needs a number of frames"),
+                )
+            }
+            "--vram-dump" => vram_dump = opts.next().map(PathBuf::from),
+            "--line-log" => line_log_path = opts.next().map(PathBuf::from),
+            "--profile" => profile_dir = opts.next().map(PathBuf::from),
+            "--symbols" => symbols_path = opts.next().map(PathBuf::from),
+            "--cheat" => cheat_codes.extend(opts.next().cloned()),
+            _ => panic!("Unknown option: {}", opt),
+        }
+    }
+
+    // Init Gb, the game's settings depend on its header
+    let mut mem = Mmu::new();
+    mem.load_rom(file_path);
+    let settings =
+        load_settings(config_path.as_deref(), &cli, mem.cart()).unwrap_or_else(|e| panic!("{}", e));
+    let sgb = settings.sgb.unwrap_or(false);
+    if sgb {
+        mem.enable_sgb();
+    }
+    if let Some(path) = &settings.boot_rom {
+        if let Err(e) = mem.load_boot_rom(path) {
+            eprintln!("failed to load the boot ROM, starting without it: {}", e);
+        }
+    }
+    if let Some(dir) = printer_dir {
+        mem.serial.connect(Box::new(Printer::new(dir)));
+    }
+    let save_path = mem
+        .cart()
+        .battery()
+        .then(|| game_file_path(&settings, Path::new(file_path), "sav"));
+    if let Some(path) = &save_path {
+        load_battery(mem.cart_mut(), path);
+    }
+    let cheats_path = game_file_path(&settings, Path::new(file_path), "cheats.toml");
+    mem.cheats = Cheats::load(&cheats_path).unwrap_or_else(|e| panic!("{}", e));
+    for code in &cheat_codes {
+        mem.cheats.add(code, "").unwrap_or_else(|e| panic!("{}", e));
+    }
+    for (i, cheat) in mem.cheats.list().iter().enumerate() {
+        println!("Cheat {}: {}", i + 1, cheat);
+    }
+
+    // DMG palettes to cycle through, the game's CGB palette and a palette file go last
+    let mut palettes = palette_list(mem.cart());
+    let mut palette_index = choose_palette(&mut palettes, settings.palette.as_deref())
+        .unwrap_or_else(|e| panic!("{}", e));
+    mem.gpu.palette = palettes[palette_index].1;
+    set_buttons(&mut mem.btns, &settings);
+
+    // Input movies
+    let mut recorder = record_path.map(|path| {
+        Recorder::create(&path)
+            .unwrap_or_else(|e| panic!("failed to create {}: {}", path.display(), e))
+    });
+    let mut player = play_path.map(|path| {
+        Player::open(&path).unwrap_or_else(|e| panic!("failed to open {}: {}", path.display(), e))
+    });
+
+    // The profiler names functions after the labels in a .sym file, the one next to
+    // the ROM by default
+    let profiler = profile_dir.is_some().then(|| {
+        let symbols = match symbols_path {
+            Some(path) => Symbols::load(&path).unwrap_or_else(|e| panic!("{}", e)),
+            None => {
+                let path = Path::new(file_path).with_extension("sym");
+                if path.exists() {
+                    Symbols::load(&path).unwrap_or_else(|e| panic!("{}", e))
+                } else {
+                    Symbols::default()
+                }
+            }
+        };
+        let title = mem.cart().title();
+        Profiler::new(if title.is_empty() { "game" } else { &title }, symbols)
+    });
+
+    let mut cpu = Cpu::from(mem);
+    cpu.trace = trace;
+    cpu.profiler = profiler;
+
+    // Without a window the game runs as fast as it can for a number of frames
+    if let Some(frames) = headless {
+        for _ in 0..frames {
+            match player.as_mut().map(|player| player.next_frame()) {
+                Some(Some(state)) => cpu.membus.btns.set_state(state),
+                Some(None) => {
+                    cpu.membus.btns.release_all();
+                    player = None;
+                }
+                None => {}
+            }
+            while !cpu.membus.gpu.frame_ready() {
+                cpu.cycle();
+            }
+            // Nothing will happen anymore, scripts should know it failed
+            if cpu.locked().is_some() {
+                break;
+            }
+        }
+        shut_down(
+            &cpu,
+            save_path.as_deref(),
+            &cheats_path,
+            vram_dump.as_deref(),
+            line_log_path.as_deref(),
+            profile_dir.as_deref(),
+        );
+        if let Some(pc) = cpu.locked() {
+            eprintln!("Stopped, the CPU locked up at {:04X}", pc);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Init SDL
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    let mut controllers = Vec::new();
+    let mut keymap = build_keymap(&settings).unwrap_or_else(|e| panic!("{}", e));
 
+    let (width, height) = if sgb {
+        (BORDER_WIDTH, BORDER_HEIGHT)
+    } else {
+        (WIDTH, HEIGHT)
+    };
+    let mut scale = scale(&settings).unwrap_or_else(|e| panic!("{}", e));
+    let mut stretch = settings.stretch.unwrap_or(false);
     let window = video_subsystem
-        .window("Game Boy", WIDTH, HEIGHT)
+        .window("Game Boy", width * scale, height * scale)
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
 
     let mut canvas = window.into_canvas().build().unwrap();
     canvas.clear();
     canvas.present();
-
-    // Init Gb
-    let mut mem = Mmu::new();
-    mem.load_rom(file_path);
-    let mut cpu = Cpu::from(mem);
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width * scale, height * scale)
+        .unwrap();
+    let mut filter = build_filter(&settings, scale).unwrap_or_else(|e| panic!("{}", e));
 
     // Game loop
     let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut paused = false;
+    let mut fast_forward = false;
+    let mut rewinding = false;
+    let mut save_state = None;
+    let mut rewind: VecDeque<Cpu> = VecDeque::new();
+    let mut frames: u64 = 0;
+    let mut locked = None;
+    let mut debugger = Debugger::new();
+    let mut vram_window: Option<Canvas<Window>> = None;
+    if debug {
+        debugger.enter(&cpu);
+    }
     'running: loop {
+        let frame_start = Instant::now();
+        let mut take_screenshot = false;
+
         // Handle events
         for event in event_pump.poll_iter() {
             match event {
-                // Quit
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                // Controls (Buttons)
-                Event::KeyDown {
-                    keycode: Some(Keycode::X),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Button(A)),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Z),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Button(B)),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Return),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Button(Start)),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Backspace),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Button(Select)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::X),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Button(A)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Z),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Button(B)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Return),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Button(Start)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Backspace),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Button(Select)),
-                // Dpad
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
+                Event::Quit { .. } => break 'running,
+                // With the VRAM viewer open, closing either window isn't a quit
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
                     ..
-                } => cpu.membus.btns.press(GbKeyEvent::Dpad(Down)),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Dpad(Up)),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Dpad(Left)),
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => cpu.membus.btns.press(GbKeyEvent::Dpad(Right)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Down),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Dpad(Down)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Dpad(Up)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Dpad(Left)),
-                Event::KeyUp {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => cpu.membus.btns.release(GbKeyEvent::Dpad(Right)),
+                } => {
+                    if vram_window.as_ref().map(|c| c.window().id()) == Some(window_id) {
+                        vram_window = None;
+                    } else {
+                        break 'running;
+                    }
+                }
+                // Controllers already plugged in are added at startup too
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("Controller connected: {}", controller.name());
+                            controllers.push(controller);
+                        }
+                        Err(e) => eprintln!("Failed to open controller: {}", e),
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.retain(|c| c.instance_id() != which)
+                }
                 _ => {}
             }
+
+            for (action, pressed) in keymap.actions(&event) {
+                // A movie being played or recorded can't jump back in time
+                let movie = player.is_some() || recorder.is_some();
+                match action {
+                    // The movie being played has the joypad
+                    Action::Joypad(_) | Action::Turbo(_) if player.is_some() => {}
+                    Action::Joypad(key) if pressed => cpu.membus.btns.press(key),
+                    Action::Joypad(key) => cpu.membus.btns.release(key),
+                    Action::Turbo(btn) if pressed => cpu.membus.btns.press_turbo(btn),
+                    Action::Turbo(btn) => cpu.membus.btns.release_turbo(btn),
+                    Action::FastForward => fast_forward = pressed,
+                    Action::Rewind => rewinding = pressed && !movie,
+                    // The rest happen on press
+                    _ if !pressed => {}
+                    Action::Quit => break 'running,
+                    Action::Pause => paused = !paused,
+                    Action::SaveState => save_state = Some(cpu.clone()),
+                    Action::LoadState if movie => {}
+                    Action::LoadState => {
+                        if let Some(state) = &save_state {
+                            cpu.restore(state);
+                        }
+                    }
+                    Action::Screenshot => take_screenshot = true,
+                    Action::Fullscreen => {
+                        let window = canvas.window_mut();
+                        let fullscreen = match window.fullscreen_state() {
+                            FullscreenType::Off => FullscreenType::Desktop,
+                            _ => FullscreenType::Off,
+                        };
+                        window.set_fullscreen(fullscreen).unwrap();
+                    }
+                    Action::NextPalette => {
+                        palette_index = (palette_index + 1) % palettes.len();
+                        let (name, palette) = &palettes[palette_index];
+                        cpu.membus.gpu.palette = *palette;
+                        if locked.is_none() {
+                            let title = format!("Game Boy ({} palette)", name);
+                            canvas.window_mut().set_title(&title).unwrap();
+                        }
+                    }
+                    Action::Grid => filter.next_grid(),
+                    Action::Ghosting => filter.ghosting = !filter.ghosting,
+                    Action::Tint => filter.tint = !filter.tint,
+                    Action::ColorCorrection => filter.color_correction = !filter.color_correction,
+                    Action::VramViewer if vram_window.is_some() => vram_window = None,
+                    Action::VramViewer => {
+                        let image = vram_viewer::overview(&cpu.membus.gpu);
+                        let window = video_subsystem
+                            .window("VRAM", image.width as u32, image.height() as u32)
+                            .resizable()
+                            .build()
+                            .unwrap();
+                        vram_window = Some(window.into_canvas().build().unwrap());
+                        println!("{}", vram_viewer::oam_table(&cpu.membus.gpu));
+                    }
+                    Action::Debug if debugger.active => debugger.active = false,
+                    Action::Debug => debugger.enter(&cpu),
+                    Action::Cheat(i) => match cpu.membus.cheats.toggle(i) {
+                        Some(cheat) => println!("Cheat {}: {}", i + 1, cheat),
+                        None => println!("No cheat {}", i + 1),
+                    },
+                    // The SGB, boot ROM and save directory only apply at startup
+                    Action::ReloadConfig => {
+                        let mem = &mut cpu.membus;
+                        let reloaded = load_settings(config_path.as_deref(), &cli, mem.cart())
+                            .and_then(|settings| {
+                                let mut new_palettes = palette_list(mem.cart());
+                                let index =
+                                    choose_palette(&mut new_palettes, settings.palette.as_deref())?;
+                                let new_scale = self::scale(&settings)?;
+                                Ok((
+                                    build_keymap(&settings)?,
+                                    build_filter(&settings, new_scale)?,
+                                    new_palettes,
+                                    index,
+                                    new_scale,
+                                    settings,
+                                ))
+                            });
+                        match reloaded {
+                            Ok((
+                                new_keymap,
+                                new_filter,
+                                new_palettes,
+                                index,
+                                new_scale,
+                                settings,
+                            )) => {
+                                keymap = new_keymap;
+                                filter = new_filter;
+                                palettes = new_palettes;
+                                palette_index = index;
+                                mem.gpu.palette = palettes[palette_index].1;
+                                stretch = settings.stretch.unwrap_or(false);
+                                set_buttons(&mut mem.btns, &settings);
+                                if new_scale != scale {
+                                    scale = new_scale;
+                                    let window = canvas.window_mut();
+                                    if let Err(e) = window.set_size(width * scale, height * scale) {
+                                        eprintln!("failed to resize window: {}", e);
+                                    }
+                                }
+                                println!("Reloaded config");
+                            }
+                            Err(e) => eprintln!("failed to reload config: {}", e),
+                        }
+                    }
+                }
+            }
         }
 
-        // Cycle device
-        cpu.cycle();
+        debugger.poll(&mut cpu);
+        if rewinding {
+            if let Some(state) = rewind.pop_back() {
+                cpu.restore(&state);
+            }
+        } else if !paused && !debugger.active {
+            let btns = &mut cpu.membus.btns;
+            match player.as_mut().map(|player| player.next_frame()) {
+                Some(Some(state)) => btns.set_state(state),
+                // Live input takes over from nothing held, keys pressed during
+                // playback count from their next press
+                Some(None) => {
+                    println!("Movie finished");
+                    btns.release_all();
+                    player = None;
+                }
+                None => btns.turbo_frame(),
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.record(btns.state());
+            }
+
+            // Cycle device until the next frame is done
+            while !cpu.membus.gpu.frame_ready() {
+                cpu.cycle();
+            }
+
+            frames += 1;
+            if cpu.locked() != locked {
+                // Rewinding or loading a state can unlock it again
+                locked = cpu.locked();
+                let title = match locked {
+                    Some(pc) => format!("Game Boy (locked up at {:04X})", pc),
+                    None => "Game Boy".to_string(),
+                };
+                canvas.window_mut().set_title(&title).unwrap();
+            }
+            if frames.is_multiple_of(REWIND_INTERVAL) {
+                if rewind.len() == REWIND_STATES {
+                    rewind.pop_front();
+                }
+                rewind.push_back(cpu.clone());
+            }
+        }
 
+        let mem = &mut cpu.membus;
+        match mem.sgb.as_mut() {
+            Some(sgb) => {
+                let frame = sgb.render(mem.gpu.shades());
+                if take_screenshot {
+                    screenshot(frame);
+                }
+                draw_frame(
+                    &mut canvas,
+                    &texture_creator,
+                    &mut texture,
+                    &mut filter,
+                    frame,
+                    stretch,
+                );
+            }
+            None => {
+                if take_screenshot {
+                    screenshot(mem.gpu.frame());
+                }
+                draw_frame(
+                    &mut canvas,
+                    &texture_creator,
+                    &mut texture,
+                    &mut filter,
+                    mem.gpu.frame(),
+                    stretch,
+                );
+            }
+        }
         canvas.present();
-        std::thread::sleep(Duration::from_nanos(238));
+        if let Some(canvas) = &mut vram_window {
+            draw_image(canvas, &vram_viewer::overview(&cpu.membus.gpu));
+        }
+        if !fast_forward {
+            std::thread::sleep(FRAME_TIME.saturating_sub(frame_start.elapsed()));
+        }
+    }
+
+    shut_down(
+        &cpu,
+        save_path.as_deref(),
+        &cheats_path,
+        vram_dump.as_deref(),
+        line_log_path.as_deref(),
+        profile_dir.as_deref(),
+    );
+}
+
+/// Draw a debug image as big as it fits, in a texture that's made each frame
+fn draw_image(canvas: &mut Canvas<Window>, image: &vram_viewer::Image) {
+    let (width, height) = (image.width as u32, image.height() as u32);
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+        .unwrap();
+    texture
+        .with_lock(None, |buf, pitch| {
+            display::copy_pixels(&image.pixels, image.width, buf, pitch)
+        })
+        .unwrap();
+    canvas.set_draw_color(Color::BLACK);
+    canvas.clear();
+    let window = canvas.output_size().unwrap();
+    let dst = display::viewport(window, (width, height), false);
+    canvas.copy(&texture, None, dst).unwrap();
+    canvas.present();
+}
+
+/// Write what's kept when the emulator quits
+fn shut_down(
+    cpu: &Cpu,
+    save_path: Option<&Path>,
+    cheats_path: &Path,
+    vram_dump: Option<&Path>,
+    line_log_path: Option<&Path>,
+    profile_dir: Option<&Path>,
+) {
+    if let Some(path) = save_path {
+        save_battery(cpu.membus.cart(), path);
+    }
+    if !cpu.membus.cheats.is_empty() {
+        if let Err(e) = cpu.membus.cheats.save(cheats_path) {
+            eprintln!("failed to save cheats: {}", e);
+        }
+    }
+    if let Some(dir) = vram_dump {
+        match vram_viewer::dump(&cpu.membus.gpu, dir) {
+            Ok(()) => println!("Dumped VRAM to {}", dir.display()),
+            Err(e) => eprintln!("failed to dump VRAM: {}", e),
+        }
+    }
+    if let Some(path) = line_log_path {
+        if let Err(e) = line_log::save_csv(cpu.membus.gpu.line_log(), path) {
+            eprintln!("failed to save the line log: {}", e);
+        }
+    }
+    if let (Some(profiler), Some(dir)) = (&cpu.profiler, profile_dir) {
+        match profiler.save(dir) {
+            Ok(()) => println!("Saved the profile to {}", dir.display()),
+            Err(e) => eprintln!("failed to save the profile: {}", e),
+        }
+    }
+}
+
+/// Global config, from `path` or the default one, then the game's overrides, then the
+/// command line
+fn load_settings(
+    path: Option<&Path>,
+    cli: &Settings,
+    cart: &Cartridge,
+) -> Result<Settings, String> {
+    let config = match path {
+        Some(path) => config::load(path)?,
+        None => config::load_default()?,
+    };
+    Ok(config.for_game(cart).merge(cli))
+}
+
+fn set_buttons(btns: &mut Btns, settings: &Settings) {
+    btns.block_opposite = settings.block_opposite.unwrap_or(false);
+    btns.turbo_rate = settings.turbo_rate.unwrap_or(TURBO_RATE);
+}
+
+fn scale(settings: &Settings) -> Result<u32, String> {
+    match settings.scale {
+        Some(0) => Err("scale needs to be at least 1".to_string()),
+        scale => Ok(scale.unwrap_or(3)),
     }
 }
+
+fn build_keymap(settings: &Settings) -> Result<Keymap, String> {
+    let mut keymap = Keymap::new();
+    if let Some(table) = &settings.keymap {
+        keymap.apply(table)?;
+    }
+    Ok(keymap)
+}
+
+fn build_filter(settings: &Settings, scale: u32) -> Result<PostFilter, String> {
+    let mut filter = PostFilter::new(scale as usize);
+    for name in settings.filters.iter().flatten() {
+        filter.set(name)?;
+    }
+    Ok(filter)
+}
+
+fn palette_list(cart: &Cartridge) -> Vec<(String, DmgPalette)> {
+    let mut palettes: Vec<(String, DmgPalette)> = PRESETS
+        .iter()
+        .map(|&(name, palette)| (name.to_string(), palette))
+        .collect();
+    palettes.push(("auto".to_string(), palette::auto(cart)));
+    palettes
+}
+
+/// Index of a preset, or of a palette file added to the list
+fn choose_palette(
+    palettes: &mut Vec<(String, DmgPalette)>,
+    choice: Option<&str>,
+) -> Result<usize, String> {
+    let Some(choice) = choice else {
+        return Ok(0);
+    };
+    if let Some(i) = palettes.iter().position(|(name, _)| name == choice) {
+        return Ok(i);
+    }
+    let palette = palette::load(Path::new(choice))?;
+    palettes.push((choice.to_string(), palette));
+    Ok(palettes.len() - 1)
+}
+
+/// The ROM's name with another extension, next to the ROM unless there's a save directory
+fn game_file_path(settings: &Settings, rom: &Path, extension: &str) -> PathBuf {
+    let dir = match &settings.save_dir {
+        Some(dir) => dir.as_path(),
+        None => rom.parent().unwrap_or(Path::new("")),
+    };
+    dir.join(rom.file_stem().unwrap_or_default())
+        .with_extension(extension)
+}
+
+fn load_battery(cart: &mut Cartridge, path: &Path) {
+    match fs::read(path) {
+        Ok(data) if !cart.load_ram(&data) => {
+            eprintln!("{} doesn't match the cartridge RAM size", path.display())
+        }
+        Ok(_) => println!("Loaded {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("failed to load {}: {}", path.display(), e),
+    }
+}
+
+fn save_battery(cart: &Cartridge, path: &Path) {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Err(e) = fs::write(path, cart.ram()) {
+        eprintln!("failed to save {}: {}", path.display(), e);
+    }
+}
+
+/// Save a frame as the next free screenshot_NNN.png
+fn screenshot<const W: usize>(frame: &[[Rgb; W]]) {
+    let path = (1..)
+        .map(|i| PathBuf::from(format!("screenshot_{:03}.png", i)))
+        .find(|path| !path.exists())
+        .unwrap();
+    match image::write_frame(&path, frame) {
+        Ok(()) => println!("Saved {}", path.display()),
+        Err(e) => eprintln!("failed to save screenshot {}: {}", path.display(), e),
+    }
+}
+
+fn draw_frame<'t, const W: usize>(
+    canvas: &mut Canvas<Window>,
+    texture_creator: &'t TextureCreator<WindowContext>,
+    texture: &mut Texture<'t>,
+    filter: &mut PostFilter,
+    frame: &[[Rgb; W]],
+    stretch: bool,
+) {
+    let window = canvas.output_size().unwrap();
+    let frame_size = (W as u32, frame.len() as u32);
+    let dst = display::viewport(window, frame_size, stretch);
+    // Upscale to the whole multiple the window shows it at, so grid lines stay one
+    // screen pixel wide through resizing and fullscreen
+    let scale = (dst.width() / frame_size.0).max(1);
+    filter.scale = scale as usize;
+    if texture.query().width != frame_size.0 * scale {
+        *texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                frame_size.0 * scale,
+                frame_size.1 * scale,
+            )
+            .unwrap();
+    }
+
+    let pixels = filter.apply(frame);
+    let width = texture.query().width as usize;
+    texture
+        .with_lock(None, |buf, pitch| {
+            display::copy_pixels(pixels, width, buf, pitch)
+        })
+        .unwrap();
+
+    // Black bars around the frame
+    canvas.set_draw_color(Color::BLACK);
+    canvas.clear();
+    canvas.copy(texture, None, dst).unwrap();
+}
@@ -1,94 +1,325 @@
 use std::fs;
+use std::path::Path;
 
 use crate::buttons::Btns;
+use crate::cartridge::Cartridge;
+use crate::cheats::Cheats;
 use crate::graphics::Gpu;
+use crate::serial::Serial;
+use crate::sgb::Sgb;
 use crate::sound::Apu;
+use crate::timer::Timer;
 
-struct Timer {
-    div: u8,
-    tima: u8,
-    tma: u8,
-    tac: u8,
-    running_div: u32,
-    running_counter: u32,
+/// OAM DMA in progress, one byte is copied per M-cycle
+#[derive(Clone)]
+struct Dma {
+    source: u16,
+    index: u16,
+    delay: u8, // M-cycles until the first byte is copied
 }
 
-impl Timer {
-    fn enabled(&self) -> bool {
-        self.tac & 0b100 > 0
+impl Dma {
+    fn running(&self) -> bool {
+        self.delay == 0
     }
 
-    fn step_size(&self) -> u32 {
-        match self.tac & 0b11 {
-            0b00 => 256,
-            0b01 => 4,
-            0b10 => 16,
-            _ => 64,
+    /// Address DMA is currently reading from
+    fn addr(&self) -> u16 {
+        self.source + self.index
+    }
+}
+
+/// Bits of IO registers that aren't wired up and always read as 1
+fn io_read_mask(addr: u16, cgb: bool) -> u8 {
+    match addr {
+        0xFF00 => 0xC0,
+        0xFF01 => 0x00,
+        0xFF02 => 0x7E,
+        0xFF04..=0xFF06 => 0x00,
+        0xFF07 => 0xF8,
+        0xFF0F => 0xE0,
+        // Sound
+        0xFF10 => 0x80,
+        0xFF11 | 0xFF16 => 0x3F,
+        0xFF12 | 0xFF17 | 0xFF21 | 0xFF22 | 0xFF24 | 0xFF25 => 0x00,
+        0xFF14 | 0xFF19 | 0xFF1E | 0xFF23 => 0xBF,
+        0xFF1A => 0x7F,
+        0xFF1C => 0x9F,
+        0xFF26 => 0x70,
+        0xFF30..=0xFF3F => 0x00,
+        // LCD
+        0xFF40 => 0x00,
+        0xFF41 => 0x80,
+        0xFF42..=0xFF4B => 0x00,
+        // CGB
+        0xFF4D if cgb => 0x7E,
+        0xFF4F if cgb => 0xFE,
+        0xFF55 if cgb => 0x00,
+        0xFF56 if cgb => 0x3E, // infrared, nothing ever shines on the sensor
+        0xFF68 | 0xFF6A if cgb => 0x40,
+        0xFF69 | 0xFF6B if cgb => 0x00,
+        0xFF6C if cgb => 0xFE,
+        0xFF70 if cgb => 0xF8,
+        // Undocumented CGB registers
+        0xFF72 | 0xFF73 if cgb => 0x00,
+        0xFF74 if cgb => 0x00,
+        0xFF75 if cgb => 0x8F,
+        // Write only and unmapped registers
+        _ => 0xFF,
+    }
+}
+
+/// CGB VRAM DMA, all at once or 16 bytes per HBlank
+#[derive(Clone)]
+struct Hdma {
+    source: u16,
+    dest: u16,
+    blocks: u8, // 16 byte blocks left
+    hblank: bool,
+}
+
+impl Hdma {
+    /// HDMA5 reads the blocks left minus one, bit 7 clear while an HBlank DMA runs
+    fn status(&self) -> u8 {
+        let len = self.blocks.wrapping_sub(1) & 0x7F;
+        if self.hblank && self.blocks > 0 {
+            len
+        } else {
+            0x80 | len
         }
     }
 }
 
+/// VRAM sits on its own bus, everything else below OAM shares the external bus
+fn on_vram_bus(addr: u16) -> bool {
+    (0x8000..=0x9FFF).contains(&addr)
+}
+
+#[derive(Clone)]
 pub struct Mmu {
-    ram: [u8; 65535],
-    ie: u8,    // interrupt enable, seperate from the CPUs ime reg
-    iflag: u8, // interrupt flag
+    cgb: bool,
+    cart: Cartridge,
+    wram: [u8; 0x8000],
+    wram_bank: u8, // CGB bank mapped at 0xD000
+    hram: [u8; 0x7F],
+    io: [u8; 0x80],            // IO registers not handled by any device yet
+    boot_rom: Option<Vec<u8>>, // mapped over 0x0000-0x00FF until 0xFF50 is written
+    ie: u8,                    // interrupt enable, seperate from the CPUs ime reg
+    iflag: u8,                 // interrupt flag
     timer: Timer,
+    dma: Option<Dma>,
+    dma_reg: u8,
+    hdma: Hdma,
+    double_speed: bool, // CGB double speed mode
+    speed_armed: bool,  // KEY1.0, switch speed on the next STOP
     pub btns: Btns,
+    pub serial: Serial,
     pub gpu: Gpu,
     pub apu: Apu,
+    pub sgb: Option<Sgb>,
+    pub cheats: Cheats,
 }
 
 impl Mmu {
     pub fn new() -> Self {
         Mmu {
-            ram: [0; 65535],
+            cgb: false,
+            cart: Cartridge::new(Vec::new()),
+            wram: [0; 0x8000],
+            wram_bank: 1,
+            hram: [0; 0x7F],
+            io: [0; 0x80],
+            boot_rom: None,
             ie: 0xE1,
             iflag: 0,
-            timer: Timer {
-                div: 0xAB,
-                tima: 0,
-                tma: 0,
-                tac: 0xF8,
-                running_div: 0,
-                running_counter: 0,
+            timer: Timer::new(),
+            dma: None,
+            dma_reg: 0xFF,
+            hdma: Hdma {
+                source: 0,
+                dest: 0,
+                blocks: 0,
+                hblank: false,
             },
+            double_speed: false,
+            speed_armed: false,
             btns: Btns::new(),
+            serial: Serial::new(),
             gpu: Gpu::new(),
             apu: Apu::new(),
+            sgb: None,
+            cheats: Cheats::new(),
         }
     }
 
     pub fn load_rom(&mut self, file_path: &String) {
         let data = fs::read(file_path).expect("failed to open rom file");
+        self.insert(Cartridge::new(data));
+    }
 
-        for i in 0..0x7FFF {
-            self.ram[i] = data[i];
+    /// Put in a cartridge, CGB games start in CGB mode as the CGB boot ROM leaves it
+    pub fn insert(&mut self, cart: Cartridge) {
+        self.cart = cart;
+        self.set_cgb(self.cart.cgb());
+        if self.cgb {
+            self.cgb_post_boot();
         }
     }
 
+    /// IO registers after the CGB boot ROM hands over to a CGB game: single speed,
+    /// VRAM bank 0, WRAM bank 1 and all BG palettes white. LCDC, STAT and BGP are the
+    /// same as after the DMG boot ROM.
+    fn cgb_post_boot(&mut self) {
+        self.gpu.vram_bank = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_armed = false;
+        self.hdma.blocks = 0;
+        self.hdma.hblank = false;
+        // Written with auto increment, which wraps back around to 0
+        self.gpu.bcp.write_spec(0x80);
+        for _ in 0..32 {
+            self.gpu.bcp.write_data(0xFF, false);
+            self.gpu.bcp.write_data(0x7F, false);
+        }
+        for addr in [0xFF56, 0xFF6C, 0xFF72, 0xFF73, 0xFF74, 0xFF75] {
+            self.io[addr - 0xFF00] = 0;
+        }
+    }
+
+    /// Map the boot ROM and put the hardware in its power on state
+    pub fn load_boot_rom(&mut self, file_path: &Path) -> Result<(), String> {
+        let data = fs::read(file_path).map_err(|e| format!("{}: {}", file_path.display(), e))?;
+        if data.len() != 0x100 {
+            return Err(format!(
+                "{}: the boot ROM must be 256 bytes, not {}",
+                file_path.display(),
+                data.len()
+            ));
+        }
+        self.boot_rom = Some(data);
+        self.set_cgb(false);
+
+        self.ie = 0;
+        self.timer = Timer::zeroed();
+        self.gpu.lcdc = 0;
+        self.gpu.stat = 0;
+        self.gpu.bgp = 0;
+        Ok(())
+    }
+
+    /// Run as a Super Game Boy, which colors DMG games and draws a border
+    pub fn enable_sgb(&mut self) {
+        self.set_cgb(false);
+        self.sgb = Some(Sgb::new());
+    }
+
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn cart(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    pub fn cart_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.gpu.cgb = cgb;
+    }
+
+    fn wram_index(&self, addr: u16) -> usize {
+        let offset = addr as usize & 0x1FFF;
+        if offset < 0x1000 {
+            offset
+        } else {
+            self.wram_bank as usize * 0x1000 + offset - 0x1000
+        }
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
     /// Get the address of the interrupt to be serviced (if there is one)
-    pub fn interrupt_addr(&mut self) -> Option<u8> {
-        let requested = self.iflag & self.ie;
-        let addr = match requested {
-            0b00001 => 0x40, // VBlank
-            0b00010 => 0x48, // Lcd
-            0b00100 => 0x50, // Timer
-            0b01000 => 0x58, // Serial
-            0b10000 => 0x60, // Joypad
-            _ => return None,
-        };
-        self.iflag &= !requested;
-        Some(addr)
+    pub fn pending_interrupt(&self) -> Option<u8> {
+        // Lowest bit has the highest priority
+        let pending = self.iflag & self.ie & 0x1F;
+        (pending != 0).then(|| 0x40 + pending.trailing_zeros() as u8 * 8)
+    }
+
+    /// Clear the request of the interrupt at `addr`
+    pub fn ack_interrupt(&mut self, addr: u8) {
+        self.iflag &= !(1 << ((addr - 0x40) / 8));
     }
 
     pub fn read(&self, addr: u16) -> u8 {
+        if let Some(dma) = self.dma.as_ref().filter(|dma| dma.running()) {
+            match addr {
+                0xFE00..=0xFEFF => return 0xFF,
+                0xFF00..=0xFFFF => {}
+                // Bus conflict, the CPU sees the byte DMA is reading
+                _ if on_vram_bus(addr) == on_vram_bus(dma.source) => {
+                    return self.bus_read(dma.addr())
+                }
+                _ => {}
+            }
+        }
         match addr {
+            0x8000..=0x9FFF if !self.gpu.vram_accessible() => 0xFF,
+            0xFE00..=0xFEFF if !self.gpu.oam_accessible() => 0xFF,
+            _ => self.bus_read(addr),
+        }
+    }
+
+    /// Read for the debugger, past DMA, the PPU and disabled cartridge RAM
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xA000..=0xBFFF => self.cart.peek_ram(addr).unwrap_or(0xFF),
+            _ => self.bus_read(addr),
+        }
+    }
+
+    /// Write for the debugger, like `peek`
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xA000..=0xBFFF => self.cart.poke_ram(addr, val),
+            _ => self.bus_write(addr, val),
+        }
+    }
+
+    fn bus_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x00FF if self.boot_rom.is_some() => {
+                self.boot_rom.as_ref().unwrap()[addr as usize]
+            }
+            0x0000..=0x7FFF => self.cheats.read_rom(addr, self.cart.read_rom(addr)),
             0x8000..=0x9FFF => self.gpu.read_vram(addr),
-            0xFF00 => self.btns.data(),
-            0xFF04 => self.timer.div,
-            0xFF05 => self.timer.tima,
-            0xFF06 => self.timer.tma,
-            0xFF07 => self.timer.tac,
+            0xA000..=0xBFFF => self.cart.read_ram(addr),
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)], // Including echo RAM
+            0xFE00..=0xFE9F => self.gpu.read_oam(addr),
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.read_io(addr) | io_read_mask(addr, self.cgb),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80],
+            0xFFFF => self.ie,
+        }
+    }
+
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF00 => match &self.sgb {
+                Some(sgb) if self.btns.data() & 0x30 == 0x30 => 0xF0 | sgb.joypad_id(),
+                _ => self.btns.data(),
+            },
+            0xFF01 => self.serial.data,
+            0xFF02 => self.serial.control,
+            0xFF04 => self.timer.div(),
+            0xFF05 => self.timer.tima(),
+            0xFF06 => self.timer.tma(),
+            0xFF07 => self.timer.tac(),
             0xFF0F => self.iflag,
             0xFF24 => self.apu.master_volume_vin_panning,
             0xFF25 => self.apu.panning,
@@ -99,31 +330,75 @@ impl Mmu {
             0xFF43 => self.gpu.scx,
             0xFF44 => self.gpu.ly,
             0xFF45 => self.gpu.lyc,
-            0xFF46 => 0, // DMA transfer on write only
+            0xFF46 => self.dma_reg,
             0xFF47 => self.gpu.bgp,
             0xFF48 => self.gpu.obp0,
             0xFF49 => self.gpu.obp1,
             0xFF4A => self.gpu.wy,
             0xFF4B => self.gpu.wx,
-            0xFFFF => self.ie,
-            _ => self.ram[addr as usize],
+            0xFF4D if self.cgb => (self.double_speed as u8) << 7 | self.speed_armed as u8,
+            0xFF4F if self.cgb => self.gpu.vram_bank,
+            0xFF55 if self.cgb => self.hdma.status(),
+            0xFF68 if self.cgb => self.gpu.bcp.spec(),
+            0xFF69 if self.cgb && self.gpu.vram_accessible() => self.gpu.bcp.data(),
+            0xFF6A if self.cgb => self.gpu.ocp.spec(),
+            0xFF6B if self.cgb && self.gpu.vram_accessible() => self.gpu.ocp.data(),
+            0xFF69 | 0xFF6B if self.cgb => 0xFF,
+            0xFF70 if self.cgb => self.wram_bank,
+            _ => self.io[addr as usize - 0xFF00],
         }
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
+        if let Some(dma) = self.dma.as_ref().filter(|dma| dma.running()) {
+            match addr {
+                0xFE00..=0xFEFF => return,
+                0xFF00..=0xFFFF => {}
+                _ if on_vram_bus(addr) == on_vram_bus(dma.source) => return,
+                _ => {}
+            }
+        }
         match addr {
+            0x8000..=0x9FFF if !self.gpu.vram_accessible() => {}
+            0xFE00..=0xFEFF if !self.gpu.oam_accessible() => {}
+            _ => self.bus_write(addr, val),
+        }
+    }
+
+    fn bus_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.cart.write_rom(addr, val),
             0x8000..=0x9FFF => self.gpu.write_vram(addr, val),
-            0xFF00 => self.btns.pick_row(val),
-            0xFF04 => self.timer.div = 0,
-            0xFF05 => self.timer.tima = val,
-            0xFF06 => self.timer.tma = val,
-            0xFF07 => self.timer.tac = val,
+            0xA000..=0xBFFF => self.cart.write_ram(addr, val),
+            0xC000..=0xFDFF => self.wram[self.wram_index(addr)] = val, // Including echo RAM
+            0xFE00..=0xFE9F => self.gpu.write_oam(addr, val),
+            0xFEA0..=0xFEFF => {} // Unusable
+            0xFF00..=0xFF7F => self.write_io(addr, val),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80] = val,
+            0xFFFF => self.ie = val,
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF00 => {
+                self.btns.pick_row(val);
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_joypad(val, &self.gpu);
+                }
+            }
+            0xFF01 => self.serial.data = val,
+            0xFF02 => self.serial.write_control(val),
+            0xFF04 => self.timer.write_div(),
+            0xFF05 => self.timer.write_tima(val),
+            0xFF06 => self.timer.write_tma(val),
+            0xFF07 => self.timer.write_tac(val),
             0xFF24 => self.apu.master_volume_vin_panning = val,
             0xFF25 => self.apu.panning = val,
-            0xFF26 => self.apu.master_control = val & (0 << 3), // Lower nib is read only
+            0xFF26 => self.apu.master_control = val & 0x80, // Lower nib is read only
             0xFF0F => self.iflag = val,
-            0xFF40 => self.gpu.lcdc = val,
-            0xFF41 => self.gpu.stat = val,
+            0xFF40 => self.gpu.write_lcdc(val),
+            0xFF41 => self.gpu.write_stat(val),
             0xFF42 => self.gpu.scy = val,
             0xFF43 => self.gpu.scx = val,
             0xFF44 => {} // LY read only,
@@ -134,52 +409,483 @@ impl Mmu {
             0xFF49 => self.gpu.obp1 = val,
             0xFF4A => self.gpu.wy = val,
             0xFF4B => self.gpu.wx = val,
-            0xFFFF => self.ie = val,
-            _ => self.ram[addr as usize] = val,
+            0xFF4D if self.cgb => self.speed_armed = val & 1 > 0,
+            0xFF4F if self.cgb => self.gpu.vram_bank = val & 1,
+            0xFF51 if self.cgb => self.hdma.source = (val as u16) << 8 | (self.hdma.source & 0xF0),
+            0xFF52 if self.cgb => {
+                self.hdma.source = (self.hdma.source & 0xFF00) | (val & 0xF0) as u16
+            }
+            0xFF53 if self.cgb => {
+                self.hdma.dest = ((val & 0x1F) as u16) << 8 | (self.hdma.dest & 0xF0)
+            }
+            0xFF54 if self.cgb => self.hdma.dest = (self.hdma.dest & 0x1F00) | (val & 0xF0) as u16,
+            0xFF55 if self.cgb => self.hdma_transfer(val),
+            0xFF50 if val != 0 => self.boot_rom = None,
+            0xFF68 if self.cgb => self.gpu.bcp.write_spec(val),
+            0xFF69 if self.cgb => {
+                let locked = !self.gpu.vram_accessible();
+                self.gpu.bcp.write_data(val, locked)
+            }
+            0xFF6A if self.cgb => self.gpu.ocp.write_spec(val),
+            0xFF6B if self.cgb => {
+                let locked = !self.gpu.vram_accessible();
+                self.gpu.ocp.write_data(val, locked)
+            }
+            0xFF70 if self.cgb => self.wram_bank = (val & 0b111).max(1),
+            _ => self.io[addr as usize - 0xFF00] = val,
         };
     }
 
     pub fn do_cycles(&mut self, m_cycles: u32) {
+        // Interrupts requested by any of the hardware below
+        let mut requested = 0;
+
+        // DMA routine
+        for _ in 0..m_cycles {
+            self.dma_step();
+        }
+
         // Timer routine
-        self.timer.running_div += m_cycles;
-        while self.timer.running_div >= 64 {
-            self.timer.div = self.timer.div.wrapping_add(1);
-            self.timer.running_div -= 64;
-        }
-
-        if self.timer.enabled() {
-            self.timer.running_counter += m_cycles;
-            let step_size = self.timer.step_size();
-            while self.timer.running_counter >= step_size {
-                self.timer.tima = self.timer.tima.wrapping_add(1);
-                if self.timer.tima == 0 {
-                    // Timer overflowed
-                    self.timer.tima = self.timer.tma;
-                    self.iflag |= 0b100;
-                }
-                self.timer.running_counter -= step_size;
+        for _ in 0..m_cycles {
+            if self.timer.tick() {
+                requested |= 0b100;
             }
         }
 
-        // Buttons routine (check lower nibble any button is pressed)
-        if self.btns.data() & 0xF < 0xF {
-            self.iflag |= 1 << 4;
+        // Buttons routine
+        if self.btns.should_interrupt() {
+            requested |= 1 << 4;
+        }
+
+        // Serial routine
+        if self.serial.step(m_cycles) {
+            requested |= 1 << 3;
         }
 
-        // GPU routine
+        // GPU routine, it keeps running at single speed
+        let dots = if self.double_speed { 2 } else { 4 };
+        self.gpu.step(m_cycles * dots);
         if self.gpu.should_vblank_interrupt() {
-            self.iflag |= 1;
+            requested |= 1;
+            self.apply_cheats();
         }
         if self.gpu.should_stat_interrupt() {
-            self.iflag |= 0b10;
+            requested |= 0b10;
+        }
+        self.iflag |= requested;
+        self.gpu.log_interrupts(requested);
+
+        if self.gpu.hblank_started() && self.hdma.hblank && self.hdma.blocks > 0 {
+            self.hdma_block();
+        }
+    }
+
+    /// GameShark codes write their values once a frame
+    fn apply_cheats(&mut self) {
+        let writes: Vec<_> = self.cheats.ram_writes().collect();
+        for (bank, addr, val) in writes {
+            match (bank, addr) {
+                (Some(bank), 0xD000..=0xDFFF) if self.cgb => {
+                    self.wram[bank as usize * 0x1000 + (addr as usize & 0xFFF)] = val
+                }
+                _ => self.bus_write(addr, val),
+            }
+        }
+    }
+
+    /// STOP switches the CGB speed when armed through KEY1
+    pub fn stop(&mut self) {
+        if !self.cgb || !self.speed_armed {
+            return;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_armed = false;
+        self.timer.write_div();
+        // The CPU is stopped while the clock settles
+        self.do_cycles(2050);
+    }
+
+    fn hdma_transfer(&mut self, val: u8) {
+        // Writing bit 7 clear stops a running HBlank DMA
+        if self.hdma.hblank && self.hdma.blocks > 0 && val & 0x80 == 0 {
+            self.hdma.hblank = false;
+            return;
+        }
+
+        self.hdma.blocks = (val & 0x7F) + 1;
+        self.hdma.hblank = val & 0x80 > 0;
+        if !self.hdma.hblank {
+            while self.hdma.blocks > 0 {
+                self.hdma_block();
+            }
         }
     }
 
+    /// Copy 16 bytes to VRAM, the CPU is stalled meanwhile
+    fn hdma_block(&mut self) {
+        for _ in 0..16 {
+            let val = self.bus_read(self.hdma.source);
+            self.gpu.write_vram(0x8000 | (self.hdma.dest & 0x1FFF), val);
+            self.hdma.source = self.hdma.source.wrapping_add(1);
+            self.hdma.dest = self.hdma.dest.wrapping_add(1);
+        }
+        self.hdma.blocks -= 1;
+        self.do_cycles(if self.double_speed { 16 } else { 8 });
+    }
+
+    /// Start (or restart) an OAM DMA transfer
     fn dma_transfer(&mut self, val: u8) {
-        let source = (val as usize) << 8;
-        for offset in 0..0x9F {
-            self.ram[0xFE00 + offset] = self.ram[source + offset];
+        self.dma_reg = val;
+        // Sources above 0xDFFF read from WRAM
+        let page = if val >= 0xE0 { val - 0x20 } else { val };
+        self.dma = Some(Dma {
+            source: (page as u16) << 8,
+            index: 0,
+            delay: 1,
+        });
+    }
+
+    fn dma_step(&mut self) {
+        let Some(dma) = &mut self.dma else { return };
+        if dma.delay > 0 {
+            dma.delay -= 1;
+            return;
+        }
+
+        let (src, dst) = (dma.addr(), 0xFE00 + dma.index);
+        dma.index += 1;
+        if dma.index == 160 {
+            self.dma = None;
         }
-        self.do_cycles(160);
+        let val = self.bus_read(src);
+        self.gpu.write_oam(dst, val);
+    }
+}
+
+/// What the CPU is connected to, memory and the hardware that runs alongside it
+pub trait Bus {
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// Run everything else for an M-cycle
+    fn tick(&mut self);
+
+    /// Vector of the highest priority interrupt that's requested and enabled
+    fn pending_interrupt(&self) -> Option<u8>;
+
+    /// The CPU jumped to the interrupt at `addr`, clear its request
+    fn ack_interrupt(&mut self, addr: u8);
+
+    fn stop(&mut self) {}
+
+    /// ROM or WRAM bank mapped at `addr`, tells apart code at the same address
+    fn bank(&self, _addr: u16) -> u16 {
+        0
+    }
+}
+
+impl Bus for Mmu {
+    fn read(&self, addr: u16) -> u8 {
+        Mmu::read(self, addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        Mmu::write(self, addr, val)
+    }
+
+    fn tick(&mut self) {
+        self.do_cycles(1)
+    }
+
+    fn pending_interrupt(&self) -> Option<u8> {
+        Mmu::pending_interrupt(self)
+    }
+
+    fn ack_interrupt(&mut self, addr: u8) {
+        Mmu::ack_interrupt(self, addr)
+    }
+
+    fn stop(&mut self) {
+        Mmu::stop(self)
+    }
+
+    fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x0000..=0x7FFF => self.cart().rom_bank_at(addr) as u16,
+            0xD000..=0xDFFF => self.wram_bank as u16,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_rom_size() {
+        let path = std::env::temp_dir().join(format!("gb_boot_{}.bin", std::process::id()));
+        let mut mem = Mmu::new();
+        fs::write(&path, [0x31; 0x80]).unwrap();
+        assert!(mem.load_boot_rom(&path).is_err());
+        assert!(!mem.boot_rom_mapped());
+        fs::write(&path, [0x31; 0x100]).unwrap();
+        assert!(mem.load_boot_rom(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mem.read(0x0000), 0x31);
+        assert!(mem.load_boot_rom(&path).is_err());
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut mem = Mmu::new();
+        mem.write(0xFF40, 0); // Keep OAM unlocked by the PPU
+        for i in 0..160 {
+            mem.write(0xC000 + i, i as u8);
+        }
+        mem.write(0xFF80, 0x42);
+
+        // Echo RAM source mirrors WRAM
+        mem.write(0xFF46, 0xE0);
+        assert_eq!(mem.read(0xFF46), 0xE0);
+        mem.do_cycles(1);
+        mem.do_cycles(2);
+
+        // Only HRAM and IO are reachable while DMA runs
+        assert_eq!(mem.read(0xFE00), 0xFF);
+        assert_eq!(mem.read(0xFF80), 0x42);
+        assert_eq!(mem.read(0x8000), 0);
+        assert_eq!(mem.read(0x0000), 2);
+        mem.write(0xC000, 0xAA);
+        assert_eq!(mem.read(0xC000), 2);
+
+        mem.do_cycles(157);
+        assert_eq!(mem.read(0xFE00), 0xFF);
+        mem.do_cycles(1);
+        for i in 0..160 {
+            assert_eq!(mem.read(0xFE00 + i), i as u8);
+        }
+        assert_eq!(mem.read(0xC000), 0);
+    }
+
+    #[test]
+    fn memory_map() {
+        let mut mem = Mmu::new();
+
+        // Echo RAM
+        mem.write(0xE123, 0x42);
+        assert_eq!(mem.read(0xC123), 0x42);
+        mem.write(0xD000, 0x24);
+        assert_eq!(mem.read(0xF000), 0x24);
+
+        // Unusable area
+        mem.write(0xFEA0, 0x42);
+        assert_eq!(mem.read(0xFEA0), 0x00);
+
+        // Unused IO bits
+        mem.write(0xFF0F, 0);
+        assert_eq!(mem.read(0xFF0F), 0xE0);
+        mem.write(0xFF4C, 0);
+        assert_eq!(mem.read(0xFF4C), 0xFF);
+        mem.write(0xFF30, 0x5A);
+        assert_eq!(mem.read(0xFF30), 0x5A);
+        mem.write(0xFFFF, 0xFF);
+        assert_eq!(mem.read(0xFFFF), 0xFF);
+
+        // VRAM and OAM locked while drawing
+        mem.write(0x8000, 0x11);
+        mem.write(0xFF40, 0);
+        mem.write(0xFF40, 0x91);
+        assert_eq!(mem.read(0x8000), 0x11);
+        assert_eq!(mem.read(0xFE00), 0xFF);
+        mem.do_cycles(20);
+        assert_eq!(mem.read(0x8000), 0xFF);
+        mem.write(0x8000, 0x22);
+        mem.do_cycles(43);
+        assert_eq!(mem.read(0x8000), 0x11);
+        assert_eq!(mem.read(0xFE00), 0x00);
+    }
+
+    #[test]
+    fn cgb_banks() {
+        let mut mem = Mmu::new();
+        mem.write(0xFF40, 0);
+
+        // Registers are unmapped on DMG
+        mem.write(0xFF70, 2);
+        assert_eq!(mem.read(0xFF70), 0xFF);
+
+        mem.set_cgb(true);
+        mem.write(0xD000, 0x11);
+        mem.write(0xFF70, 0);
+        assert_eq!(mem.read(0xFF70), 0xF9);
+        assert_eq!(mem.read(0xD000), 0x11);
+        mem.write(0xFF70, 7);
+        mem.write(0xD000, 0x77);
+        assert_eq!(mem.read(0xF000), 0x77);
+        mem.write(0xFF70, 1);
+        assert_eq!(mem.read(0xD000), 0x11);
+
+        mem.write(0x8000, 0x01);
+        mem.write(0xFF4F, 1);
+        assert_eq!(mem.read(0xFF4F), 0xFF);
+        assert_eq!(mem.read(0x8000), 0x00);
+        mem.write(0x8000, 0x02);
+        mem.write(0xFF4F, 0);
+        assert_eq!(mem.read(0xFF4F), 0xFE);
+        assert_eq!(mem.read(0x8000), 0x01);
+    }
+
+    #[test]
+    fn cgb_post_boot() {
+        let mut mem = Mmu::new();
+        mem.wram_bank = 3;
+        mem.gpu.vram_bank = 1;
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        mem.insert(Cartridge::new(rom));
+        assert!(mem.cgb());
+
+        let regs = [
+            (0xFF40, 0x91), // LCDC
+            (0xFF41, 0x85), // STAT
+            (0xFF47, 0xFC), // BGP
+            (0xFF4D, 0x7E), // KEY1
+            (0xFF4F, 0xFE), // VBK
+            (0xFF55, 0xFF), // HDMA5
+            (0xFF56, 0x3E), // RP
+            (0xFF68, 0xC0), // BCPS
+            (0xFF6A, 0x40), // OCPS
+            (0xFF6C, 0xFE), // OPRI
+            (0xFF70, 0xF9), // SVBK
+            (0xFF72, 0x00),
+            (0xFF75, 0x8F),
+        ];
+        for (addr, val) in regs {
+            assert_eq!(mem.read(addr), val, "{:04X}", addr);
+        }
+        assert_eq!(mem.gpu.bcp.color(7, 3), (0xFF, 0xFF, 0xFF));
+
+        // Not on a DMG
+        let mut mem = Mmu::new();
+        mem.insert(Cartridge::new(vec![0; 0x8000]));
+        assert_eq!(mem.read(0xFF4D), 0xFF);
+        assert_eq!(mem.read(0xFF75), 0xFF);
+    }
+
+    #[test]
+    fn hdma() {
+        let mut mem = Mmu::new();
+        mem.write(0xFF40, 0);
+        mem.set_cgb(true);
+        for i in 0..0x40 {
+            mem.write(0xC000 + i, i as u8 + 1);
+        }
+        mem.write(0xFF51, 0xC0);
+        mem.write(0xFF52, 0x00);
+        mem.write(0xFF53, 0x80);
+        mem.write(0xFF54, 0x10);
+
+        // General purpose, all at once
+        mem.write(0xFF55, 0x01);
+        assert_eq!(mem.read(0xFF55), 0xFF);
+        assert_eq!(mem.read(0x8010), 1);
+        assert_eq!(mem.read(0x802F), 0x20);
+
+        // HBlank, 16 bytes per line
+        mem.write(0xFF55, 0x80);
+        assert_eq!(mem.read(0xFF55), 0x00);
+        assert_eq!(mem.read(0x8030), 0);
+        mem.write(0xFF40, 0x91);
+        mem.do_cycles(63);
+        assert_eq!(mem.read(0xFF55), 0xFF);
+        assert_eq!(mem.read(0x8030), 0x21);
+    }
+
+    #[test]
+    fn speed_switch() {
+        let mut mem = Mmu::new();
+        mem.set_cgb(true);
+        assert_eq!(mem.read(0xFF4D), 0x7E);
+        mem.write(0xFF4D, 1);
+        assert_eq!(mem.read(0xFF4D), 0x7F);
+        mem.stop();
+        assert_eq!(mem.read(0xFF4D), 0xFE);
+
+        // The PPU sees half as many dots per M-cycle
+        mem.write(0xFF40, 0);
+        mem.write(0xFF40, 0x91);
+        mem.do_cycles(114);
+        assert_eq!(mem.read(0xFF44), 0);
+        mem.do_cycles(114);
+        assert_eq!(mem.read(0xFF44), 1);
+    }
+
+    #[test]
+    fn cheats() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x150] = 0x12;
+        let mut mem = Mmu::new();
+        mem.cart = Cartridge::new(rom);
+        mem.cheats.add("3E1-50F-A62", "").unwrap();
+        mem.cheats.add("0142A0C0", "").unwrap();
+        assert_eq!(mem.read(0x150), 0x3E);
+
+        // RAM is written at the start of VBlank
+        mem.write(0xFF40, 0);
+        mem.write(0xFF40, 0x91);
+        mem.do_cycles(114 * 143);
+        assert_eq!(mem.read(0xC0A0), 0x00);
+        mem.do_cycles(114);
+        assert_eq!(mem.read(0xC0A0), 0x42);
+        mem.write(0xC0A0, 0x00);
+        mem.cheats.toggle(1);
+        mem.do_cycles(114 * 154);
+        assert_eq!(mem.read(0xC0A0), 0x00);
+    }
+
+    #[test]
+    fn line_log() {
+        let mut mem = Mmu::new();
+        mem.write(0xFF40, 0);
+        mem.write(0xFF40, 0x91);
+        mem.write(0xFF41, 1 << 6); // LYC interrupt
+        mem.write(0xFF45, 10);
+        for line in 0..154 {
+            mem.write(0xFF43, line as u8);
+            mem.do_cycles(114);
+        }
+
+        let log = mem.gpu.line_log();
+        assert_eq!(log[5].scx, 5);
+        assert_eq!(log[5].lcdc, 0x91);
+        // VBlank lines have what they started with
+        assert_eq!(log[150].scx, 149);
+        assert_eq!(log[10].interrupts, 0b10);
+        assert_eq!(log[144].interrupts, 0b01);
+        assert_eq!(log[11].interrupts, 0);
+    }
+
+    #[test]
+    fn line_log_at_frame_end() {
+        let mut mem = Mmu::new();
+        mem.write(0xFF40, 0);
+        mem.write(0xFF40, 0x91);
+        mem.gpu.frame_ready();
+        // Stop where the frontend does, as VBlank starts
+        let mut line = 0;
+        while !mem.gpu.frame_ready() {
+            mem.write(0xFF42, line);
+            mem.do_cycles(114);
+            line += 1;
+        }
+
+        assert_eq!(line, 144);
+        let log = mem.gpu.line_log();
+        assert_eq!(log[0].scy, 0);
+        assert_eq!(log[143].scy, 143);
+        assert_eq!(log[144].scy, 143);
+        assert_eq!(log[144].interrupts & 1, 1);
+        assert_eq!(log[145], crate::graphics::LineRegs::default());
     }
 }
@@ -0,0 +1,68 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Input movies hold the joypad state of every frame, one byte each as returned by
+// Btns::state. Turbo and the opposite direction filter are already applied,
+// so playback doesn't depend on either.
+
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Recorder {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, state: u8) {
+        if let Err(e) = self.out.write_all(&[state]) {
+            eprintln!("failed to record input: {}", e);
+        }
+    }
+}
+
+pub struct Player {
+    inputs: Vec<u8>,
+    frame: usize,
+}
+
+impl Player {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Player {
+            inputs: fs::read(path)?,
+            frame: 0,
+        })
+    }
+
+    /// Joypad state of the next frame, None once the movie is over
+    pub fn next_frame(&mut self) -> Option<u8> {
+        let state = self.inputs.get(self.frame).copied();
+        self.frame += 1;
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_play() {
+        let path = std::env::temp_dir().join(format!("gb_movie_{}.bin", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        for state in [0xFF, 0xFE, 0x7F] {
+            recorder.record(state);
+        }
+        drop(recorder);
+
+        let mut player = Player::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(player.next_frame(), Some(0xFF));
+        assert_eq!(player.next_frame(), Some(0xFE));
+        assert_eq!(player.next_frame(), Some(0x7F));
+        assert_eq!(player.next_frame(), None);
+    }
+}
@@ -0,0 +1,381 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::cartridge::Cartridge;
+use crate::graphics::{rgb555, Rgb, SHADES};
+
+/// Colors of the 4 shades, separately for each DMG palette register
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DmgPalette {
+    pub bg: [Rgb; 4],   // BGP
+    pub obj0: [Rgb; 4], // OBP0
+    pub obj1: [Rgb; 4], // OBP1
+}
+
+impl DmgPalette {
+    const fn same(colors: [Rgb; 4]) -> Self {
+        DmgPalette {
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        }
+    }
+
+    pub const fn grey() -> Self {
+        DmgPalette::same(SHADES)
+    }
+}
+
+const fn rgb(hex: u32) -> Rgb {
+    ((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+}
+
+const fn colors(hex: [u32; 4]) -> [Rgb; 4] {
+    [rgb(hex[0]), rgb(hex[1]), rgb(hex[2]), rgb(hex[3])]
+}
+
+const fn split(bg: [u32; 4], obj0: [u32; 4], obj1: [u32; 4]) -> DmgPalette {
+    DmgPalette {
+        bg: colors(bg),
+        obj0: colors(obj0),
+        obj1: colors(obj1),
+    }
+}
+
+const BROWN: [u32; 4] = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+const RED: [u32; 4] = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000];
+const GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000];
+const BLUE: [u32; 4] = [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000];
+
+pub const PRESETS: [(&str, DmgPalette); 15] = [
+    ("grey", DmgPalette::grey()),
+    (
+        "green",
+        DmgPalette::same(colors([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F])),
+    ),
+    (
+        "pocket",
+        DmgPalette::same(colors([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F])),
+    ),
+    (
+        "contrast",
+        DmgPalette::same(colors([0xFFFFFF, 0xB0B0B0, 0x404040, 0x000000])),
+    ),
+    // The CGB boot ROM palettes, also picked with button combos at boot
+    ("cgb-brown", DmgPalette::same(colors(BROWN))),
+    ("cgb-red", split(RED, GREEN, GREEN)),
+    (
+        "cgb-dark-brown",
+        DmgPalette::same(colors([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108])),
+    ),
+    ("cgb-blue", split(BLUE, RED, RED)),
+    (
+        "cgb-dark-blue",
+        split([0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000], RED, BROWN),
+    ),
+    (
+        "cgb-pastel",
+        DmgPalette::same(colors([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000])),
+    ),
+    (
+        "cgb-orange",
+        DmgPalette::same(colors([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000])),
+    ),
+    (
+        "cgb-yellow",
+        split([0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000], BLUE, GREEN),
+    ),
+    (
+        "cgb-green",
+        DmgPalette::same(colors([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000])),
+    ),
+    (
+        "cgb-dark-green",
+        split([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000], RED, RED),
+    ),
+    (
+        "cgb-inverted",
+        DmgPalette::same(colors([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF])),
+    ),
+];
+
+// The CGB boot ROM's tables for coloring DMG games, a game's title checksum picks
+// one of the combinations of 3 palettes
+
+/// 30 palettes of 4 RGB555 colors
+const BOOT_COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, //
+    0x7FFF, 0x6E31, 0x454A, 0x0000, 0x7FFF, 0x1BEF, 0x0200, 0x0000, //
+    0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000, //
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, //
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000, 0x7E74, 0x03FF, 0x0180, 0x0000, //
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000, //
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, //
+    0x03ED, 0x7FFF, 0x255F, 0x0000, 0x036A, 0x021F, 0x03FF, 0x7FFF, //
+    0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009, //
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, //
+    0x7FFF, 0x027F, 0x001F, 0x0000, 0x7FFF, 0x03E0, 0x0206, 0x0120, //
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F, //
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, //
+    0x7FFF, 0x033F, 0x0193, 0x0000, 0x0000, 0x4200, 0x037F, 0x7FFF, //
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000, //
+];
+
+/// OBJ0, OBJ1 and BG palettes of a combination, as the index of their first color
+const fn comb(obj0: usize, obj1: usize, bg: usize) -> [usize; 3] {
+    [obj0 * 4, obj1 * 4, bg * 4]
+}
+
+const BOOT_COMBINATIONS: [[usize; 3]; 51] = [
+    comb(4, 4, 29),
+    comb(18, 18, 18),
+    comb(20, 20, 20),
+    comb(24, 24, 24),
+    comb(9, 9, 9),
+    comb(0, 0, 0),
+    comb(27, 27, 27),
+    comb(5, 5, 5),
+    comb(12, 12, 12),
+    comb(26, 26, 26),
+    comb(16, 8, 8),
+    comb(4, 28, 28),
+    comb(4, 2, 2),
+    comb(3, 4, 4),
+    comb(4, 29, 29),
+    comb(28, 4, 28),
+    comb(2, 17, 2),
+    comb(16, 16, 8),
+    comb(4, 4, 7),
+    comb(4, 4, 18),
+    comb(4, 4, 20),
+    comb(19, 19, 9),
+    // The boot ROM really does start some OBJ palettes one color early
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    comb(17, 17, 2),
+    comb(4, 4, 2),
+    comb(4, 4, 3),
+    comb(28, 28, 0),
+    comb(3, 3, 0),
+    comb(0, 0, 1),
+    comb(18, 22, 18),
+    comb(20, 22, 20),
+    comb(24, 22, 24),
+    comb(16, 22, 8),
+    comb(17, 4, 13),
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4],
+    comb(19, 22, 9),
+    comb(16, 28, 10),
+    comb(4, 23, 28),
+    comb(17, 22, 2),
+    comb(4, 0, 2),
+    comb(4, 28, 3),
+    comb(28, 3, 0),
+    comb(3, 28, 4),
+    comb(21, 28, 4),
+    comb(3, 28, 0),
+    comb(25, 3, 28),
+    comb(0, 28, 8),
+    comb(4, 3, 28),
+    comb(28, 3, 6),
+    comb(4, 28, 29),
+];
+
+/// Title checksums of Nintendo games and the combination each gets. From
+/// `FIRST_DUPLICATE` on the checksums are shared, the 4th title letter tells them apart.
+const BOOT_CHECKSUMS: [(u8, u8); 94] = [
+    (0x00, 0),
+    (0x88, 4),  // ALLEY WAY
+    (0x16, 5),  // YAKUMAN
+    (0x36, 35), // BASEBALL
+    (0xD1, 34), // TENNIS
+    (0xDB, 3),  // TETRIS
+    (0xF2, 31), // QIX
+    (0x3C, 15), // DR.MARIO
+    (0x8C, 10), // RADARMISSION
+    (0x92, 5),  // F1RACE
+    (0x3D, 19), // YOSSY NO TAMAGO
+    (0x5C, 36),
+    (0x58, 7),  // X
+    (0xC9, 37), // MARIOLAND2
+    (0x3E, 30), // YOSSY NO COOKIE
+    (0x70, 44), // ZELDA
+    (0x1D, 21),
+    (0x59, 32),
+    (0x69, 31), // TETRIS FLASH
+    (0x19, 20), // DONKEY KONG
+    (0x35, 5),  // MARIO'S PICROSS
+    (0xA8, 33),
+    (0x14, 13), // POKEMON RED
+    (0xAA, 14), // POKEMON GREEN
+    (0x75, 5),  // PICROSS 2
+    (0x95, 29), // YOSSY NO PANEPON
+    (0x99, 5),  // KIRAKIRA KIDS
+    (0x34, 18), // GAMEBOY GALLERY
+    (0x6F, 9),  // POCKETCAMERA
+    (0x15, 3),
+    (0xFF, 2),  // BALLOON KID
+    (0x97, 26), // KINGOFTHEZOO
+    (0x4B, 25), // DMG FOOTBALL
+    (0x90, 25), // WORLD CUP
+    (0x17, 41), // OTHELLO
+    (0x10, 42), // SUPER RC PRO-AM
+    (0x39, 26), // DYNABLASTER
+    (0xF7, 45), // BOY AND BLOB GB2
+    (0xF6, 42), // MEGAMAN
+    (0xA2, 45), // STAR WARS-NOA
+    (0x49, 36),
+    (0x4E, 38), // WAVERACE
+    (0x43, 26),
+    (0x68, 42), // LOLO2
+    (0xE0, 30), // YOSHI'S COOKIE
+    (0x8B, 41), // MYSTIC QUEST
+    (0xF0, 34),
+    (0xCE, 34), // TOPRANKINGTENNIS
+    (0x0C, 5),  // MANSELL
+    (0x29, 42), // MEGAMAN3
+    (0xE8, 6),  // SPACE INVADERS
+    (0xB7, 5),  // GAME&WATCH
+    (0x86, 33), // DONKEYKONGLAND95
+    (0x9A, 25), // ASTEROIDS/MISCMD
+    (0x52, 42), // STREET FIGHTER 2
+    (0x01, 42), // DEFENDER/JOUST
+    (0x9D, 40), // KILLERINSTINCT95
+    (0x71, 2),  // TETRIS BLAST
+    (0x9C, 16), // PINOCCHIO
+    (0xBD, 25),
+    (0x5D, 42), // BA.TOSHINDEN
+    (0x6D, 42), // NETTOU KOF 95
+    (0x67, 5),
+    (0x3F, 0),  // TETRIS PLUS
+    (0x6B, 39), // DONKEYKONGLAND 3
+    (0xB3, 36),
+    (0x46, 22), // SUPER MARIOLAND
+    (0x28, 25), // GOLF
+    (0xA5, 6),  // SOLARSTRIKER
+    (0xC6, 32), // GBWARS
+    (0xD3, 12), // KAERUNOTAMENI
+    (0x27, 36),
+    (0x61, 11), // POKEMON BLUE
+    (0x18, 39), // DONKEYKONGLAND
+    (0x66, 18), // GAMEBOY GALLERY2
+    (0x6A, 39), // DONKEYKONGLAND 2
+    (0xBF, 24), // KID ICARUS
+    (0x0D, 31), // TETRIS2
+    (0xF4, 50),
+    (0xB3, 17), // MOGURANYA
+    (0x46, 46),
+    (0x28, 6),  // GALAXIAN
+    (0xA5, 27), // BT2RAGNAROKWORLD
+    (0xC6, 0),  // KEN GRIFFEY JR
+    (0xD3, 47),
+    (0x27, 41), // MAGNETIC SOCCER
+    (0x61, 41), // VEGAS STAKES
+    (0x18, 0),
+    (0x66, 0),  // MILLI/CENTI/PEDE
+    (0x6A, 19), // MARIO & YOSHI
+    (0xBF, 34), // SOCCER
+    (0x0D, 23), // POKEBOM
+    (0xF4, 18), // G&W GALLERY
+    (0xB3, 29), // TETRIS ATTACK
+];
+
+const FIRST_DUPLICATE: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Palette the CGB boot ROM would give a DMG game, anything it doesn't know gets dark green
+pub fn auto(cart: &Cartridge) -> DmgPalette {
+    let checksum = cart.title_checksum();
+    let letter = cart.header(0x137);
+    let index = BOOT_CHECKSUMS
+        .iter()
+        .enumerate()
+        .position(|(i, &(sum, _))| {
+            sum == checksum
+                && (i < FIRST_DUPLICATE || FOURTH_LETTERS[i - FIRST_DUPLICATE] == letter)
+        })
+        .filter(|_| cart.nintendo());
+    let [obj0, obj1, bg] = BOOT_COMBINATIONS[index.map_or(0, |i| BOOT_CHECKSUMS[i].1 as usize)];
+    let colors = |first: usize| std::array::from_fn(|i| rgb555(BOOT_COLORS[first + i]));
+    DmgPalette {
+        bg: colors(bg),
+        obj0: colors(obj0),
+        obj1: colors(obj1),
+    }
+}
+
+/// Load a TOML palette file with a list of 4 colors for each register, lightest shade first.
+/// Registers that are left out use the BG colors.
+///
+/// bg = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]
+/// obj0 = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000]
+pub fn load(path: &Path) -> Result<DmgPalette, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaletteFile {
+    bg: [u32; 4],
+    obj0: Option<[u32; 4]>,
+    obj1: Option<[u32; 4]>,
+}
+
+fn parse(text: &str) -> Result<DmgPalette, String> {
+    let file: PaletteFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    Ok(split(
+        file.bg,
+        file.obj0.unwrap_or(file.bg),
+        file.obj1.unwrap_or(file.bg),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_file() {
+        let palette = parse(
+            "# Red objects\n\
+             bg = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]\n\
+             obj1 = [0xFFFFFF, 0xFF0000, 0x800000, 0x000000]\n",
+        )
+        .unwrap();
+        assert_eq!(palette.bg, SHADES);
+        assert_eq!(palette.obj0, SHADES);
+        assert_eq!(palette.obj1[1], (0xFF, 0, 0));
+
+        assert!(parse("obj0 = [0xFFFFFF, 0, 0, 0]").is_err());
+        assert!(parse("bg = [0xFFFFFF, 0, 0]").is_err());
+    }
+
+    #[test]
+    fn auto_palette() {
+        let game = |title: &[u8], licensee: u8| {
+            let mut rom = vec![0; 0x8000];
+            rom[0x134..0x134 + title.len()].copy_from_slice(title);
+            rom[0x14B] = licensee;
+            Cartridge::new(rom)
+        };
+        let dark_green = auto(&game(b"POKEMON RED", 0x00));
+        assert_eq!(dark_green.bg[2], (0x00, 0x63, 0xC6));
+
+        let red = game(b"POKEMON RED", 0x01);
+        assert_eq!(red.title_checksum(), 0x14);
+        assert_eq!(auto(&red).bg[1], (0xFF, 0x84, 0x84));
+        assert_eq!(auto(&red).obj0, colors(GREEN));
+
+        // Vegas Stakes has the same checksum as Pokemon Blue, the 4th letter decides
+        let blue = game(b"POKEMON BLUE", 0x01);
+        let vegas = game(b"POKAMON BLUI", 0x01);
+        assert_eq!(blue.title_checksum(), 0x61);
+        assert_eq!(vegas.title_checksum(), 0x61);
+        assert_eq!(auto(&blue).bg, colors(BLUE));
+        assert_eq!(auto(&vegas).bg, colors(GREEN));
+        assert_eq!(auto(&game(b"POKXMON BLU2", 0x01)), dark_green);
+    }
+}
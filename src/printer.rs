@@ -0,0 +1,331 @@
+use std::path::PathBuf;
+
+use crate::graphics::{tile_color_map, Tile};
+use crate::image::write_png;
+use crate::serial::SerialDevice;
+
+/// Paper is 160 px wide, 20 tiles per row
+const PAPER_WIDTH: usize = 160;
+/// Size of the printer's image RAM
+const BUFFER_SIZE: usize = 0x2000;
+/// Height of a blank line feed in px
+const FEED_HEIGHT: usize = 16;
+/// Status inquiries answered as busy after a print
+const PRINT_BUSY_INQUIRIES: u8 = 2;
+
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+// Status bits
+const CHECKSUM_ERROR: u8 = 1 << 0;
+const PRINTING: u8 = 1 << 1;
+const IMAGE_FULL: u8 = 1 << 2;
+const UNPROCESSED: u8 = 1 << 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    checksum: u16,
+    packet: Vec<u8>,
+    buffer: Vec<u8>, // decompressed tile data
+    status: u8,
+    busy: u8,
+    paper: Vec<u8>, // shades of the sheet being printed, PAPER_WIDTH per row
+    out_dir: PathBuf,
+    prints: u32,
+}
+
+impl Printer {
+    pub fn new(out_dir: PathBuf) -> Self {
+        Printer {
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            checksum: 0,
+            packet: Vec::new(),
+            buffer: Vec::new(),
+            status: 0,
+            busy: 0,
+            paper: Vec::new(),
+            out_dir,
+            prints: 0,
+        }
+    }
+
+    fn exec(&mut self) {
+        match self.command {
+            // INIT
+            0x01 => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy = 0;
+            }
+            // PRINT
+            0x02 => {
+                if self.packet.len() < 4 {
+                    return;
+                }
+                let margins = self.packet[1];
+                let palette = self.packet[2];
+                self.print(margins >> 4, margins & 0xF, palette);
+                self.buffer.clear();
+                self.status = (self.status & !UNPROCESSED) | PRINTING;
+                self.busy = PRINT_BUSY_INQUIRIES;
+            }
+            // DATA, an empty packet only marks the end of the data
+            0x04 => {
+                let data = if self.compressed {
+                    decompress(&self.packet)
+                } else {
+                    self.packet.clone()
+                };
+                let room = BUFFER_SIZE - self.buffer.len();
+                if data.len() > room {
+                    self.status |= IMAGE_FULL;
+                }
+                self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+                if !self.buffer.is_empty() {
+                    self.status |= UNPROCESSED;
+                }
+            }
+            // STATUS
+            0x0F => {
+                if self.busy > 0 {
+                    self.busy -= 1;
+                } else {
+                    self.status &= !PRINTING;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Transfer the image buffer onto the paper
+    fn print(&mut self, margin_before: u8, margin_after: u8, palette: u8) {
+        // 0x00 is treated as the default palette
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        self.feed(margin_before);
+        let tiles: Vec<Tile> = self
+            .buffer
+            .chunks_exact(16)
+            .map(|chunk| chunk.try_into().unwrap())
+            .collect();
+        for tile_row in tiles.chunks(PAPER_WIDTH / 8) {
+            let maps: Vec<_> = tile_row.iter().map(|t| tile_color_map(*t)).collect();
+            for line in 0..8 {
+                for x in 0..PAPER_WIDTH {
+                    let color = maps.get(x / 8).map_or(0, |map| map[line][x % 8]);
+                    let shade = (palette >> (color * 2)) & 0b11;
+                    self.paper.push(SHADES[shade as usize]);
+                }
+            }
+        }
+        self.feed(margin_after);
+
+        // A sheet is done once the paper is fed out
+        if margin_after > 0 {
+            self.cut();
+        }
+    }
+
+    fn feed(&mut self, lines: u8) {
+        let len = self.paper.len() + lines as usize * FEED_HEIGHT * PAPER_WIDTH;
+        self.paper.resize(len, SHADES[0]);
+    }
+
+    /// Save the current sheet as a PNG
+    fn cut(&mut self) {
+        if self.paper.is_empty() {
+            return;
+        }
+        self.prints += 1;
+        let path = self.out_dir.join(format!("print_{:03}.png", self.prints));
+        if let Err(e) = write_png(
+            &path,
+            PAPER_WIDTH as u32,
+            png::ColorType::Grayscale,
+            &self.paper,
+        ) {
+            eprintln!("failed to save print {}: {}", path.display(), e);
+        }
+        self.paper.clear();
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.state = match self.state {
+            State::Magic1 if byte == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if byte == 0x33 => State::Command,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 1 > 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length > 0 {
+                    State::Data
+                } else {
+                    State::ChecksumLow
+                }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.checksum ^= byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.checksum ^= (byte as u16) << 8;
+                if self.checksum == 0 {
+                    self.status &= !CHECKSUM_ERROR;
+                    self.exec();
+                } else {
+                    self.status |= CHECKSUM_ERROR;
+                }
+                State::Alive
+            }
+            State::Alive => {
+                reply = 0x81;
+                State::Status
+            }
+            State::Status => {
+                reply = self.status;
+                State::Magic1
+            }
+        };
+        reply
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.cut();
+    }
+}
+
+/// Expand the printer's run length encoding
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let ctrl = data[i];
+        i += 1;
+        if ctrl & 0x80 > 0 {
+            // Run of one byte
+            let Some(&byte) = data.get(i) else { break };
+            let len = (ctrl & 0x7F) as usize + 2;
+            out.extend(std::iter::repeat_n(byte, len));
+            i += 1;
+        } else {
+            // Literal bytes
+            let len = ctrl as usize + 1;
+            let end = (i + len).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![0x88, 0x33, command, compressed as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let sum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        packet.extend_from_slice(&sum.to_le_bytes());
+
+        for byte in packet {
+            assert_eq!(printer.transfer(byte), 0);
+        }
+        (printer.transfer(0), printer.transfer(0))
+    }
+
+    #[test]
+    fn rle() {
+        assert_eq!(
+            decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]),
+            [0xAA, 0xAA, 0xAA, 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn packets() {
+        let mut printer = Printer::new(std::env::temp_dir());
+
+        assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0));
+        // Two black tiles
+        assert_eq!(
+            send(&mut printer, 0x04, true, &[0x9E, 0xFF]),
+            (0x81, UNPROCESSED)
+        );
+        assert_eq!(printer.buffer.len(), 32);
+
+        // Bad checksum
+        let mut bad = vec![0x88, 0x33, 0x0F, 0, 0, 0, 0x00, 0x00];
+        bad.extend([0, 0]);
+        let replies: Vec<u8> = bad.into_iter().map(|b| printer.transfer(b)).collect();
+        assert_eq!(replies[9] & CHECKSUM_ERROR, CHECKSUM_ERROR);
+
+        // Print with no feed after keeps the sheet going
+        let (_, status) = send(&mut printer, 0x02, false, &[1, 0x10, 0xE4, 0x40]);
+        assert_eq!(status, PRINTING);
+        assert!(printer.buffer.is_empty());
+        assert_eq!(printer.paper.len(), (FEED_HEIGHT + 8) * PAPER_WIDTH);
+        assert_eq!(printer.paper[FEED_HEIGHT * PAPER_WIDTH], SHADES[3]);
+        assert_eq!(printer.paper[FEED_HEIGHT * PAPER_WIDTH + 16], SHADES[0]);
+
+        for _ in 0..PRINT_BUSY_INQUIRIES {
+            assert_eq!(send(&mut printer, 0x0F, false, &[]).1, PRINTING);
+        }
+        assert_eq!(send(&mut printer, 0x0F, false, &[]).1, 0);
+        printer.paper.clear();
+    }
+}
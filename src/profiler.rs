@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+// Where a game spends its time: M-cycles per instruction address and, through the
+// calls and returns the CPU reports, per call stack for flame graphs
+
+/// Rows in each table of the report
+const TOP: usize = 40;
+/// Calls deeper than this aren't tracked, returns still unwind the ones that are
+const MAX_DEPTH: usize = 64;
+
+/// Labels from an RGBDS style .sym file, "BB:AAAA Name" on each line
+#[derive(Default)]
+pub struct Symbols {
+    labels: HashMap<u16, Vec<(u16, String)>>, // by bank, sorted by address
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            // Section headers like [labels] from other assemblers
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+            let label = line.split_once(char::is_whitespace).and_then(|(at, name)| {
+                let (bank, addr) = at.split_once(':')?;
+                let bank = u16::from_str_radix(bank, 16).ok()?;
+                let addr = u16::from_str_radix(addr, 16).ok()?;
+                Some((bank, addr, name.trim()))
+            });
+            let Some((bank, addr, name)) = label else {
+                return Err(format!("line {}: expected BB:AAAA Name", i + 1));
+            };
+            symbols
+                .labels
+                .entry(bank)
+                .or_default()
+                .push((addr, name.to_string()));
+        }
+        for labels in symbols.labels.values_mut() {
+            labels.sort();
+        }
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Symbols::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// The function `addr` is in and how far into it, that's the last label before
+    /// it in the same bank that isn't a local one
+    pub fn function(&self, bank: u16, addr: u16) -> Option<(&str, u16)> {
+        let labels = self.labels.get(&bank)?;
+        let end = labels.partition_point(|&(at, _)| at <= addr);
+        labels[..end]
+            .iter()
+            .rev()
+            .find(|(_, name)| !name.contains('.'))
+            .map(|(at, name)| (name.as_str(), addr - at))
+    }
+}
+
+type Stacks = HashMap<Vec<(u16, u16)>, u64>;
+
+/// Counts M-cycles while the game runs, see `Cpu::profiler`
+pub struct Profiler {
+    name: String, // bottom of every call stack
+    symbols: Symbols,
+    pcs: HashMap<(u16, u16), u64>, // by bank and address of the instruction
+    executing: u64,
+    halted: u64,
+    calls: Vec<(u16, u16)>, // bank and address of the functions called
+    return_sps: Vec<u16>,   // where each one's return address is on the stack
+    next_call: Option<(u16, u16, u16)>,
+    next_ret: Option<u16>,
+    stacks: Stacks, // by the calls that led there
+    halted_stacks: Stacks,
+}
+
+fn add(stacks: &mut Stacks, calls: &[(u16, u16)], m_cycles: u64) {
+    match stacks.get_mut(calls) {
+        Some(count) => *count += m_cycles,
+        None => {
+            stacks.insert(calls.to_vec(), m_cycles);
+        }
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    part as f64 * 100.0 / total.max(1) as f64
+}
+
+impl Profiler {
+    pub fn new(name: &str, symbols: Symbols) -> Self {
+        Profiler {
+            name: name.to_string(),
+            symbols,
+            pcs: HashMap::new(),
+            executing: 0,
+            halted: 0,
+            calls: Vec::new(),
+            return_sps: Vec::new(),
+            next_call: None,
+            next_ret: None,
+            stacks: HashMap::new(),
+            halted_stacks: HashMap::new(),
+        }
+    }
+
+    /// The instruction at `bank:pc` took `m_cycles`, a call or return it made comes
+    /// after so its cycles count toward the caller or the function returning
+    pub fn run(&mut self, bank: u16, pc: u16, m_cycles: u32) {
+        *self.pcs.entry((bank, pc)).or_default() += m_cycles as u64;
+        self.executing += m_cycles as u64;
+        add(&mut self.stacks, &self.calls, m_cycles as u64);
+
+        if let Some(sp) = self.next_ret.take() {
+            while self.return_sps.last().is_some_and(|&at| at < sp) {
+                self.calls.pop();
+                self.return_sps.pop();
+            }
+        }
+        if let Some((bank, addr, sp)) = self.next_call.take() {
+            if self.calls.len() < MAX_DEPTH {
+                self.calls.push((bank, addr));
+                self.return_sps.push(sp);
+            }
+        }
+    }
+
+    /// An M-cycle in HALT
+    pub fn halt(&mut self) {
+        self.halted += 1;
+        add(&mut self.halted_stacks, &self.calls, 1);
+    }
+
+    /// A CALL, RST or interrupt jumped to `bank:addr` with the return address at `sp`
+    pub fn call(&mut self, bank: u16, addr: u16, sp: u16) {
+        self.next_call = Some((bank, addr, sp));
+    }
+
+    /// A return left SP at `sp`, the calls whose return address is above it are done.
+    /// Going by SP keeps the stack right when games drop return addresses.
+    pub fn ret(&mut self, sp: u16) {
+        self.next_ret = Some(sp);
+    }
+
+    /// A function by its symbol, or its bank and address
+    fn frame(&self, (bank, addr): (u16, u16)) -> String {
+        match self.symbols.function(bank, addr) {
+            Some((name, 0)) => name.to_string(),
+            _ => format!("{:02X}:{:04X}", bank, addr),
+        }
+    }
+
+    /// Executing and halted time, the functions that took the most cycles when there
+    /// are symbols and the instructions that did
+    pub fn report(&self) -> String {
+        let total = self.executing + self.halted;
+        let mut out = format!(
+            "{} M-cycles, executing {} ({:.1}%), halted {} ({:.1}%)\n",
+            total,
+            self.executing,
+            percent(self.executing, total),
+            self.halted,
+            percent(self.halted, total)
+        );
+
+        if !self.symbols.is_empty() {
+            let mut functions: HashMap<&str, u64> = HashMap::new();
+            for (&(bank, pc), &count) in &self.pcs {
+                let name = self
+                    .symbols
+                    .function(bank, pc)
+                    .map_or("?", |(name, _)| name);
+                *functions.entry(name).or_default() += count;
+            }
+            let mut functions: Vec<_> = functions.into_iter().collect();
+            functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            out.push_str("\nFunctions\n  M-cycles      %  function");
+            for (name, count) in functions.into_iter().take(TOP) {
+                let _ = write!(
+                    out,
+                    "\n{:>10} {:5.1}%  {}",
+                    count,
+                    percent(count, self.executing),
+                    name
+                );
+            }
+            out.push('\n');
+        }
+
+        let mut pcs: Vec<_> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        out.push_str("\nInstructions\n  M-cycles      %  address");
+        for (&(bank, pc), &count) in pcs.into_iter().take(TOP) {
+            let _ = write!(
+                out,
+                "\n{:>10} {:5.1}%  {:02X}:{:04X}",
+                count,
+                percent(count, self.executing),
+                bank,
+                pc
+            );
+            if let Some((name, offset)) = self.symbols.function(bank, pc) {
+                let _ = write!(out, " {}+{:#X}", name, offset);
+            }
+        }
+        out
+    }
+
+    /// "name;caller;callee M-cycles" per call stack, what flamegraph.pl and inferno
+    /// read. Time in HALT is on top of the stack it halted in.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        for (stacks, leaf) in [(&self.stacks, None), (&self.halted_stacks, Some("HALT"))] {
+            for (calls, count) in stacks {
+                let mut line = self.name.clone();
+                for &call in calls {
+                    line.push(';');
+                    line.push_str(&self.frame(call));
+                }
+                if let Some(leaf) = leaf {
+                    line.push(';');
+                    line.push_str(leaf);
+                }
+                let _ = write!(line, " {}", count);
+                lines.push(line);
+            }
+        }
+        lines.sort();
+        lines.iter().map(|line| line.clone() + "\n").collect()
+    }
+
+    /// profile.txt with the report and profile.folded with the call stacks in `dir`
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        for (file, text) in [
+            ("profile.txt", self.report() + "\n"),
+            ("profile.folded", self.folded()),
+        ] {
+            let path = dir.join(file);
+            fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "\
+; File generated by rgblink
+00:0150 Main
+00:0158 Main.loop
+01:4000 Update
+01:4010 Draw
+";
+
+    #[test]
+    fn symbols() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        assert_eq!(symbols.function(0, 0x015A), Some(("Main", 0x0A)));
+        assert_eq!(symbols.function(1, 0x4012), Some(("Draw", 0x02)));
+        assert_eq!(symbols.function(0, 0x0100), None);
+        assert_eq!(symbols.function(2, 0x4012), None);
+        assert!(Symbols::parse("[labels]\n00:0150 Main\n").is_ok());
+        assert!(Symbols::parse("0150 Main").is_err());
+    }
+
+    #[test]
+    fn call_stacks() {
+        let mut profiler = Profiler::new("GAME", Symbols::parse(SYMBOLS).unwrap());
+        profiler.call(1, 0x4000, 0xDFFC);
+        profiler.run(0, 0x0150, 6);
+        profiler.call(1, 0x4010, 0xDFFA);
+        profiler.run(1, 0x4000, 3);
+        profiler.run(1, 0x4010, 2);
+        // An interrupt with a handler that has no symbol, then RETI
+        profiler.call(0, 0x0040, 0xDFF8);
+        profiler.run(0, 0x0040, 5);
+        profiler.ret(0xDFFA);
+        profiler.run(0, 0x0040, 4);
+        // Draw drops its return address and Update returns to Main
+        profiler.ret(0xDFFE);
+        profiler.run(1, 0x4001, 4);
+        profiler.halt();
+        profiler.halt();
+
+        assert_eq!(
+            profiler.folded(),
+            "GAME 6\nGAME;HALT 2\nGAME;Update 3\nGAME;Update;Draw 11\nGAME;Update;Draw;00:0040 4\n"
+        );
+        let report = profiler.report();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[0],
+            "26 M-cycles, executing 24 (92.3%), halted 2 (7.7%)"
+        );
+        assert_eq!(lines[4], "         9  37.5%  ?");
+        assert_eq!(lines[5], "         7  29.2%  Update");
+        assert_eq!(lines[11], "         9  37.5%  00:0040");
+        assert_eq!(lines[12], "         6  25.0%  00:0150 Main+0x0");
+    }
+}
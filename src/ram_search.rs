@@ -0,0 +1,176 @@
+use std::ops::RangeInclusive;
+
+use crate::memory::Mmu;
+
+/// How the bytes at an address are read as a number
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValueType {
+    U8,
+    U16, // little endian
+    Bcd8,
+    Bcd16, // little endian, 4 digits
+}
+
+impl ValueType {
+    pub fn parse(name: &str) -> Option<ValueType> {
+        match name {
+            "8" => Some(ValueType::U8),
+            "16" => Some(ValueType::U16),
+            "bcd" | "bcd8" => Some(ValueType::Bcd8),
+            "bcd16" => Some(ValueType::Bcd16),
+            _ => None,
+        }
+    }
+
+    fn len(self) -> u16 {
+        match self {
+            ValueType::U8 | ValueType::Bcd8 => 1,
+            ValueType::U16 | ValueType::Bcd16 => 2,
+        }
+    }
+
+    /// The value at `addr`, None if it isn't valid BCD
+    fn read(self, mem: &Mmu, addr: u16) -> Option<u32> {
+        let low = mem.peek(addr);
+        let high = || mem.peek(addr + 1);
+        match self {
+            ValueType::U8 => Some(low as u32),
+            ValueType::U16 => Some(u16::from_le_bytes([low, high()]) as u32),
+            ValueType::Bcd8 => bcd(low),
+            ValueType::Bcd16 => Some(bcd(high())? * 100 + bcd(low)?),
+        }
+    }
+}
+
+fn bcd(byte: u8) -> Option<u32> {
+    let (tens, ones) = (byte >> 4, byte & 0xF);
+    (tens < 10 && ones < 10).then_some(tens as u32 * 10 + ones as u32)
+}
+
+/// What to keep, compared to a value or to the last snapshot when there's none
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    Equal(Option<u32>),
+    NotEqual(Option<u32>),
+    Greater(Option<u32>),
+    Less(Option<u32>),
+    Changed(Option<i64>), // by exactly this much when given
+    Unchanged,
+}
+
+impl Filter {
+    fn keeps(self, old: u32, new: u32) -> bool {
+        match self {
+            Filter::Equal(val) => new == val.unwrap_or(old),
+            Filter::NotEqual(val) => new != val.unwrap_or(old),
+            Filter::Greater(val) => new > val.unwrap_or(old),
+            Filter::Less(val) => new < val.unwrap_or(old),
+            Filter::Changed(None) => new != old,
+            Filter::Changed(Some(by)) => new as i64 - old as i64 == by,
+            Filter::Unchanged => new == old,
+        }
+    }
+}
+
+/// Cartridge RAM when there is some, WRAM and HRAM, in the banks that are mapped
+fn ranges(mem: &Mmu) -> Vec<RangeInclusive<u16>> {
+    let mut ranges = vec![0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+    if !mem.cart().ram().is_empty() {
+        ranges.insert(0, 0xA000..=0xBFFF);
+    }
+    ranges
+}
+
+/// Narrows down where a value is kept by comparing snapshots of RAM
+pub struct RamSearch {
+    value_type: ValueType,
+    candidates: Vec<(u16, u32)>, // address and value in the last snapshot
+}
+
+impl RamSearch {
+    /// Start with every address holding a valid value
+    pub fn new(mem: &Mmu, value_type: ValueType) -> Self {
+        let candidates = ranges(mem)
+            .into_iter()
+            .flat_map(|range| {
+                // Values don't straddle two regions
+                let last = range.end() + 1 - value_type.len();
+                *range.start()..=last
+            })
+            .filter_map(|addr| Some((addr, value_type.read(mem, addr)?)))
+            .collect();
+        RamSearch {
+            value_type,
+            candidates,
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    /// Addresses still in the running with their value in the last snapshot
+    pub fn candidates(&self) -> &[(u16, u32)] {
+        &self.candidates
+    }
+
+    /// Take a snapshot and keep the addresses that pass the filter
+    pub fn filter(&mut self, mem: &Mmu, filter: Filter) {
+        let value_type = self.value_type;
+        self.candidates
+            .retain_mut(|(addr, old)| match value_type.read(mem, *addr) {
+                Some(new) if filter.keeps(*old, new) => {
+                    *old = new;
+                    true
+                }
+                _ => false,
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_down() {
+        let mut mem = Mmu::new();
+        mem.write(0xC123, 3);
+        mem.write(0xFF90, 3);
+        let mut search = RamSearch::new(&mem, ValueType::U8);
+        assert_eq!(search.candidates().len(), 0x2000 + 0x7F);
+
+        search.filter(&mem, Filter::Equal(Some(3)));
+        assert_eq!(search.candidates(), [(0xC123, 3), (0xFF90, 3)]);
+        mem.write(0xC123, 2);
+        search.filter(&mem, Filter::Changed(Some(-1)));
+        assert_eq!(search.candidates(), [(0xC123, 2)]);
+        search.filter(&mem, Filter::Unchanged);
+        assert_eq!(search.candidates(), [(0xC123, 2)]);
+        mem.write(0xC123, 5);
+        search.filter(&mem, Filter::Less(None));
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn value_types() {
+        let mut mem = Mmu::new();
+        mem.write(0xC000, 0x34);
+        mem.write(0xC001, 0x12);
+        assert_eq!(ValueType::U16.read(&mem, 0xC000), Some(0x1234));
+        assert_eq!(ValueType::Bcd16.read(&mem, 0xC000), Some(1234));
+        assert_eq!(ValueType::Bcd8.read(&mem, 0xC001), Some(12));
+        mem.write(0xC001, 0x1A);
+        assert_eq!(ValueType::Bcd16.read(&mem, 0xC000), None);
+
+        // The last 16-bit value in WRAM starts one before the end
+        let search = RamSearch::new(&mem, ValueType::U16);
+        let last = search.candidates().iter().map(|&(addr, _)| addr);
+        assert!(last.clone().any(|addr| addr == 0xDFFE));
+        assert!(!last.clone().any(|addr| addr == 0xDFFF));
+
+        let mut search = RamSearch::new(&mem, ValueType::Bcd16);
+        search.filter(&mem, Filter::Greater(Some(9000)));
+        assert!(search.candidates().is_empty());
+    }
+}
@@ -5,6 +5,7 @@ pub enum Flag {
     C = 0b00010000,
 }
 
+#[derive(Clone)]
 pub struct Reg {
     pub a: u8,
     pub f: u8,
@@ -38,6 +39,54 @@ impl Reg {
         }
     }
 
+    /// State after the CGB boot ROM
+    pub fn new_cgb() -> Self {
+        Reg {
+            a: 0x11,
+            f: 0x80,
+            b: 0x00,
+            c: 0x00,
+            d: 0xFF,
+            e: 0x56,
+            h: 0x00,
+            l: 0x0D,
+            pc: 0x0100,
+            sp: 0xFFFE,
+        }
+    }
+
+    /// State after the SGB boot ROM
+    pub fn new_sgb() -> Self {
+        Reg {
+            a: 0x01,
+            f: 0x00,
+            b: 0x00,
+            c: 0x14,
+            d: 0x00,
+            e: 0x00,
+            h: 0xC0,
+            l: 0x60,
+            pc: 0x0100,
+            sp: 0xFFFE,
+        }
+    }
+
+    /// State at power on, before the boot ROM runs
+    pub fn zeroed() -> Self {
+        Reg {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            pc: 0,
+            sp: 0,
+        }
+    }
+
     pub fn af(&self) -> u16 {
         ((self.a as u16) << 8) | ((self.f & 0xF0) as u16)
     }
@@ -0,0 +1,113 @@
+/// M-cycles to shift out a whole byte using the internal 8192 Hz clock
+const TRANSFER_CYCLES: u32 = 1024;
+
+/// Anything that can be plugged into the link port
+pub trait SerialDevice {
+    /// Exchange a byte with the device, returns the byte shifted back in
+    fn transfer(&mut self, byte: u8) -> u8;
+}
+
+pub struct Serial {
+    pub data: u8,    // SB
+    pub control: u8, // SC
+    running: u32,
+    device: Option<Box<dyn SerialDevice>>,
+}
+
+/// Whatever is plugged in isn't part of save states, clones start unplugged
+impl Clone for Serial {
+    fn clone(&self) -> Self {
+        Serial {
+            data: self.data,
+            control: self.control,
+            running: self.running,
+            device: None,
+        }
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            data: 0,
+            control: 0,
+            running: 0,
+            device: None,
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.device.take()
+    }
+
+    pub fn write_control(&mut self, val: u8) {
+        self.control = val;
+        self.running = 0;
+    }
+
+    /// SC.7 and SC.0, only transfers clocked by the Game Boy can finish
+    fn transferring(&self) -> bool {
+        self.control & 0x81 == 0x81
+    }
+
+    /// Returns true when a transfer finished and the serial interrupt should fire
+    pub fn step(&mut self, m_cycles: u32) -> bool {
+        if !self.transferring() {
+            return false;
+        }
+
+        self.running += m_cycles;
+        if self.running < TRANSFER_CYCLES {
+            return false;
+        }
+
+        self.data = match &mut self.device {
+            Some(device) => device.transfer(self.data),
+            None => {
+                // blarggs test - serial output
+                if let Some(c) = char::from_u32(self.data as u32) {
+                    print!("{}", c);
+                }
+                0xFF
+            }
+        };
+        self.control &= !0x80;
+        self.running = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl SerialDevice for Echo {
+        fn transfer(&mut self, byte: u8) -> u8 {
+            !byte
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Echo));
+        serial.data = 0x0F;
+        serial.write_control(0x81);
+
+        assert!(!serial.step(TRANSFER_CYCLES - 1));
+        assert_eq!(serial.control, 0x81);
+        assert!(serial.step(1));
+        assert_eq!(serial.data, 0xF0);
+        assert_eq!(serial.control, 0x01);
+
+        // External clock never completes without a partner
+        serial.write_control(0x80);
+        assert!(!serial.step(TRANSFER_CYCLES * 2));
+    }
+}
@@ -0,0 +1,457 @@
+use crate::graphics::{rgb555, Gpu, Rgb, Shades, HEIGHT, WIDTH};
+
+pub const BORDER_WIDTH: u32 = 256;
+pub const BORDER_HEIGHT: u32 = 224;
+
+// Top left of the Game Boy screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// The attribute map assigns a palette to each 8x8 cell of the screen
+const CELLS_X: usize = WIDTH as usize / 8;
+const CELLS_Y: usize = HEIGHT as usize / 8;
+
+/// Bytes picked up from the screen by a VRAM transfer
+const TRANSFER_LEN: usize = 0x1000;
+const ATTR_FILE_LEN: usize = 90;
+const ATTR_FILES: usize = 45;
+
+pub type SgbFrame = [[Rgb; BORDER_WIDTH as usize]; BORDER_HEIGHT as usize];
+
+#[derive(Clone)]
+pub struct Sgb {
+    receiving: bool,
+    bits: usize, // bits of the current packet received
+    packet: [u8; 16],
+    data: Vec<u8>, // packets of the current command
+    last_row: u8,
+    players: u8,
+    player: u8,
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>, // 512 palettes of 4 colors, sent by PAL_TRN
+    attr_files: Vec<u8>,       // sent by ATTR_TRN
+    attrs: [u8; CELLS_X * CELLS_Y],
+    mask: u8,
+    frozen: Shades,        // screen shown while MASK_EN freezes it
+    border_tiles: Vec<u8>, // 256 SNES 4bpp tiles
+    border_map: [u16; 32 * 28],
+    border_palettes: [[u16; 16]; 4], // SNES palettes 4-7
+    frame: Box<SgbFrame>,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        // DMG shades until the game sends its own
+        let gray = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+        Sgb {
+            receiving: false,
+            bits: 0,
+            packet: [0; 16],
+            data: Vec::new(),
+            last_row: 0x30,
+            players: 1,
+            player: 0,
+            palettes: [gray; 4],
+            system_palettes: vec![0; 512 * 4],
+            attr_files: vec![0; ATTR_FILES * ATTR_FILE_LEN],
+            attrs: [0; CELLS_X * CELLS_Y],
+            mask: 0,
+            frozen: [[0; WIDTH as usize]; HEIGHT as usize],
+            border_tiles: vec![0; 2 * TRANSFER_LEN],
+            border_map: [0; 32 * 28],
+            border_palettes: [[0; 16]; 4],
+            frame: Box::new([[(0, 0, 0); BORDER_WIDTH as usize]; BORDER_HEIGHT as usize]),
+        }
+    }
+
+    /// Joypad ID read from the lower nibble of P1 while no row is selected
+    pub fn joypad_id(&self) -> u8 {
+        0xF - self.player
+    }
+
+    /// Packets are sent bit by bit through P14 and P15.
+    /// Both low resets, P14 low is a 0 and P15 low is a 1, both high ends the pulse.
+    pub fn write_joypad(&mut self, val: u8, gpu: &Gpu) {
+        let row = val & 0x30;
+        match row {
+            0x00 => {
+                self.receiving = true;
+                self.bits = 0;
+                self.packet = [0; 16];
+            }
+            0x10 | 0x20 if self.receiving && self.last_row == 0x30 => {
+                let bit = row == 0x10;
+                if self.bits == 128 {
+                    // Stop bit
+                    self.receiving = false;
+                    if !bit {
+                        self.packet_received(gpu);
+                    }
+                } else {
+                    if bit {
+                        self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+                    }
+                    self.bits += 1;
+                }
+            }
+            // Reading all rows of a joypad moves on to the next one
+            0x30 if !self.receiving && self.last_row & 0x20 == 0 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+        self.last_row = row;
+    }
+
+    /// Commands are 1 to 7 packets long, the length is in the first byte
+    fn packet_received(&mut self, gpu: &Gpu) {
+        self.data.extend_from_slice(&self.packet);
+        let len = (self.data[0] & 0b111).max(1) as usize;
+        if self.data.len() == len * 16 {
+            let data = std::mem::take(&mut self.data);
+            self.command(&data, gpu);
+        }
+    }
+
+    fn command(&mut self, data: &[u8], gpu: &Gpu) {
+        match data[0] >> 3 {
+            0x00 => self.set_palettes(0, 1, data), // PAL01
+            0x01 => self.set_palettes(2, 3, data), // PAL23
+            0x02 => self.set_palettes(0, 3, data), // PAL03
+            0x03 => self.set_palettes(1, 2, data), // PAL12
+            0x04 => self.attr_blk(data),
+            0x05 => self.attr_lin(data),
+            0x06 => self.attr_div(data),
+            0x07 => self.attr_chr(data),
+            0x0A => self.pal_set(data),
+            // PAL_TRN
+            0x0B => {
+                let transfer = gpu.screen_tile_data(TRANSFER_LEN);
+                self.system_palettes = colors(&transfer);
+            }
+            // MLT_REQ
+            0x11 => {
+                self.players = match data[1] & 0b11 {
+                    0b01 => 2,
+                    0b11 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            // CHR_TRN, bit 0 picks the upper or lower 128 tiles
+            0x13 => {
+                let start = (data[1] & 1) as usize * TRANSFER_LEN;
+                self.border_tiles[start..start + TRANSFER_LEN]
+                    .copy_from_slice(&gpu.screen_tile_data(TRANSFER_LEN));
+            }
+            // PCT_TRN, the border map followed by its palettes
+            0x14 => {
+                let transfer = gpu.screen_tile_data(TRANSFER_LEN);
+                let map = colors(&transfer[..2 * self.border_map.len()]);
+                self.border_map.copy_from_slice(&map);
+                let palettes = colors(&transfer[0x800..0x880]);
+                for (palette, colors) in self.border_palettes.iter_mut().zip(palettes.chunks(16)) {
+                    palette.copy_from_slice(colors);
+                }
+            }
+            // ATTR_TRN
+            0x15 => {
+                let transfer = gpu.screen_tile_data(TRANSFER_LEN);
+                self.attr_files
+                    .copy_from_slice(&transfer[..ATTR_FILES * ATTR_FILE_LEN]);
+            }
+            // ATTR_SET
+            0x16 => {
+                self.attr_file(data[1] & 0x3F);
+                if data[1] & 0x40 > 0 {
+                    self.mask = 0;
+                }
+            }
+            // MASK_EN
+            0x17 => {
+                self.mask = data[1] & 0b11;
+                if self.mask == 1 {
+                    self.frozen = *gpu.shades();
+                }
+            }
+            // Sound and SNES side commands aren't emulated
+            _ => {}
+        }
+    }
+
+    /// Color 0 is shared by all palettes
+    fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+        let colors = colors(&data[1..15]);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = colors[0];
+        }
+        self.palettes[a][1..].copy_from_slice(&colors[1..4]);
+        self.palettes[b][1..].copy_from_slice(&colors[4..7]);
+    }
+
+    fn set_attr(&mut self, x: usize, y: usize, palette: u8) {
+        if x < CELLS_X && y < CELLS_Y {
+            self.attrs[y * CELLS_X + x] = palette & 0b11;
+        }
+    }
+
+    /// Color the inside, border and outside of rectangles
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] as usize).min(18);
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let control = set[0] & 0b111;
+            let palettes = set[1];
+            let (x1, y1, x2, y2) = (
+                set[2] as usize,
+                set[3] as usize,
+                set[4] as usize,
+                set[5] as usize,
+            );
+            let inside = palettes & 0b11;
+            // A lone inside or outside also colors the border
+            let border = match control {
+                0b001 => inside,
+                0b100 => palettes >> 4,
+                _ => palettes >> 2,
+            };
+            let control = if control == 0b001 || control == 0b100 {
+                control | 0b010
+            } else {
+                control
+            };
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let in_rect = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = in_rect && (x == x1 || x == x2 || y == y1 || y == y2);
+                    if on_edge && control & 0b010 > 0 {
+                        self.set_attr(x, y, border);
+                    } else if in_rect && !on_edge && control & 0b001 > 0 {
+                        self.set_attr(x, y, inside);
+                    } else if !in_rect && control & 0b100 > 0 {
+                        self.set_attr(x, y, palettes >> 4);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Color whole rows or columns
+    fn attr_lin(&mut self, data: &[u8]) {
+        let lines = data[1] as usize;
+        for &line in data[2..].iter().take(lines) {
+            let n = (line & 0x1F) as usize;
+            let palette = line >> 5;
+            if line & 0x80 > 0 {
+                for x in 0..CELLS_X {
+                    self.set_attr(x, n, palette);
+                }
+            } else {
+                for y in 0..CELLS_Y {
+                    self.set_attr(n, y, palette);
+                }
+            }
+        }
+    }
+
+    /// Split the screen in two along a row or column
+    fn attr_div(&mut self, data: &[u8]) {
+        let at = data[2] as usize;
+        let horizontal = data[1] & 0x40 > 0;
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let pos = if horizontal { y } else { x };
+                let palette = match pos.cmp(&at) {
+                    std::cmp::Ordering::Less => data[1] >> 2,
+                    std::cmp::Ordering::Equal => data[1] >> 4,
+                    std::cmp::Ordering::Greater => data[1],
+                };
+                self.set_attr(x, y, palette);
+            }
+        }
+    }
+
+    /// Color single cells, 4 per byte, going right or down from a start cell
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 1 > 0;
+        let count = count.min(4 * (data.len() - 6)).min(CELLS_X * CELLS_Y);
+        for i in 0..count {
+            if x >= CELLS_X || y >= CELLS_Y {
+                break;
+            }
+            let palette = data[6 + i / 4] >> (6 - 2 * (i % 4));
+            self.set_attr(x, y, palette);
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    /// Load palettes 0-3 from the system palettes, optionally with an attribute file
+    fn pal_set(&mut self, data: &[u8]) {
+        let ids = colors(&data[1..9]);
+        for (palette, &id) in self.palettes.iter_mut().zip(ids.iter()) {
+            let i = (id as usize & 0x1FF) * 4;
+            palette.copy_from_slice(&self.system_palettes[i..i + 4]);
+        }
+        let color0 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+
+        if data[9] & 0x80 > 0 {
+            self.attr_file(data[9] & 0x3F);
+        }
+        if data[9] & 0x40 > 0 {
+            self.mask = 0;
+        }
+    }
+
+    /// Attribute files hold 2 bits per cell
+    fn attr_file(&mut self, id: u8) {
+        let id = id as usize;
+        if id >= ATTR_FILES {
+            return;
+        }
+        let file = &self.attr_files[id * ATTR_FILE_LEN..(id + 1) * ATTR_FILE_LEN];
+        for (i, attr) in self.attrs.iter_mut().enumerate() {
+            *attr = (file[i / 4] >> (6 - 2 * (i % 4))) & 0b11;
+        }
+    }
+
+    /// Color id of pixel x, y of a border tile, stored as two SNES 2bpp planes
+    fn border_pixel(&self, tile: usize, x: usize, y: usize) -> usize {
+        let addr = tile * 32 + 2 * y;
+        let bit = 7 - x;
+        [0, 1, 16, 17]
+            .iter()
+            .enumerate()
+            .map(|(plane, offset)| {
+                (((self.border_tiles[addr + offset] >> bit) & 1) as usize) << plane
+            })
+            .sum()
+    }
+
+    /// Draw the border and color the Game Boy screen in the middle of it
+    pub fn render(&mut self, shades: &Shades) -> &SgbFrame {
+        let backdrop = rgb555(self.palettes[0][0]);
+        for (i, &entry) in self.border_map.iter().enumerate() {
+            let (tx, ty) = (i % 32, i / 32);
+            let tile = (entry & 0xFF) as usize;
+            let palette = ((entry >> 10) & 0b111) as usize;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let px = if entry & (1 << 14) > 0 { 7 - x } else { x };
+                    let py = if entry & (1 << 15) > 0 { 7 - y } else { y };
+                    let color = self.border_pixel(tile, px, py);
+                    // Color 0 shows the backdrop, and only palettes 4-7 belong to the border
+                    self.frame[ty * 8 + y][tx * 8 + x] = if color == 0 || palette < 4 {
+                        backdrop
+                    } else {
+                        rgb555(self.border_palettes[palette - 4][color])
+                    };
+                }
+            }
+        }
+
+        let shades = if self.mask == 1 { &self.frozen } else { shades };
+        for (y, line) in shades.iter().enumerate() {
+            for (x, &shade) in line.iter().enumerate() {
+                let palette = self.attrs[(y / 8) * CELLS_X + x / 8] as usize;
+                self.frame[SCREEN_Y + y][SCREEN_X + x] = match self.mask {
+                    2 => (0, 0, 0),
+                    3 => backdrop,
+                    _ => rgb555(self.palettes[palette][shade as usize]),
+                };
+            }
+        }
+        &self.frame
+    }
+}
+
+/// Little endian 16-bit words, as SGB colors and border map entries are sent
+fn colors(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(sgb: &mut Sgb, gpu: &Gpu, packet: [u8; 16]) {
+        sgb.write_joypad(0x00, gpu);
+        sgb.write_joypad(0x30, gpu);
+        for i in 0..128 {
+            let bit = packet[i / 8] >> (i % 8) & 1;
+            sgb.write_joypad(if bit > 0 { 0x10 } else { 0x20 }, gpu);
+            sgb.write_joypad(0x30, gpu);
+        }
+        sgb.write_joypad(0x20, gpu);
+        sgb.write_joypad(0x30, gpu);
+    }
+
+    #[test]
+    fn palettes() {
+        let gpu = Gpu::new();
+        let mut sgb = Sgb::new();
+        // PAL01: red color 0, palette 1 color 3 blue
+        let mut packet = [0; 16];
+        packet[0] = 0x01;
+        packet[1..3].copy_from_slice(&0x001Fu16.to_le_bytes());
+        packet[13..15].copy_from_slice(&0x7C00u16.to_le_bytes());
+        send(&mut sgb, &gpu, packet);
+        assert_eq!(sgb.palettes[2][0], 0x001F);
+        assert_eq!(sgb.palettes[1][3], 0x7C00);
+
+        // ATTR_BLK: palette 1 inside cells (1,1)-(3,3), border follows inside
+        let mut packet = [0; 16];
+        packet[0] = 0x04 << 3 | 1;
+        packet[1] = 1;
+        packet[2..8].copy_from_slice(&[0b001, 0b01, 1, 1, 3, 3]);
+        send(&mut sgb, &gpu, packet);
+        assert_eq!(sgb.attrs[0], 0);
+        assert_eq!(sgb.attrs[CELLS_X + 1], 1);
+        assert_eq!(sgb.attrs[3 * CELLS_X + 3], 1);
+        assert_eq!(sgb.attrs[4 * CELLS_X + 4], 0);
+
+        let mut shades = [[0; WIDTH as usize]; HEIGHT as usize];
+        shades[8][8] = 3;
+        let frame = sgb.render(&shades);
+        assert_eq!(frame[0][0], (0xFF, 0, 0)); // backdrop
+        assert_eq!(frame[SCREEN_Y + 8][SCREEN_X + 8], (0, 0, 0xFF));
+        assert_eq!(frame[SCREEN_Y][SCREEN_X], (0xFF, 0, 0));
+    }
+
+    #[test]
+    fn multiplayer() {
+        let gpu = Gpu::new();
+        let mut sgb = Sgb::new();
+        let mut packet = [0; 16];
+        packet[0] = 0x11 << 3 | 1;
+        packet[1] = 0b01;
+        send(&mut sgb, &gpu, packet);
+        assert_eq!(sgb.joypad_id(), 0xF);
+        for row in [0x20, 0x30, 0x10, 0x30] {
+            sgb.write_joypad(row, &gpu);
+        }
+        assert_eq!(sgb.joypad_id(), 0xE);
+        for row in [0x20, 0x30, 0x10, 0x30] {
+            sgb.write_joypad(row, &gpu);
+        }
+        assert_eq!(sgb.joypad_id(), 0xF);
+    }
+}
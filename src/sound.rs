@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Apu {
     pub master_control: u8,
     pub panning: u8,
@@ -0,0 +1,188 @@
+#[derive(Clone)]
+pub struct Timer {
+    counter: u16, // internal system counter, DIV is the upper byte
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    overflowed: bool, // TIMA overflowed last cycle, reload pending
+    reloaded: bool,   // TIMA was reloaded from TMA this cycle
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0xABCC,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+            reloaded: false,
+        }
+    }
+
+    /// State at power on, before the boot ROM runs
+    pub fn zeroed() -> Self {
+        Timer {
+            counter: 0,
+            ..Timer::new()
+        }
+    }
+
+    pub fn div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    pub fn tima(&self) -> u8 {
+        self.tima
+    }
+
+    pub fn tma(&self) -> u8 {
+        self.tma
+    }
+
+    /// Upper 5 bits of TAC are unused
+    pub fn tac(&self) -> u8 {
+        self.tac | 0xF8
+    }
+
+    pub fn write_div(&mut self) {
+        let signal = self.signal();
+        self.counter = 0;
+        self.detect_edge(signal);
+    }
+
+    pub fn write_tima(&mut self, val: u8) {
+        // Writes in the reload cycle lose to TMA
+        if self.reloaded {
+            return;
+        }
+        // Writes in the cycle after the overflow cancel the reload
+        self.overflowed = false;
+        self.tima = val;
+    }
+
+    pub fn write_tma(&mut self, val: u8) {
+        self.tma = val;
+        if self.reloaded {
+            self.tima = val;
+        }
+    }
+
+    pub fn write_tac(&mut self, val: u8) {
+        let signal = self.signal();
+        self.tac = val & 0b111;
+        self.detect_edge(signal);
+    }
+
+    /// Counter bit that clocks TIMA
+    fn bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _ => 1 << 7,
+        }
+    }
+
+    /// TIMA is clocked by the selected counter bit ANDed with TAC.2
+    fn signal(&self) -> bool {
+        self.tac & 0b100 > 0 && self.counter & self.bit() > 0
+    }
+
+    fn detect_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.signal() {
+            let (tima, overflowed) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflowed |= overflowed;
+        }
+    }
+
+    /// Advance one M-cycle, returns true when the timer interrupt should fire
+    pub fn tick(&mut self) -> bool {
+        self.reloaded = false;
+        let interrupt = self.overflowed;
+        if self.overflowed {
+            self.overflowed = false;
+            self.tima = self.tma;
+            self.reloaded = true;
+        }
+
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_edge(signal);
+        interrupt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(timer: &mut Timer, n: u32) -> u32 {
+        (0..n).filter(|_| timer.tick()).count() as u32
+    }
+
+    #[test]
+    fn div() {
+        let mut timer = Timer::zeroed();
+        ticks(&mut timer, 64);
+        assert_eq!(timer.div(), 1);
+        timer.write_div();
+        assert_eq!(timer.div(), 0);
+    }
+
+    #[test]
+    fn overflow_reload() {
+        let mut timer = Timer::zeroed();
+        timer.write_tma(0x42);
+        timer.write_tima(0xFF);
+        timer.write_tac(0b101); // every 4 M-cycles
+
+        assert_eq!(ticks(&mut timer, 4), 0);
+        // TIMA reads 0 for one cycle before the reload
+        assert_eq!(timer.tima(), 0);
+        assert!(timer.tick());
+        assert_eq!(timer.tima(), 0x42);
+
+        // Writing TIMA in the reload cycle is ignored
+        timer.write_tima(0x10);
+        assert_eq!(timer.tima(), 0x42);
+        // but writing TMA goes through to TIMA
+        timer.write_tma(0x50);
+        assert_eq!(timer.tima(), 0x50);
+    }
+
+    #[test]
+    fn cancel_reload() {
+        let mut timer = Timer::zeroed();
+        timer.write_tma(0x42);
+        timer.write_tima(0xFF);
+        timer.write_tac(0b101);
+
+        ticks(&mut timer, 4);
+        timer.write_tima(0x10);
+        assert!(!timer.tick());
+        assert_eq!(timer.tima(), 0x10);
+    }
+
+    #[test]
+    fn falling_edge_glitches() {
+        let mut timer = Timer::zeroed();
+        timer.write_tac(0b101);
+        ticks(&mut timer, 2); // bit 3 set
+
+        // Resetting DIV drops the selected bit
+        timer.write_div();
+        assert_eq!(timer.tima(), 1);
+
+        // Disabling the timer while the bit is set
+        ticks(&mut timer, 2);
+        timer.write_tac(0b001);
+        assert_eq!(timer.tima(), 2);
+
+        // Switching to a bit that is clear
+        timer.write_tac(0b101);
+        timer.write_tac(0b110);
+        assert_eq!(timer.tima(), 3);
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::graphics::{shade, tile_color_map, Gpu, Rgb, HEIGHT, SHADES, WIDTH};
+use crate::image;
+
+// What's in VRAM drawn as images: the 384 tiles of each bank and both tile maps,
+// plus the objects in OAM as a table
+
+/// Tiles are laid out 16 to a row
+const TILES_PER_ROW: usize = 16;
+const TILES: usize = 384;
+const MAP_SIZE: usize = 256;
+/// Outline of what's on screen in the BG map
+const VIEWPORT: Rgb = (0xFF, 0x00, 0x00);
+
+pub struct Image {
+    pub width: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            pixels: vec![SHADES[0]; width * height],
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.pixels.len() / self.width
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: Rgb) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Copy another image in with its top left corner at x, y
+    fn draw(&mut self, x: usize, y: usize, image: &Image) {
+        for (row, line) in image.pixels.chunks(image.width).enumerate() {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + image.width].copy_from_slice(line);
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|&(r, g, b)| [r, g, b])
+            .collect();
+        image::write_png(path, self.width as u32, png::ColorType::Rgb, &data)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// All 384 tiles at 0x8000-0x97FF in grey by color id, the CGB's second bank to the right
+pub fn tiles(gpu: &Gpu) -> Image {
+    let banks = if gpu.cgb { 2 } else { 1 };
+    let width = TILES_PER_ROW * 8;
+    let mut image = Image::new(width * banks, TILES / TILES_PER_ROW * 8);
+    for bank in 0..banks {
+        for i in 0..TILES {
+            let colors = tile_color_map(gpu.tile(bank as u8, 0x8000 + 16 * i as u16));
+            let (tx, ty) = (bank * width + i % TILES_PER_ROW * 8, i / TILES_PER_ROW * 8);
+            for (y, row) in colors.iter().enumerate() {
+                for (x, &color) in row.iter().enumerate() {
+                    image.set(tx + x, ty + y, SHADES[color as usize]);
+                }
+            }
+        }
+    }
+    image
+}
+
+/// The 32x32 tile map at `map` (0x9800 or 0x9C00) in the game's colors. The BG
+/// map has the part SCX/SCY puts on screen outlined.
+pub fn tile_map(gpu: &Gpu, map: u16) -> Image {
+    let mut image = Image::new(MAP_SIZE, MAP_SIZE);
+    for i in 0..32 * 32 {
+        let map_addr = map + i as u16;
+        let id = gpu.vram_at(0, map_addr);
+        let attrs = if gpu.cgb { gpu.vram_at(1, map_addr) } else { 0 };
+        let tile = gpu.tile((attrs >> 3) & 1, gpu.bg_win_tile_addr(id));
+        let colors = tile_color_map(tile);
+        let (tx, ty) = (i % 32 * 8, i / 32 * 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                let px = if attrs & (1 << 5) > 0 { 7 - x } else { x };
+                let py = if attrs & (1 << 6) > 0 { 7 - y } else { y };
+                let color = colors[py][px];
+                let rgb = if gpu.cgb {
+                    gpu.bcp.color(attrs & 0b111, color)
+                } else {
+                    gpu.palette.bg[shade(gpu.bgp, color) as usize]
+                };
+                image.set(tx + x, ty + y, rgb);
+            }
+        }
+    }
+
+    if map == gpu.bg_tile_map_area() {
+        // The viewport wraps around the edges
+        let (scx, scy) = (gpu.scx as usize, gpu.scy as usize);
+        let (w, h) = (WIDTH as usize, HEIGHT as usize);
+        for i in 0..w {
+            image.set((scx + i) % MAP_SIZE, scy, VIEWPORT);
+            image.set((scx + i) % MAP_SIZE, (scy + h - 1) % MAP_SIZE, VIEWPORT);
+        }
+        for i in 0..h {
+            image.set(scx, (scy + i) % MAP_SIZE, VIEWPORT);
+            image.set((scx + w - 1) % MAP_SIZE, (scy + i) % MAP_SIZE, VIEWPORT);
+        }
+    }
+    image
+}
+
+/// Both tile maps and the tiles side by side, for the viewer window
+pub fn overview(gpu: &Gpu) -> Image {
+    let tiles = tiles(gpu);
+    let mut image = Image::new(2 * MAP_SIZE + tiles.width, MAP_SIZE);
+    image.draw(0, 0, &tile_map(gpu, 0x9800));
+    image.draw(MAP_SIZE, 0, &tile_map(gpu, 0x9C00));
+    image.draw(2 * MAP_SIZE, 0, &tiles);
+    image
+}
+
+/// Every object in OAM, positions are on screen so hidden ones are off it
+pub fn oam_table(gpu: &Gpu) -> String {
+    let mut out = format!(
+        "Objects are 8x{}\n #    X    Y tile attrs palette  flip prio",
+        gpu.obj_height()
+    );
+    if gpu.cgb {
+        out.push_str(" bank");
+    }
+    for (i, obj) in gpu.oam().chunks_exact(4).enumerate() {
+        let (y, x, tile, attrs) = (obj[0], obj[1], obj[2], obj[3]);
+        let palette = if gpu.cgb {
+            format!("CGB {}", attrs & 0b111)
+        } else if attrs & (1 << 4) > 0 {
+            "OBP1".to_string()
+        } else {
+            "OBP0".to_string()
+        };
+        let flip = match (attrs & (1 << 5) > 0, attrs & (1 << 6) > 0) {
+            (false, false) => "-",
+            (true, false) => "X",
+            (false, true) => "Y",
+            (true, true) => "XY",
+        };
+        let prio = if attrs & (1 << 7) > 0 { "BG" } else { "OBJ" };
+        let mut line = format!(
+            "{:2} {:4} {:4}   {:02X}    {:02X} {:<8} {:<4} {:<4}",
+            i,
+            x as i16 - 8,
+            y as i16 - 16,
+            tile,
+            attrs,
+            palette,
+            flip,
+            prio
+        );
+        if gpu.cgb {
+            let _ = write!(line, " {}", (attrs >> 3) & 1);
+        }
+        out.push('\n');
+        out.push_str(line.trim_end());
+    }
+    out
+}
+
+/// tiles.png, map_9800.png, map_9C00.png and oam.txt in `dir`
+pub fn dump(gpu: &Gpu, dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    tiles(gpu).save(&dir.join("tiles.png"))?;
+    tile_map(gpu, 0x9800).save(&dir.join("map_9800.png"))?;
+    tile_map(gpu, 0x9C00).save(&dir.join("map_9C00.png"))?;
+    let path = dir.join("oam.txt");
+    fs::write(&path, oam_table(gpu) + "\n").map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_sheet() {
+        let mut gpu = Gpu::new();
+        // Tile 17 is solid color 3, the last one has color 1 in its top left pixel
+        for i in 0..16 {
+            gpu.write_vram(0x8110 + i, 0xFF);
+        }
+        gpu.write_vram(0x97F0, 0x80);
+        let image = tiles(&gpu);
+        assert_eq!((image.width, image.height()), (128, 192));
+        assert_eq!(image.pixels[8 * 128 + 8], SHADES[3]);
+        assert_eq!(image.pixels[8 * 128 + 16], SHADES[0]);
+        assert_eq!(image.pixels[184 * 128 + 120], SHADES[1]);
+
+        gpu.cgb = true;
+        assert_eq!(tiles(&gpu).width, 256);
+    }
+
+    #[test]
+    fn map_viewport() {
+        let mut gpu = Gpu::new();
+        gpu.bgp = 0xE4;
+        for i in 0..16 {
+            gpu.write_vram(0x8010 + i, 0xFF);
+        }
+        gpu.write_vram(0x9801, 1);
+        gpu.scx = 200;
+        gpu.scy = 10;
+
+        let image = tile_map(&gpu, 0x9800);
+        let at = |x: usize, y: usize| image.pixels[y * MAP_SIZE + x];
+        assert_eq!(at(8, 0), gpu.palette.bg[3]);
+        assert_eq!(at(0, 0), gpu.palette.bg[0]);
+        // Outline from 200, 10 wrapping around to 103, 153
+        assert_eq!(at(200, 10), VIEWPORT);
+        assert_eq!(at(5, 10), VIEWPORT);
+        assert_eq!(at(103, 100), VIEWPORT);
+        assert_eq!(at(100, 153), VIEWPORT);
+        assert_eq!(at(100, 100), gpu.palette.bg[0]);
+        // The other map isn't the BG map
+        assert!(!tile_map(&gpu, 0x9C00).pixels.contains(&VIEWPORT));
+    }
+
+    #[test]
+    fn oam() {
+        let mut gpu = Gpu::new();
+        gpu.write_oam(0xFE04, 20);
+        gpu.write_oam(0xFE05, 12);
+        gpu.write_oam(0xFE06, 0x42);
+        gpu.write_oam(0xFE07, 1 << 7 | 1 << 5 | 1 << 4);
+        let table = oam_table(&gpu);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 42);
+        assert_eq!(lines[0], "Objects are 8x8");
+        assert_eq!(lines[2], " 0   -8  -16   00    00 OBP0     -    OBJ");
+        assert_eq!(lines[3], " 1    4    4   42    B0 OBP1     X    BG");
+
+        gpu.cgb = true;
+        gpu.write_oam(0xFE07, 1 << 6 | 1 << 3 | 5);
+        let table = oam_table(&gpu);
+        assert_eq!(
+            table.lines().nth(3),
+            Some(" 1    4    4   42    4D CGB 5    Y    OBJ  1")
+        );
+    }
+}